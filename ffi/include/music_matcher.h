@@ -0,0 +1,94 @@
+#ifndef MUSIC_MATCHER_H
+#define MUSIC_MATCHER_H
+
+/* Generated by cbindgen from ffi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum MmStatus {
+  MM_STATUS_OK = 0,
+  // `mm_match` found nothing above the confidence cutoff.
+  MM_STATUS_NO_MATCH = 1,
+  MM_STATUS_INVALID_ARGUMENT = -1,
+  // The buffer isn't decodable audio, or exceeds the upload limits.
+  MM_STATUS_DECODE_ERROR = -2,
+  MM_STATUS_DATABASE_ERROR = -3,
+  // A bug inside the library; the call had no effect.
+  MM_STATUS_INTERNAL_ERROR = -4,
+} MmStatus;
+
+// Opaque handle to a catalog database, the same SQLite file the service
+// uses. Calls on one handle may come from any thread.
+typedef struct MmDatabase MmDatabase;
+
+// Opaque fingerprint of one recording.
+typedef struct MmFingerprint MmFingerprint;
+
+// Best match for a fingerprint.
+typedef struct MmMatch {
+  int64_t song_id;
+  double confidence;
+  uint64_t shared_hashes;
+  uint64_t query_hashes;
+} MmMatch;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message for the last failed call on this thread; empty if none. Valid
+// until the next call on the same thread.
+const char *mm_last_error(void);
+
+// Decodes an audio file held in memory (WAV, FLAC, Ogg, MP4 or Matroska)
+// and fingerprints it with the default hashing parameters.
+//
+// # Safety
+// `buf` must point to `len` readable bytes and `out` must be writable.
+enum MmStatus mm_fingerprint(const uint8_t *buf, uintptr_t len, struct MmFingerprint **out);
+
+// Number of hashes in the fingerprint.
+//
+// # Safety
+// `fp` must be a live fingerprint from `mm_fingerprint`.
+uintptr_t mm_fingerprint_hash_count(const struct MmFingerprint *fp);
+
+// Duration of the fingerprinted audio in seconds.
+//
+// # Safety
+// `fp` must be a live fingerprint from `mm_fingerprint`.
+double mm_fingerprint_duration(const struct MmFingerprint *fp);
+
+// # Safety
+// `fp` must come from `mm_fingerprint` and not be used afterwards. Null is
+// ignored.
+void mm_fingerprint_free(struct MmFingerprint *fp);
+
+// Opens (creating if missing) the catalog database at `path`.
+//
+// # Safety
+// `path` must be a NUL-terminated string and `out` must be writable.
+enum MmStatus mm_db_open(const char *path, struct MmDatabase **out);
+
+// # Safety
+// `db` must come from `mm_db_open` and not be used afterwards. Null is
+// ignored.
+void mm_db_close(struct MmDatabase *db);
+
+// Looks up the best catalog match for `fp`. Returns MM_STATUS_NO_MATCH,
+// leaving `out` untouched, when nothing clears the confidence cutoff.
+//
+// # Safety
+// `db` and `fp` must be live handles and `out` must be writable.
+enum MmStatus mm_match(const struct MmDatabase *db,
+                       const struct MmFingerprint *fp,
+                       struct MmMatch *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MUSIC_MATCHER_H */
@@ -0,0 +1,222 @@
+//! C API for embedding the matcher in-process. Every function returns an
+//! `MmStatus`; on failure `mm_last_error()` describes what went wrong on the
+//! calling thread. Objects returned through out-pointers are owned by the
+//! caller and released with the matching `*_free` / `mm_db_close`.
+
+use music_matcher::config::AudioLimits;
+use music_matcher::database::Database;
+use music_matcher::fingerprint::{AudioFingerprint, HashParams};
+use music_matcher::{audio, fingerprint};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmStatus {
+    Ok = 0,
+    /// `mm_match` found nothing above the confidence cutoff.
+    NoMatch = 1,
+    InvalidArgument = -1,
+    /// The buffer isn't decodable audio, or exceeds the upload limits.
+    DecodeError = -2,
+    DatabaseError = -3,
+    /// A bug inside the library; the call had no effect.
+    InternalError = -4,
+}
+
+/// Opaque fingerprint of one recording.
+pub struct MmFingerprint {
+    inner: AudioFingerprint,
+}
+
+/// Opaque handle to a catalog database, the same SQLite file the service
+/// uses. Calls on one handle may come from any thread.
+pub struct MmDatabase {
+    db: Database,
+    runtime: tokio::runtime::Runtime,
+}
+
+/// Best match for a fingerprint.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MmMatch {
+    pub song_id: i64,
+    pub confidence: f64,
+    pub shared_hashes: u64,
+    pub query_hashes: u64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(message: impl std::fmt::Display) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// Runs `body` with panics turned into MmStatus::InternalError, since
+// unwinding into C is undefined behaviour.
+fn guard(body: impl FnOnce() -> MmStatus) -> MmStatus {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| {
+        set_error("internal error (panic) in music-matcher");
+        MmStatus::InternalError
+    })
+}
+
+/// Message for the last failed call on this thread; empty if none. Valid
+/// until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn mm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Decodes an audio file held in memory (WAV, FLAC, Ogg, MP4 or Matroska)
+/// and fingerprints it with the default hashing parameters.
+///
+/// # Safety
+/// `buf` must point to `len` readable bytes and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mm_fingerprint(buf: *const u8, len: usize, out: *mut *mut MmFingerprint) -> MmStatus {
+    guard(|| {
+        if buf.is_null() || out.is_null() {
+            set_error("buf and out must not be null");
+            return MmStatus::InvalidArgument;
+        }
+        let data = std::slice::from_raw_parts(buf, len);
+
+        let result = audio::decode_audio(data, &AudioLimits::default())
+            .and_then(|audio| fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &HashParams::default()));
+
+        match result {
+            Ok(inner) => {
+                *out = Box::into_raw(Box::new(MmFingerprint { inner }));
+                MmStatus::Ok
+            }
+            Err(e) => {
+                set_error(e);
+                MmStatus::DecodeError
+            }
+        }
+    })
+}
+
+/// Number of hashes in the fingerprint.
+///
+/// # Safety
+/// `fp` must be a live fingerprint from `mm_fingerprint`.
+#[no_mangle]
+pub unsafe extern "C" fn mm_fingerprint_hash_count(fp: *const MmFingerprint) -> usize {
+    fp.as_ref().map(|fp| fp.inner.hashes.len()).unwrap_or(0)
+}
+
+/// Duration of the fingerprinted audio in seconds.
+///
+/// # Safety
+/// `fp` must be a live fingerprint from `mm_fingerprint`.
+#[no_mangle]
+pub unsafe extern "C" fn mm_fingerprint_duration(fp: *const MmFingerprint) -> f64 {
+    fp.as_ref().map(|fp| fp.inner.duration).unwrap_or(0.0)
+}
+
+/// # Safety
+/// `fp` must come from `mm_fingerprint` and not be used afterwards. Null is
+/// ignored.
+#[no_mangle]
+pub unsafe extern "C" fn mm_fingerprint_free(fp: *mut MmFingerprint) {
+    if !fp.is_null() {
+        drop(Box::from_raw(fp));
+    }
+}
+
+/// Opens (creating if missing) the catalog database at `path`.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mm_db_open(path: *const c_char, out: *mut *mut MmDatabase) -> MmStatus {
+    guard(|| {
+        if path.is_null() || out.is_null() {
+            set_error("path and out must not be null");
+            return MmStatus::InvalidArgument;
+        }
+        let Ok(path) = CStr::from_ptr(path).to_str() else {
+            set_error("path is not valid UTF-8");
+            return MmStatus::InvalidArgument;
+        };
+
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(anyhow::Error::from)
+            .and_then(|runtime| {
+                let db = runtime.block_on(async {
+                    let db = Database::new(&format!("sqlite:{}", path), None).await?;
+                    db.init().await?;
+                    anyhow::Ok(db)
+                })?;
+                Ok(MmDatabase { db, runtime })
+            });
+
+        match result {
+            Ok(handle) => {
+                *out = Box::into_raw(Box::new(handle));
+                MmStatus::Ok
+            }
+            Err(e) => {
+                set_error(e);
+                MmStatus::DatabaseError
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `db` must come from `mm_db_open` and not be used afterwards. Null is
+/// ignored.
+#[no_mangle]
+pub unsafe extern "C" fn mm_db_close(db: *mut MmDatabase) {
+    if !db.is_null() {
+        // Fields drop in order, so the pool goes before its runtime.
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Looks up the best catalog match for `fp`. Returns MM_STATUS_NO_MATCH,
+/// leaving `out` untouched, when nothing clears the confidence cutoff.
+///
+/// # Safety
+/// `db` and `fp` must be live handles and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mm_match(db: *const MmDatabase, fp: *const MmFingerprint, out: *mut MmMatch) -> MmStatus {
+    guard(|| {
+        let (Some(handle), Some(fp)) = (db.as_ref(), fp.as_ref()) else {
+            set_error("db and fp must not be null");
+            return MmStatus::InvalidArgument;
+        };
+        if out.is_null() {
+            set_error("out must not be null");
+            return MmStatus::InvalidArgument;
+        }
+
+        match handle.runtime.block_on(handle.db.find_all_matches(&fp.inner)) {
+            Ok(matches) => match matches.into_iter().next() {
+                Some(best) => {
+                    *out = MmMatch {
+                        song_id: best.song_id,
+                        confidence: best.confidence,
+                        shared_hashes: best.evidence.shared_hashes as u64,
+                        query_hashes: best.evidence.query_hashes as u64,
+                    };
+                    MmStatus::Ok
+                }
+                None => MmStatus::NoMatch,
+            },
+            Err(e) => {
+                set_error(e);
+                MmStatus::DatabaseError
+            }
+        }
+    })
+}
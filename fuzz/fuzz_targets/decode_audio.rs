@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use music_matcher::audio::decode_audio;
+use music_matcher::config::AudioLimits;
+
+// Uploads reach decode_audio unfiltered, so any input may fail but none may
+// panic or allocate past the configured limits.
+fuzz_target!(
+    // libfuzzer's panic hook aborts before unwinding, which would defeat the
+    // catch_unwind guard around symphonia's demuxers. Panics that escape
+    // decode_audio still abort the run.
+    init: {
+        let _ = std::panic::take_hook();
+    },
+    |data: &[u8]| {
+        let _ = decode_audio(data, &AudioLimits::default());
+    }
+);
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use music_matcher::audio::decode_audio;
+use music_matcher::config::AudioLimits;
+use music_matcher::fingerprint::{generate_fingerprint, HashParams};
+
+// The full upload path: whatever decodes must also fingerprint without
+// panicking, including degenerate audio such as silence or a single frame.
+fuzz_target!(
+    // libfuzzer's panic hook aborts before unwinding, which would defeat the
+    // catch_unwind guard around symphonia's demuxers. Panics that escape
+    // decode_audio still abort the run.
+    init: {
+        let _ = std::panic::take_hook();
+    },
+    |data: &[u8]| {
+        if let Ok(audio) = decode_audio(data, &AudioLimits::default()) {
+            let _ = generate_fingerprint(&audio.samples, audio.sample_rate, &HashParams::default());
+        }
+    }
+);
@@ -0,0 +1,223 @@
+use music_matcher::config::AudioLimits;
+use music_matcher::database::Database;
+use music_matcher::fingerprint::{AudioFingerprint, HashParams};
+use music_matcher::scoring::{match_evidence, scorer_for, ScorerKind};
+use music_matcher::{audio, codec, fingerprint};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+fn value_error(e: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn runtime_error(e: impl std::fmt::Display) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+fn parse_scorer(name: &str) -> PyResult<ScorerKind> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|_| value_error(format!("Unknown scorer {:?}", name)))
+}
+
+// A fingerprint as stored by the service. Round-trips through the same binary
+// form the catalog keeps in songs.fingerprint_data, or through JSON.
+#[pyclass(name = "Fingerprint", module = "music_matcher", frozen)]
+#[derive(Clone)]
+struct PyFingerprint {
+    inner: AudioFingerprint,
+}
+
+#[pymethods]
+impl PyFingerprint {
+    #[getter]
+    fn hashes(&self) -> Vec<u64> {
+        self.inner.hashes.clone()
+    }
+
+    #[getter]
+    fn offsets(&self) -> Vec<u32> {
+        self.inner.offsets.clone()
+    }
+
+    #[getter]
+    fn weights(&self) -> Vec<u8> {
+        self.inner.weights.clone()
+    }
+
+    #[getter]
+    fn duration(&self) -> f64 {
+        self.inner.duration
+    }
+
+    #[getter]
+    fn time_delta_step(&self) -> u32 {
+        self.inner.params.time_delta_step
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.inner).map_err(runtime_error)
+    }
+
+    #[staticmethod]
+    fn from_json(data: &str) -> PyResult<Self> {
+        serde_json::from_str(data)
+            .map(|inner| PyFingerprint { inner })
+            .map_err(value_error)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &codec::encode(&self.inner))
+    }
+
+    // Also takes the JSON of rows stored before the binary form.
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        codec::decode(data)
+            .map(|inner| PyFingerprint { inner })
+            .map_err(value_error)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.hashes.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Fingerprint(hashes={}, duration={:.2})",
+            self.inner.hashes.len(),
+            self.inner.duration
+        )
+    }
+}
+
+// Decodes an upload (WAV, FLAC, Ogg, MP4 or Matroska) to mono samples and
+// their sample rate, with the service's default size and duration limits.
+#[pyfunction]
+fn decode(data: &[u8]) -> PyResult<(Vec<f32>, u32)> {
+    audio::decode_audio(data, &AudioLimits::default())
+        .map(|audio| (audio.samples, audio.sample_rate))
+        .map_err(value_error)
+}
+
+// Fingerprints mono samples. The GIL is released while hashing, so
+// notebooks can fingerprint a catalog from a thread pool.
+#[pyfunction]
+#[pyo3(signature = (samples, sample_rate = 44100, time_delta_step = 1))]
+fn fingerprint_samples(
+    py: Python<'_>,
+    samples: Vec<f32>,
+    sample_rate: u32,
+    time_delta_step: u32,
+) -> PyResult<PyFingerprint> {
+    let params = HashParams { time_delta_step, ..HashParams::default() };
+    py.allow_threads(|| fingerprint::generate_fingerprint(&samples, sample_rate, &params))
+        .map(|inner| PyFingerprint { inner })
+        .map_err(value_error)
+}
+
+#[pyfunction]
+#[pyo3(signature = (path, time_delta_step = 1))]
+fn fingerprint_file(py: Python<'_>, path: std::path::PathBuf, time_delta_step: u32) -> PyResult<PyFingerprint> {
+    let params = HashParams { time_delta_step, ..HashParams::default() };
+    py.allow_threads(|| {
+        let data = std::fs::read(&path)?;
+        let audio = audio::decode_audio(&data, &AudioLimits::default())?;
+        fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &params)
+    })
+    .map(|inner| PyFingerprint { inner })
+    .map_err(value_error)
+}
+
+// Scores `query` against `reference` with one of the service's scorers
+// (the names accepted by `matching.scorer` in the config).
+#[pyfunction]
+#[pyo3(signature = (query, reference, scorer = "containment"))]
+fn similarity(query: &PyFingerprint, reference: &PyFingerprint, scorer: &str) -> PyResult<f64> {
+    Ok(scorer_for(parse_scorer(scorer)?).score(&query.inner, &reference.inner))
+}
+
+// Shared/query/aligned hash counts behind a score, as /match reports them.
+#[pyfunction]
+fn evidence(query: &PyFingerprint, reference: &PyFingerprint) -> (usize, usize, usize) {
+    let evidence = match_evidence(&query.inner, &reference.inner);
+    (evidence.shared_hashes, evidence.query_hashes, evidence.aligned_hashes)
+}
+
+// A catalog database, the same SQLite file the service uses. Opening one
+// creates the schema if needed, so notebooks can build catalogs the service
+// can later serve.
+#[pyclass(name = "Catalog", module = "music_matcher")]
+struct PyCatalog {
+    db: Database,
+    runtime: tokio::runtime::Runtime,
+}
+
+#[pymethods]
+impl PyCatalog {
+    #[new]
+    #[pyo3(signature = (path, scorer = "containment", candidates = 20))]
+    fn new(path: &str, scorer: &str, candidates: usize) -> PyResult<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(runtime_error)?;
+        let scorer = parse_scorer(scorer)?;
+
+        let db = runtime
+            .block_on(async {
+                let db = Database::new(&format!("sqlite:{}", path), None)
+                    .await?
+                    .with_scorer(scorer)
+                    .with_candidate_limit(candidates);
+                db.init().await?;
+                anyhow::Ok(db)
+            })
+            .map_err(runtime_error)?;
+
+        Ok(PyCatalog { db, runtime })
+    }
+
+    fn add_song(&self, py: Python<'_>, title: &str, artist: &str, fingerprint: &PyFingerprint) -> PyResult<i64> {
+        py.allow_threads(|| {
+            self.runtime
+                .block_on(self.db.add_song(title, artist, &fingerprint.inner))
+        })
+        .map_err(runtime_error)
+    }
+
+    // Matches above the service's confidence cutoff, best first, as
+    // (song_id, title, artist, confidence) tuples. Nothing is recorded in
+    // the match history.
+    #[pyo3(name = "match")]
+    fn find_matches(&self, py: Python<'_>, fingerprint: &PyFingerprint) -> PyResult<Vec<(i64, String, String, f64)>> {
+        let matches = py
+            .allow_threads(|| self.runtime.block_on(self.db.find_all_matches(&fingerprint.inner)))
+            .map_err(runtime_error)?;
+
+        Ok(matches
+            .into_iter()
+            .map(|m| (m.song_id, m.title, m.artist, m.confidence))
+            .collect())
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        self.runtime
+            .block_on(self.db.count_songs())
+            .map(|count| count as usize)
+            .map_err(runtime_error)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "music_matcher")]
+fn init_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyFingerprint>()?;
+    m.add_class::<PyCatalog>()?;
+    m.add_function(wrap_pyfunction!(decode, m)?)?;
+    m.add_function(wrap_pyfunction!(fingerprint_samples, m)?)?;
+    m.add_function(wrap_pyfunction!(fingerprint_file, m)?)?;
+    m.add_function(wrap_pyfunction!(similarity, m)?)?;
+    m.add_function(wrap_pyfunction!(evidence, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct AdminAuth {
+    token: Option<Arc<str>>,
+    // Grants /admin/sync/changes only, so edges needn't hold the admin token.
+    sync_token: Option<Arc<str>>,
+}
+
+impl AdminAuth {
+    pub fn new(token: Option<String>, sync_token: Option<String>) -> Self {
+        AdminAuth {
+            token: token.map(Arc::from),
+            sync_token: sync_token.map(Arc::from),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.token.is_some()
+    }
+
+    fn accepts(&self, request: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        presents(request, token)
+    }
+
+    fn accepts_sync(&self, request: &Request) -> bool {
+        self.accepts(request) || self.sync_token.as_ref().is_some_and(|token| presents(request, token))
+    }
+}
+
+fn presents(request: &Request, token: &str) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| constant_time_eq(presented.trim().as_bytes(), token.as_bytes()))
+}
+
+// Guards the admin routes with `Authorization: Bearer <token>` once a token
+// is configured.
+pub async fn require_admin(State(auth): State<AdminAuth>, request: Request, next: Next) -> Response {
+    if auth.accepts(&request) {
+        return next.run(request).await;
+    }
+
+    unauthorized(&request)
+}
+
+// Guards the change log edges pull, which also accepts the sync token.
+pub async fn require_sync_reader(State(auth): State<AdminAuth>, request: Request, next: Next) -> Response {
+    if auth.accepts_sync(&request) {
+        return next.run(request).await;
+    }
+
+    unauthorized(&request)
+}
+
+fn unauthorized(request: &Request) -> Response {
+    warn!("Rejected unauthenticated request to {}", request.uri().path());
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+    )
+        .into_response()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
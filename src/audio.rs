@@ -1,25 +1,1268 @@
 use anyhow::{anyhow, Result};
-use hound::{WavReader, SampleFormat};
-use std::io::Cursor;
+use serde::{Deserialize, Serialize};
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::borrow::Cow;
+use std::f64::consts::PI;
+use std::io::{Cursor, Read};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{self, CodecParameters, CodecType, Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
 
-pub fn decode_audio(audio_data: &[u8]) -> Result<Vec<f32>> {
-    let cursor = Cursor::new(audio_data);
-    let mut reader = WavReader::new(cursor)
+use crate::config::{AudioLimits, DenoiseConfig, SilenceConfig};
+
+// An input rejected by AudioLimits rather than for being malformed.
+#[derive(Debug)]
+pub struct LimitExceeded(pub String);
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+// An upload in a container or codec there is no decoder for. The message
+// names what was detected.
+#[derive(Debug)]
+pub struct UnsupportedFormat(pub String);
+
+impl std::fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+// A requested time window that lies outside the decoded audio.
+#[derive(Debug)]
+pub struct WindowOutOfRange(pub String);
+
+impl std::fmt::Display for WindowOutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for WindowOutOfRange {}
+
+// A requested channel the upload doesn't have.
+#[derive(Debug)]
+pub struct ChannelOutOfRange(pub String);
+
+impl std::fmt::Display for ChannelOutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ChannelOutOfRange {}
+
+// A region of an upload to match or add, in seconds from its start. No
+// duration means "to the end".
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimeWindow {
+    pub offset_secs: f64,
+    pub duration_secs: Option<f64>,
+}
+
+impl TimeWindow {
+    // The window as a range of frames at `sample_rate`. The end is open
+    // without a duration, and at least one frame past the start with one.
+    fn frames(&self, sample_rate: u32) -> (u64, u64) {
+        let rate = sample_rate as f64;
+        let start = (self.offset_secs * rate) as u64;
+        let end = match self.duration_secs {
+            Some(duration) => start.saturating_add(((duration * rate) as u64).max(1)),
+            None => u64::MAX,
+        };
+        (start, end)
+    }
+
+    // How many of the first `total` frames are inside the window.
+    fn frames_within(&self, total: u64, sample_rate: u32) -> u64 {
+        let (start, end) = self.frames(sample_rate);
+        end.min(total).saturating_sub(start)
+    }
+}
+
+// The part of an upload to decode: a time window, and one channel (numbered
+// from 1) to take instead of the average of all of them, e.g. the program
+// feed of a broadcast recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Selection {
+    pub window: TimeWindow,
+    pub channel: Option<u16>,
+}
+
+impl Selection {
+    // Checks the channel against an upload with `channels` channels.
+    fn check_channel(&self, channels: usize) -> Result<()> {
+        match self.channel {
+            Some(channel) if channel == 0 || channel as usize > channels => Err(ChannelOutOfRange(format!(
+                "Channel {} was requested but the upload has {} channel{}",
+                channel,
+                channels,
+                if channels == 1 { "" } else { "s" }
+            ))
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Decoded frames, mixed to mono, of which only those inside a TimeWindow are
+// kept, so the rest of a long upload is never held in memory.
+struct WindowedSamples {
+    window: TimeWindow,
+    channel: Option<u16>,
+    // Frames at the start of the stream that aren't audio (Opus encoder
+    // priming) and are dropped before the window is counted.
+    skip: u64,
+    // Frames decoded so far, kept or not.
+    decoded: u64,
+    samples: Vec<f32>,
+    // The current block mixed to mono.
+    mono: Vec<f32>,
+}
+
+impl WindowedSamples {
+    fn new(selection: &Selection) -> Self {
+        WindowedSamples {
+            window: selection.window,
+            channel: selection.channel,
+            skip: 0,
+            decoded: 0,
+            samples: Vec::new(),
+            mono: Vec::new(),
+        }
+    }
+
+    // Takes the next frames of the stream, `channels` interleaved samples each.
+    fn extend(&mut self, interleaved: &[f32], channels: usize, sample_rate: u32) {
+        let mut mono = std::mem::take(&mut self.mono);
+        mono.clear();
+        mix_into(&mut mono, interleaved, channels, self.channel);
+
+        let skipped = self.skip.min(mono.len() as u64) as usize;
+        self.skip -= skipped as u64;
+        let frames = &mono[skipped..];
+
+        let (start, end) = self.window.frames(sample_rate);
+        let from = start.saturating_sub(self.decoded).min(frames.len() as u64) as usize;
+        let to = end.saturating_sub(self.decoded).min(frames.len() as u64) as usize;
+        if from < to {
+            self.samples.extend_from_slice(&frames[from..to]);
+        }
+        self.decoded += frames.len() as u64;
+        self.mono = mono;
+    }
+
+    // Whether the window's end has been decoded, so decoding can stop.
+    fn is_complete(&self, sample_rate: u32) -> bool {
+        self.decoded >= self.window.frames(sample_rate).1
+    }
+
+    // The kept samples, or WindowOutOfRange if the upload ended before the
+    // window started.
+    fn finish(self, sample_rate: u32) -> Result<Vec<f32>> {
+        if self.samples.is_empty() && self.decoded > 0 {
+            return Err(WindowOutOfRange(format!(
+                "Offset {}s is past the end of the {:.1}s upload",
+                self.window.offset_secs,
+                self.decoded as f64 / sample_rate.max(1) as f64
+            ))
+            .into());
+        }
+        Ok(self.samples)
+    }
+}
+
+// Mono samples at the rate the upload was recorded at.
+#[derive(Debug, Clone, Default)]
+pub struct DecodedAudio {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub source: SourceFormat,
+}
+
+// What an upload was decoded from, for ingest reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SourceFormat {
+    pub container: String,
+    pub codec: String,
+    // Before downmixing to mono.
+    pub channels: u16,
+    // The one channel taken instead of the downmix, if one was selected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u16>,
+}
+
+impl DecodedAudio {
+    pub fn duration_secs(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate.max(1) as f64
+    }
+}
+
+// The samples inside `window`. A window running past the end is cut short;
+// one starting past the end is an error.
+pub fn select_window<'a>(audio: &'a DecodedAudio, window: &TimeWindow) -> Result<&'a [f32]> {
+    let rate = audio.sample_rate as f64;
+    let start = (window.offset_secs * rate) as usize;
+    if start >= audio.samples.len() {
+        return Err(WindowOutOfRange(format!(
+            "Offset {}s is past the end of the {:.1}s upload",
+            window.offset_secs,
+            audio.duration_secs()
+        ))
+        .into());
+    }
+
+    let end = match window.duration_secs {
+        Some(duration) => start.saturating_add((duration * rate) as usize).min(audio.samples.len()),
+        None => audio.samples.len(),
+    };
+
+    Ok(&audio.samples[start..end])
+}
+
+// What a duration cap cut from a clip, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Truncation {
+    pub original_duration: f64,
+    pub matched_duration: f64,
+}
+
+// The first `max_secs` of `samples`, and what was cut if the clip was longer.
+pub fn truncate(samples: &[f32], sample_rate: u32, max_secs: Option<f64>) -> (&[f32], Option<Truncation>) {
+    let max_len = match max_secs {
+        Some(secs) => (secs * sample_rate as f64) as usize,
+        None => return (samples, None),
+    };
+    if samples.len() <= max_len {
+        return (samples, None);
+    }
+
+    let truncation = Truncation {
+        original_duration: samples.len() as f64 / sample_rate as f64,
+        matched_duration: max_len as f64 / sample_rate as f64,
+    };
+    (&samples[..max_len], Some(truncation))
+}
+
+// How many bytes of the upload the decoded sample count is checked against:
+// all of it when the upload was buffered, or what the decoder has read so far
+// when it is decoded while still arriving.
+enum InputSize {
+    Known(usize),
+    Streamed(Arc<AtomicUsize>),
+}
+
+impl InputSize {
+    fn bytes(&self) -> usize {
+        match self {
+            InputSize::Known(len) => *len,
+            InputSize::Streamed(read) => read.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Counts the bytes a decoder has pulled from a streamed upload.
+struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicUsize>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+// Checks `decoded` frames, of which `kept` are inside the window, from an
+// input of `input_len` bytes. Limits count every channel. The size limit
+// applies to everything decoded, since it guards against inputs that expand
+// far beyond their size, and the duration limit to what is kept.
+fn check_limits(
+    decoded: u64,
+    kept: u64,
+    input_len: usize,
+    sample_rate: u32,
+    channels: u16,
+    limits: &AudioLimits,
+) -> Result<()> {
+    let channels = channels.max(1) as u64;
+    let by_size = (input_len as u64).saturating_mul(limits.max_samples_per_byte);
+    let by_duration = limits.max_duration_secs * sample_rate as u64 * channels;
+    check_sample_count(decoded.saturating_mul(channels), by_size)?;
+    check_sample_count(kept.saturating_mul(channels), by_duration)
+}
+
+fn check_sample_count(count: u64, max: u64) -> Result<()> {
+    if count > max {
+        return Err(LimitExceeded(format!(
+            "Decoded audio exceeds {} samples allowed for this upload (size and duration limits)",
+            max
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Wav,
+    Mp4,
+    Matroska,
+    Flac,
+    Ogg,
+    // Raw AAC in ADTS frames, as streamed by internet radio.
+    Adts,
+    // Recognized, but not decodable; holds the format's name.
+    Unsupported(&'static str),
+}
+
+// Bytes detect_container needs to look at.
+const CONTAINER_MAGIC_LEN: usize = 8;
+
+// Decodes an upload to one mono channel; the fingerprinter works on mono.
+pub fn decode_audio(audio_data: &[u8], limits: &AudioLimits) -> Result<DecodedAudio> {
+    decode_audio_selection(audio_data, limits, &Selection::default())
+}
+
+// decode_audio for only the `selection` of an upload. Decoding stops at the
+// end of its window, and the duration limit applies to the window rather than
+// the whole upload.
+pub fn decode_audio_selection(audio_data: &[u8], limits: &AudioLimits, selection: &Selection) -> Result<DecodedAudio> {
+    let input = InputSize::Known(audio_data.len());
+    let source = || -> Box<dyn MediaSource> { Box::new(Cursor::new(audio_data.to_vec())) };
+
+    let audio = match detect_container(audio_data) {
+        Container::Wav => decode_wav(Cursor::new(audio_data), &input, limits, selection),
+        Container::Mp4 => decode_container(source(), "mp4", &input, limits, selection),
+        Container::Matroska => decode_container(source(), "mkv", &input, limits, selection),
+        Container::Flac => decode_container(source(), "flac", &input, limits, selection),
+        Container::Ogg => decode_container(source(), "ogg", &input, limits, selection),
+        Container::Adts => decode_container(source(), "aac", &input, limits, selection),
+        Container::Unsupported(format) => return Err(unsupported_container(format)),
+    }?;
+    finish_decode(audio, limits)
+}
+
+// decode_audio for an upload that is still arriving, e.g. a request body read
+// chunk by chunk, so the encoded file is never held in memory whole and
+// decoding keeps pace with the upload. MP4 may keep its index at the end of
+// the file, so MP4 uploads are read to the end before decoding.
+pub fn decode_audio_stream<R: Read + Send + Sync + 'static>(
+    reader: R,
+    limits: &AudioLimits,
+    selection: &Selection,
+) -> Result<DecodedAudio> {
+    let read = Arc::new(AtomicUsize::new(0));
+    let mut reader = CountingReader { inner: reader, read: read.clone() };
+
+    let mut head = Vec::with_capacity(CONTAINER_MAGIC_LEN);
+    (&mut reader)
+        .take(CONTAINER_MAGIC_LEN as u64)
+        .read_to_end(&mut head)
+        .map_err(|e| anyhow!("Failed to read upload: {}", e))?;
+    let container = detect_container(&head);
+    let mut stream = Cursor::new(head).chain(reader);
+    let input = InputSize::Streamed(read);
+
+    let audio = match container {
+        Container::Wav => decode_wav(stream, &input, limits, selection),
+        Container::Mp4 => {
+            let mut audio_data = Vec::new();
+            stream
+                .read_to_end(&mut audio_data)
+                .map_err(|e| anyhow!("Failed to read upload: {}", e))?;
+            return decode_audio_selection(&audio_data, limits, selection);
+        }
+        Container::Matroska => {
+            decode_container(Box::new(ReadOnlySource::new(stream)), "mkv", &input, limits, selection)
+        }
+        Container::Flac => {
+            decode_container(Box::new(ReadOnlySource::new(stream)), "flac", &input, limits, selection)
+        }
+        Container::Ogg => {
+            decode_container(Box::new(ReadOnlySource::new(stream)), "ogg", &input, limits, selection)
+        }
+        Container::Adts => {
+            decode_container(Box::new(ReadOnlySource::new(stream)), "aac", &input, limits, selection)
+        }
+        Container::Unsupported(format) => return Err(unsupported_container(format)),
+    }?;
+    finish_decode(audio, limits)
+}
+
+// What an upload holds, read from its headers without decoding it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AudioProbe {
+    pub container: String,
+    pub codec: String,
+    pub sample_rate: u32,
+    // None when the container leaves it to the codec to say.
+    pub channels: Option<u16>,
+    // None for lossy codecs, which have no fixed bit depth.
+    pub bit_depth: Option<u16>,
+    // Seconds; None when the headers don't give the length.
+    pub duration: Option<f64>,
+}
+
+// Identifies an upload the way decode_audio would, failing with the same
+// UnsupportedFormat error for anything it couldn't decode. Limits aren't
+// applied, since nothing is decoded.
+pub fn probe(audio_data: &[u8]) -> Result<AudioProbe> {
+    match detect_container(audio_data) {
+        Container::Wav => probe_wav(audio_data),
+        Container::Mp4 => probe_container(audio_data, "mp4"),
+        Container::Matroska => probe_container(audio_data, "mkv"),
+        Container::Flac => probe_container(audio_data, "flac"),
+        Container::Ogg => probe_container(audio_data, "ogg"),
+        Container::Adts => probe_container(audio_data, "aac"),
+        Container::Unsupported(format) => Err(unsupported_container(format)),
+    }
+}
+
+fn probe_wav(audio_data: &[u8]) -> Result<AudioProbe> {
+    let header = read_wav_header(&mut Cursor::new(audio_data))?;
+
+    Ok(AudioProbe {
+        container: "wav".to_string(),
+        codec: header.codec(),
+        sample_rate: header.sample_rate,
+        channels: Some(header.channels),
+        bit_depth: Some(header.bits),
+        duration: Some(header.frames() as f64 / header.sample_rate.max(1) as f64),
+    })
+}
+
+// Caught for the same reason as in decode_container.
+fn probe_container(audio_data: &[u8], extension: &str) -> Result<AudioProbe> {
+    std::panic::catch_unwind(AssertUnwindSafe(|| probe_track(audio_data, extension)))
+        .unwrap_or_else(|_| Err(anyhow!("Failed to read {} container: malformed input", extension)))
+}
+
+fn probe_track(audio_data: &[u8], extension: &str) -> Result<AudioProbe> {
+    let format = open_container(Box::new(Cursor::new(audio_data.to_vec())), extension)?;
+    let params = &audio_track(format.as_ref(), extension)?.codec_params;
+
+    let codec = if params.codec == CODEC_TYPE_OPUS {
+        "opus".to_string()
+    } else {
+        symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|descriptor| descriptor.short_name.to_string())
+            .ok_or_else(|| {
+                UnsupportedFormat(format!(
+                    "Unsupported audio codec in {} container: {}",
+                    extension,
+                    codec_name(params.codec)
+                ))
+            })?
+    };
+    let sample_rate = params.sample_rate.unwrap_or(0);
+    let duration = match (params.n_frames, params.time_base) {
+        (Some(frames), Some(time_base)) => {
+            let time = time_base.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
+        }
+        (Some(frames), None) if sample_rate > 0 => Some(frames as f64 / sample_rate as f64),
+        _ => None,
+    };
+
+    Ok(AudioProbe {
+        container: extension.to_string(),
+        codec,
+        sample_rate,
+        channels: params.channels.map(|c| c.count() as u16),
+        bit_depth: params.bits_per_sample.map(|bits| bits as u16),
+        duration,
+    })
+}
+
+// Layout of a headerless PCM upload, as declared by the client: interleaved
+// little-endian frames. 8-bit samples are unsigned, as in WAV; wider integer
+// samples are signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    pub channels: u16,
+    pub float: bool,
+}
+
+// Decodes the `selection` of raw PCM in the declared `format`. A trailing
+// partial frame is ignored.
+pub fn decode_pcm(data: &[u8], format: &PcmFormat, limits: &AudioLimits, selection: &Selection) -> Result<DecodedAudio> {
+    let encoding = SampleEncoding::new(format.bit_depth as usize / 8, format.float)
+        .filter(|_| format.bit_depth.is_multiple_of(8))
+        .ok_or_else(|| {
+            UnsupportedFormat(format!(
+                "Unsupported PCM sample format: {}-bit {}",
+                format.bit_depth,
+                if format.float { "float" } else { "integer" }
+            ))
+        })?;
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err(UnsupportedFormat("PCM channels and sample rate must be positive".to_string()).into());
+    }
+
+    let channels = format.channels as usize;
+    selection.check_channel(channels)?;
+    let frames = (data.len() / (encoding.bytes() * channels)) as u64;
+    check_limits(
+        frames,
+        selection.window.frames_within(frames, format.sample_rate),
+        data.len(),
+        format.sample_rate,
+        format.channels,
+        limits,
+    )?;
+
+    let mut kept = WindowedSamples::new(selection);
+    read_pcm_mono(data, encoding, channels, frames, format.sample_rate, &mut kept)?;
+    finish_decode(
+        DecodedAudio {
+            samples: kept.finish(format.sample_rate)?,
+            sample_rate: format.sample_rate,
+            source: SourceFormat {
+                container: "raw".to_string(),
+                codec: encoding.codec(format.bit_depth),
+                channels: format.channels,
+                channel: selection.channel,
+            },
+        },
+        limits,
+    )
+}
+
+fn unsupported_container(format: &str) -> anyhow::Error {
+    UnsupportedFormat(format!("Unsupported audio format: {}", format)).into()
+}
+
+// Checks that apply to the decoded signal whatever it was decoded from.
+fn finish_decode(audio: DecodedAudio, limits: &AudioLimits) -> Result<DecodedAudio> {
+    if audio.sample_rate < crate::fingerprint::MIN_SAMPLE_RATE {
+        return Err(UnsupportedFormat(format!(
+            "Unsupported sample rate: {} Hz, at least {} Hz is needed",
+            audio.sample_rate,
+            crate::fingerprint::MIN_SAMPLE_RATE
+        ))
+        .into());
+    }
+
+    // Decoding doesn't know the hashing settings, so this is the default
+    // analysis layout's spectrogram. A smaller hop or a larger window takes
+    // proportionally more.
+    let analysis = crate::fingerprint::FingerprintConfig::default();
+    let spectrogram_bytes = crate::fingerprint::spectrogram_bytes(audio.samples.len(), audio.sample_rate, &analysis);
+    if spectrogram_bytes > limits.max_spectrogram_bytes {
+        return Err(LimitExceeded(format!(
+            "Audio would need a {} byte spectrogram, limit is {}",
+            spectrogram_bytes, limits.max_spectrogram_bytes
+        ))
+        .into());
+    }
+
+    Ok(audio)
+}
+
+fn detect_container(data: &[u8]) -> Container {
+    if data.len() >= 8 && &data[4..8] == b"ftyp" {
+        Container::Mp4
+    } else if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Container::Matroska
+    } else if data.starts_with(b"fLaC") {
+        Container::Flac
+    } else if data.starts_with(b"OggS") {
+        Container::Ogg
+    } else if data.len() >= 2 && data[0] == 0xFF && data[1] & 0xF6 == 0xF0 {
+        // ADTS shares the MPEG audio sync word but always has layer 00.
+        Container::Adts
+    } else if data.starts_with(b"ID3") || (data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0 && data[1] & 0x06 != 0) {
+        Container::Unsupported("MP3")
+    } else if data.starts_with(b"FORM") {
+        Container::Unsupported("AIFF")
+    } else if data.starts_with(&[0x30, 0x26, 0xB2, 0x75]) {
+        Container::Unsupported("ASF (WMA)")
+    } else {
+        // Anything else is tried as WAV, so its error says what was wrong
+        // with the header.
+        Container::Wav
+    }
+}
+
+// Names the codecs uploads are most likely to carry that have no decoder
+// here, for the unsupported-format error.
+fn codec_name(codec: CodecType) -> String {
+    match codec {
+        codecs::CODEC_TYPE_MP1 | codecs::CODEC_TYPE_MP2 | codecs::CODEC_TYPE_MP3 => "MPEG audio".to_string(),
+        codecs::CODEC_TYPE_EAC3 => "E-AC-3".to_string(),
+        codecs::CODEC_TYPE_DCA => "DTS".to_string(),
+        codecs::CODEC_TYPE_WMA => "WMA".to_string(),
+        codecs::CODEC_TYPE_SPEEX => "Speex".to_string(),
+        codecs::CODEC_TYPE_WAVPACK => "WavPack".to_string(),
+        codec => format!("codec {}", codec),
+    }
+}
+
+// Appends the average of each frame of `interleaved` to `mono`, or only its
+// `channel`. Decoders downmix as they go so the multichannel signal is never
+// held whole.
+fn mix_into(mono: &mut Vec<f32>, interleaved: &[f32], channels: usize, channel: Option<u16>) {
+    if channels <= 1 {
+        mono.extend_from_slice(interleaved);
+        return;
+    }
+
+    let frames = interleaved.chunks_exact(channels);
+    match channel {
+        Some(channel) => mono.extend(frames.map(|frame| frame[channel as usize - 1])),
+        None => mono.extend(frames.map(|frame| frame.iter().sum::<f32>() / channels as f32)),
+    }
+}
+
+// Frames of PCM samples converted before each downmix.
+const BLOCK_FRAMES: usize = 4096;
+
+// How one PCM sample is stored. Integer samples narrower than their
+// container are left-justified in it, so scaling by the container's width is
+// right for them too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleEncoding {
+    // Offset binary: 128 is silence.
+    Unsigned8,
+    Signed16,
+    // Packed in three bytes.
+    Signed24,
+    Signed32,
+    Float32,
+    Float64,
+    // G.711 telephony codes, one byte each, companded from 14-bit (µ-law)
+    // or 13-bit (A-law) PCM.
+    MuLaw,
+    ALaw,
+}
+
+impl SampleEncoding {
+    fn new(bytes: usize, float: bool) -> Option<SampleEncoding> {
+        match (float, bytes) {
+            (false, 1) => Some(SampleEncoding::Unsigned8),
+            (false, 2) => Some(SampleEncoding::Signed16),
+            (false, 3) => Some(SampleEncoding::Signed24),
+            (false, 4) => Some(SampleEncoding::Signed32),
+            (true, 4) => Some(SampleEncoding::Float32),
+            (true, 8) => Some(SampleEncoding::Float64),
+            _ => None,
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            SampleEncoding::Unsigned8 | SampleEncoding::MuLaw | SampleEncoding::ALaw => 1,
+            SampleEncoding::Signed16 => 2,
+            SampleEncoding::Signed24 => 3,
+            SampleEncoding::Signed32 | SampleEncoding::Float32 => 4,
+            SampleEncoding::Float64 => 8,
+        }
+    }
+
+    // Little-endian `bytes` as a sample in [-1, 1).
+    fn to_f32(self, b: &[u8]) -> f32 {
+        match self {
+            SampleEncoding::Unsigned8 => (b[0] as f32 - 128.0) / 128.0,
+            SampleEncoding::Signed16 => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            // Shifted into the top of an i32, which sign-extends it.
+            SampleEncoding::Signed24 => i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+            SampleEncoding::Signed32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            SampleEncoding::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            SampleEncoding::Float64 => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+            SampleEncoding::MuLaw => expand_mu_law(b[0]) as f32 / 32768.0,
+            SampleEncoding::ALaw => expand_a_law(b[0]) as f32 / 32768.0,
+        }
+    }
+
+    // `bits` is how many bits of each sample are used.
+    fn codec(self, bits: u16) -> String {
+        match self {
+            SampleEncoding::Float32 | SampleEncoding::Float64 => format!("float {}-bit", bits),
+            SampleEncoding::MuLaw => "G.711 µ-law".to_string(),
+            SampleEncoding::ALaw => "G.711 A-law".to_string(),
+            _ => format!("PCM {}-bit", bits),
+        }
+    }
+}
+
+// G.711 expansion to 16-bit linear PCM. Codes are stored inverted (µ-law) or
+// with alternate bits flipped (A-law); the rest is a sign, a 3-bit segment
+// and a 4-bit step within the segment, decoded to the middle of the step.
+fn expand_mu_law(code: u8) -> i16 {
+    let code = !code;
+    let segment = (code >> 4) & 0x07;
+    let magnitude = ((((code & 0x0F) as i16) << 3) + 0x84) << segment;
+    if code & 0x80 != 0 {
+        0x84 - magnitude
+    } else {
+        magnitude - 0x84
+    }
+}
+
+fn expand_a_law(code: u8) -> i16 {
+    let code = code ^ 0x55;
+    let step = ((code & 0x0F) as i16) << 4;
+    let magnitude = match (code >> 4) & 0x07 {
+        0 => step + 0x08,
+        segment => (step + 0x108) << (segment - 1),
+    };
+    if code & 0x80 != 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+// Reads up to `frames` interleaved frames and mixes them onto `kept` a block
+// at a time, stopping early once its window is complete.
+fn read_pcm_mono<R: Read>(
+    mut reader: R,
+    encoding: SampleEncoding,
+    channels: usize,
+    frames: u64,
+    sample_rate: u32,
+    kept: &mut WindowedSamples,
+) -> Result<()> {
+    let frame_bytes = encoding.bytes() * channels;
+    let mut remaining = frames.saturating_mul(frame_bytes as u64);
+    let mut raw = vec![0u8; BLOCK_FRAMES * frame_bytes];
+    let mut block = Vec::with_capacity(BLOCK_FRAMES * channels);
+
+    while remaining > 0 && !kept.is_complete(sample_rate) {
+        let len = raw.len().min(remaining as usize);
+        reader
+            .read_exact(&mut raw[..len])
+            .map_err(|e| anyhow!("Failed to read audio samples: {}", e))?;
+        block.clear();
+        block.extend(raw[..len].chunks_exact(encoding.bytes()).map(|b| encoding.to_f32(b)));
+        kept.extend(&block, channels, sample_rate);
+        remaining -= len as u64;
+    }
+
+    Ok(())
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_ALAW: u16 = 0x0006;
+const WAVE_FORMAT_MULAW: u16 = 0x0007;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// Longest fmt chunk read; the extensible form is 40 bytes.
+const MAX_FMT_CHUNK: u32 = 1024;
+
+// What decode_wav needs from a WAV header, which is read up to the start of
+// the samples.
+struct WavHeader {
+    channels: u16,
+    sample_rate: u32,
+    encoding: SampleEncoding,
+    // Bits used in each sample, for reports.
+    bits: u16,
+    data_len: u32,
+}
+
+impl WavHeader {
+    // A trailing partial frame is ignored.
+    fn frames(&self) -> u64 {
+        self.data_len as u64 / (self.encoding.bytes() * self.channels as usize) as u64
+    }
+
+    fn codec(&self) -> String {
+        self.encoding.codec(self.bits)
+    }
+}
+
+fn read_wav_header<R: Read>(reader: &mut R) -> Result<WavHeader> {
+    let mut riff = Vec::with_capacity(12);
+    reader
+        .take(12)
+        .read_to_end(&mut riff)
         .map_err(|e| anyhow!("Failed to read WAV file: {}", e))?;
+    // Uploads in no format detect_container knows end up here, so this is
+    // the error for those rather than for a broken WAV file.
+    if !riff.starts_with(b"RIFF") {
+        return Err(UnsupportedFormat("Failed to read WAV file: no RIFF tag found".to_string()).into());
+    }
+    if riff.get(8..12) != Some(b"WAVE".as_slice()) {
+        return Err(anyhow!("Failed to read WAV file: no WAVE tag found"));
+    }
 
-    let spec = reader.spec();
-    
-    match spec.sample_format {
-        SampleFormat::Float => {
-            let samples: Result<Vec<f32>, _> = reader.samples::<f32>().collect();
-            samples.map_err(|e| anyhow!("Failed to read float samples: {}", e))
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader
+            .read_exact(&mut chunk)
+            .map_err(|_| anyhow!("Failed to read WAV file: no data chunk found"))?;
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+        match &chunk[0..4] {
+            b"fmt " if (16..=MAX_FMT_CHUNK).contains(&len) => {
+                let mut fmt = vec![0u8; len as usize];
+                reader
+                    .read_exact(&mut fmt)
+                    .map_err(|e| anyhow!("Failed to read WAV file: {}", e))?;
+                format = Some(parse_wav_format(&fmt)?);
+                skip_bytes(reader, (len & 1) as u64)?;
+            }
+            b"fmt " => return Err(anyhow!("Failed to read WAV file: fmt chunk of {} bytes", len)),
+            b"data" => {
+                let (channels, sample_rate, encoding, bits) =
+                    format.ok_or_else(|| anyhow!("Failed to read WAV file: data chunk before fmt chunk"))?;
+                return Ok(WavHeader { channels, sample_rate, encoding, bits, data_len: len });
+            }
+            // Chunks are padded to an even length.
+            _ => skip_bytes(reader, len as u64 + (len & 1) as u64)?,
         }
-        SampleFormat::Int => {
-            let samples: Result<Vec<i32>, _> = reader.samples::<i32>().collect();
-            let samples = samples.map_err(|e| anyhow!("Failed to read int samples: {}", e))?;
-            
-            let max_value = (1 << (spec.bits_per_sample - 1)) as f32;
-            Ok(samples.into_iter().map(|s| s as f32 / max_value).collect())
+    }
+}
+
+fn skip_bytes<R: Read>(reader: &mut R, len: u64) -> Result<()> {
+    let skipped = std::io::copy(&mut reader.take(len), &mut std::io::sink())
+        .map_err(|e| anyhow!("Failed to read WAV file: {}", e))?;
+    if skipped < len {
+        return Err(anyhow!("Failed to read WAV file: truncated chunk"));
+    }
+    Ok(())
+}
+
+// Channels, sample rate, encoding and bits used, from a fmt chunk.
+fn parse_wav_format(fmt: &[u8]) -> Result<(u16, u32, SampleEncoding, u16)> {
+    let u16_at = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]);
+    let channels = u16_at(2);
+    let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+    let block_align = u16_at(12);
+    let mut bits = u16_at(14);
+
+    // The extensible form keeps the real format tag at the start of its
+    // subformat GUID, and may use fewer bits than each sample is stored in.
+    let mut tag = u16_at(0);
+    if tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 40 {
+        tag = u16_at(24);
+        bits = match u16_at(18) {
+            0 => bits,
+            valid => valid,
+        };
+    }
+
+    if channels == 0 || sample_rate == 0 {
+        return Err(anyhow!("Failed to read WAV file: no channels or sample rate"));
+    }
+    let container_bytes = (block_align / channels) as usize;
+    let float = tag == WAVE_FORMAT_IEEE_FLOAT;
+    let encoding = match tag {
+        WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT => SampleEncoding::new(container_bytes, float),
+        WAVE_FORMAT_ALAW => (container_bytes == 1).then_some(SampleEncoding::ALaw),
+        WAVE_FORMAT_MULAW => (container_bytes == 1).then_some(SampleEncoding::MuLaw),
+        other => return Err(UnsupportedFormat(format!("Unsupported WAV encoding: {}", wav_encoding_name(other))).into()),
+    };
+    let encoding = encoding
+        .filter(|_| bits > 0 && bits as usize <= container_bytes * 8)
+        .ok_or_else(|| {
+            UnsupportedFormat(format!(
+                "Unsupported WAV sample format: {}-bit {} in {} bytes",
+                bits,
+                if float { "float" } else { "integer" },
+                container_bytes
+            ))
+        })?;
+
+    Ok((channels, sample_rate, encoding, bits))
+}
+
+// Names the compressed WAV encodings uploads are most likely to carry.
+fn wav_encoding_name(tag: u16) -> String {
+    match tag {
+        0x0002 => "MS ADPCM".to_string(),
+        0x0011 => "IMA ADPCM".to_string(),
+        0x0031 => "GSM 6.10".to_string(),
+        0x0055 => "MPEG audio".to_string(),
+        tag => format!("format tag 0x{:04x}", tag),
+    }
+}
+
+fn decode_wav<R: Read>(
+    mut reader: R,
+    input: &InputSize,
+    limits: &AudioLimits,
+    selection: &Selection,
+) -> Result<DecodedAudio> {
+    let header = read_wav_header(&mut reader)?;
+    selection.check_channel(header.channels as usize)?;
+
+    // The header's declared length is checked up front; the data itself can't
+    // hold more samples than the upload has bytes. A streamed upload's size
+    // isn't known yet, so only its duration is checked.
+    let input_len = match input {
+        InputSize::Known(len) => *len,
+        InputSize::Streamed(_) => usize::MAX,
+    };
+    check_limits(
+        header.frames(),
+        selection.window.frames_within(header.frames(), header.sample_rate),
+        input_len,
+        header.sample_rate,
+        header.channels,
+        limits,
+    )?;
+
+    let mut kept = WindowedSamples::new(selection);
+    let channels = header.channels as usize;
+    read_pcm_mono(reader, header.encoding, channels, header.frames(), header.sample_rate, &mut kept)?;
+
+    Ok(DecodedAudio {
+        samples: kept.finish(header.sample_rate)?,
+        sample_rate: header.sample_rate,
+        source: SourceFormat {
+            container: "wav".to_string(),
+            codec: header.codec(),
+            channels: header.channels,
+            channel: selection.channel,
+        },
+    })
+}
+
+// symphonia's demuxers can panic on malformed input (e.g. arithmetic
+// overflow in the EBML reader), so a bad upload is turned into an error
+// instead of taking down the worker.
+fn decode_container(
+    source: Box<dyn MediaSource>,
+    extension: &str,
+    input: &InputSize,
+    limits: &AudioLimits,
+    selection: &Selection,
+) -> Result<DecodedAudio> {
+    std::panic::catch_unwind(AssertUnwindSafe(move || decode_video_audio(source, extension, input, limits, selection)))
+        .unwrap_or_else(|_| Err(anyhow!("Failed to read {} container: malformed input", extension)))
+}
+
+// Demuxes the first audio track of a video container (or the stream of a
+// FLAC, Ogg or ADTS file) and decodes it to mono f32 samples, matching what the
+// WAV path produces.
+fn decode_video_audio(
+    source: Box<dyn MediaSource>,
+    extension: &str,
+    input: &InputSize,
+    limits: &AudioLimits,
+    selection: &Selection,
+) -> Result<DecodedAudio> {
+    let mut format = open_container(source, extension)?;
+    let track = audio_track(format.as_ref(), extension)?;
+    let track_id = track.id;
+
+    let mut decoder = if track.codec_params.codec == CODEC_TYPE_OPUS {
+        TrackDecoder::Opus(Box::new(OpusTrack::new(&track.codec_params)?))
+    } else {
+        TrackDecoder::Symphonia(
+            symphonia::default::get_codecs()
+                .make(&track.codec_params, &DecoderOptions::default())
+                .map_err(|_| {
+                    UnsupportedFormat(format!(
+                        "Unsupported audio codec in {} container: {}",
+                        extension,
+                        codec_name(track.codec_params.codec)
+                    ))
+                })?,
+        )
+    };
+
+    let mut kept = WindowedSamples::new(selection);
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut channels = track.codec_params.channels.map(|c| c.count()).unwrap_or(1);
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
+    if let TrackDecoder::Opus(opus) = &decoder {
+        kept.skip = opus.pre_skip as u64;
+        sample_rate = OPUS_SAMPLE_RATE;
+        channels = opus.channels;
+        selection.check_channel(channels)?;
+    }
+    let check = |kept: &WindowedSamples, channels: usize, sample_rate: u32| {
+        check_limits(
+            kept.decoded,
+            kept.samples.len() as u64,
+            input.bytes(),
+            sample_rate,
+            channels as u16,
+            limits,
+        )
+    };
+
+    while !kept.is_complete(sample_rate) {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(anyhow!("Failed to demux audio track: {}", e)),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoder = match &mut decoder {
+            TrackDecoder::Opus(opus) => {
+                opus.decode(&packet.data, &mut kept)?;
+                check(&kept, channels, sample_rate)?;
+                continue;
+            }
+            TrackDecoder::Symphonia(decoder) => decoder,
+        };
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let buf = sample_buf.get_or_insert_with(|| {
+                    channels = decoded.spec().channels.count().max(1);
+                    sample_rate = decoded.spec().rate;
+                    SampleBuffer::new(decoded.capacity() as u64, *decoded.spec())
+                });
+                buf.copy_interleaved_ref(decoded);
+                selection.check_channel(channels)?;
+                kept.extend(buf.samples(), channels, sample_rate);
+                check(&kept, channels, sample_rate)?;
+            }
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(anyhow!("Failed to decode audio track: {}", e)),
+        }
+    }
+
+    let codec = match &decoder {
+        TrackDecoder::Opus(_) => "opus".to_string(),
+        TrackDecoder::Symphonia(decoder) => {
+            let codec = decoder.codec_params().codec;
+            symphonia::default::get_codecs()
+                .get_codec(codec)
+                .map(|descriptor| descriptor.short_name.to_string())
+                .unwrap_or_else(|| codec_name(codec))
+        }
+    };
+
+    if kept.decoded == 0 {
+        return Err(anyhow!("Audio track in {} container contained no samples", extension));
+    }
+
+    Ok(DecodedAudio {
+        samples: kept.finish(sample_rate)?,
+        sample_rate,
+        source: SourceFormat {
+            container: extension.to_string(),
+            codec,
+            channels: channels as u16,
+            channel: selection.channel,
+        },
+    })
+}
+
+fn open_container(source: Box<dyn MediaSource>, extension: &str) -> Result<Box<dyn FormatReader>> {
+    let stream = MediaSourceStream::new(source, Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| match e {
+            SymphoniaError::Unsupported(feature) => {
+                UnsupportedFormat(format!("Unsupported {} container: {}", extension, feature)).into()
+            }
+            e => anyhow!("Failed to read {} container: {}", extension, e),
+        })?;
+    Ok(probed.format)
+}
+
+fn audio_track<'a>(format: &'a dyn FormatReader, extension: &str) -> Result<&'a Track> {
+    format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL && t.codec_params.sample_rate.is_some())
+        .ok_or_else(|| anyhow!("No audio track found in {} container", extension))
+}
+
+// symphonia 0.5 demuxes Opus (Ogg, WebM) but has no decoder for it.
+enum TrackDecoder {
+    Symphonia(Box<dyn Decoder>),
+    Opus(Box<OpusTrack>),
+}
+
+// Opus always decodes at 48 kHz, whatever rate the source had.
+const OPUS_SAMPLE_RATE: u32 = 48000;
+// Longest Opus packet: 120 ms at 48 kHz.
+const OPUS_MAX_FRAME: usize = 5760;
+
+struct OpusTrack {
+    decoder: opus_decoder::OpusDecoder,
+    channels: usize,
+    // Encoder priming samples per channel at the start of the stream.
+    pre_skip: usize,
+    pcm: Vec<f32>,
+}
+
+impl OpusTrack {
+    fn new(params: &CodecParameters) -> Result<Self> {
+        let channels = params.channels.map(|c| c.count()).unwrap_or(1);
+        if !(1..=2).contains(&channels) {
+            return Err(anyhow!("Opus streams with {} channels are not supported", channels));
+        }
+        let decoder = opus_decoder::OpusDecoder::new(OPUS_SAMPLE_RATE, channels)
+            .map_err(|e| anyhow!("Failed to create Opus decoder: {}", e))?;
+
+        Ok(OpusTrack {
+            decoder,
+            channels,
+            pre_skip: params.delay.unwrap_or(0) as usize,
+            pcm: vec![0.0; OPUS_MAX_FRAME * channels],
+        })
+    }
+
+    // Decodes one packet onto `kept`. Corrupt packets are skipped, like
+    // symphonia's DecodeError above.
+    fn decode(&mut self, packet: &[u8], kept: &mut WindowedSamples) -> Result<()> {
+        match self.decoder.decode_float(packet, &mut self.pcm, false) {
+            Ok(frames) => {
+                kept.extend(&self.pcm[..frames * self.channels], self.channels, OPUS_SAMPLE_RATE);
+                Ok(())
+            }
+            Err(opus_decoder::OpusError::InvalidPacket) => Ok(()),
+            Err(e) => Err(anyhow!("Failed to decode Opus packet: {}", e)),
+        }
+    }
+}
+
+// Mono samples as a 16-bit WAV file.
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut buffer, spec)
+        .map_err(|e| anyhow!("Failed to write WAV header: {}", e))?;
+    for &sample in samples {
+        writer
+            .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .map_err(|e| anyhow!("Failed to write WAV samples: {}", e))?;
+    }
+    writer
+        .finalize()
+        .map_err(|e| anyhow!("Failed to finish WAV file: {}", e))?;
+
+    Ok(buffer.into_inner())
+}
+
+// The band HashParams::band_pass keeps, in Hz. Phone microphones add rumble
+// below and hiss above the range where melodies and their harmonics sit,
+// and peaks there are the least stable between captures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandPass {
+    pub low_hz: u32,
+    pub high_hz: u32,
+}
+
+// A second-order Butterworth high-pass at `low_hz` followed by a low-pass at
+// `high_hz`. Either edge is skipped when it is 0 or at or above the Nyquist
+// frequency.
+pub fn band_pass(samples: &[f32], sample_rate: u32, band: BandPass) -> Vec<f32> {
+    let mut filtered = samples.to_vec();
+    BandPassFilter::new(sample_rate, band).process(&mut filtered);
+    filtered
+}
+
+// Streaming form of `band_pass`: the filters keep their state between calls,
+// so audio filtered chunk by chunk comes out as if filtered in one piece.
+pub struct BandPassFilter {
+    stages: Vec<(Biquad, BiquadState)>,
+}
+
+impl BandPassFilter {
+    pub fn new(sample_rate: u32, band: BandPass) -> Self {
+        let nyquist = sample_rate / 2;
+        let mut stages = Vec::new();
+
+        if band.low_hz > 0 && band.low_hz < nyquist {
+            stages.push((Biquad::new(FilterKind::HighPass, band.low_hz, sample_rate), BiquadState::default()));
+        }
+        if band.high_hz > 0 && band.high_hz < nyquist {
+            stages.push((Biquad::new(FilterKind::LowPass, band.high_hz, sample_rate), BiquadState::default()));
+        }
+
+        BandPassFilter { stages }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for (filter, state) in &mut self.stages {
+            filter.process(state, samples);
+        }
+    }
+}
+
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+// The last two inputs and outputs of a Biquad.
+#[derive(Default)]
+struct BiquadState {
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+// Direct form I biquad with RBJ cookbook coefficients.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn new(kind: FilterKind, cutoff_hz: u32, sample_rate: u32) -> Self {
+        let w0 = 2.0 * PI * cutoff_hz as f64 / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+
+        let b = match kind {
+            FilterKind::HighPass => [(1.0 + cos_w0) / 2.0, -(1.0 + cos_w0), (1.0 + cos_w0) / 2.0],
+            FilterKind::LowPass => [(1.0 - cos_w0) / 2.0, 1.0 - cos_w0, (1.0 - cos_w0) / 2.0],
+        };
+
+        Biquad {
+            b: b.map(|coefficient| coefficient / a0),
+            a: [-2.0 * cos_w0 / a0, (1.0 - alpha) / a0],
+        }
+    }
+
+    fn process(&self, state: &mut BiquadState, samples: &mut [f32]) {
+        let BiquadState { x1, x2, y1, y2 } = state;
+        for sample in samples.iter_mut() {
+            let x0 = *sample as f64;
+            let y0 = self.b[0] * x0 + self.b[1] * *x1 + self.b[2] * *x2 - self.a[0] * *y1 - self.a[1] * *y2;
+            *x2 = *x1;
+            *x1 = x0;
+            *y2 = *y1;
+            *y1 = y0;
+            *sample = y0 as f32;
         }
     }
 }
@@ -40,7 +1283,439 @@ pub fn normalize_audio(samples: &[f32]) -> Vec<f32> {
     samples.iter().map(|&s| s / max_amplitude).collect()
 }
 
-pub fn downsample(samples: &[f32], original_rate: u32, target_rate: u32) -> Vec<f32> {
+// What to drop from a query clip before fingerprinting. Phone recordings
+// often open with seconds of near-silence, which yields no peaks but still
+// counts against similarity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SilenceMode {
+    #[default]
+    Off,
+    // Leading and trailing silence.
+    Trim,
+    // Trim, plus silent stretches inside the clip.
+    Gate,
+}
+
+// `samples` without the silence `mode` drops. A frame is silent when its RMS
+// level is below the configured threshold. Gating keeps silent stretches
+// shorter than `min_gap_ms`, so rests between notes survive. A clip that is
+// silent throughout comes back unchanged.
+pub fn remove_silence<'a>(
+    samples: &'a [f32],
+    sample_rate: u32,
+    mode: SilenceMode,
+    config: &SilenceConfig,
+) -> Cow<'a, [f32]> {
+    if mode == SilenceMode::Off {
+        return Cow::Borrowed(samples);
+    }
+
+    let frame_len = ((sample_rate as u64 * config.frame_ms as u64 / 1000) as usize).max(1);
+    let threshold = 10f32.powf(config.threshold_db / 20.0);
+    let audible: Vec<bool> = samples
+        .chunks(frame_len)
+        .map(|frame| frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32 >= threshold * threshold)
+        .collect();
+
+    let (Some(first), Some(last)) = (audible.iter().position(|&a| a), audible.iter().rposition(|&a| a)) else {
+        return Cow::Borrowed(samples);
+    };
+    let frame_range = |from: usize, to: usize| &samples[from * frame_len..(to * frame_len).min(samples.len())];
+
+    if mode == SilenceMode::Trim {
+        return Cow::Borrowed(frame_range(first, last + 1));
+    }
+
+    let min_gap_frames = (config.min_gap_ms as usize * sample_rate as usize).div_ceil(1000 * frame_len);
+    let mut kept = Vec::with_capacity(frame_range(first, last + 1).len());
+    let mut frame = first;
+    while frame <= last {
+        let run_end = (frame..=last).find(|&f| audible[f] != audible[frame]).unwrap_or(last + 1);
+        if audible[frame] || run_end - frame < min_gap_frames {
+            kept.extend_from_slice(frame_range(frame, run_end));
+        }
+        frame = run_end;
+    }
+
+    Cow::Owned(kept)
+}
+
+// Spectral subtraction: the noise spectrum is averaged over the quietest
+// frames, which in a far-field recording hold mostly room tone and hum, and
+// subtracted from every frame's magnitudes while its phase is kept. Frames
+// are periodic-Hann windowed at 50% overlap, which sums back to unity gain.
+pub fn denoise<'a>(samples: &'a [f32], sample_rate: u32, enabled: bool, config: &DenoiseConfig) -> Cow<'a, [f32]> {
+    let frame_len = ((sample_rate as u64 * config.frame_ms as u64 / 1000) as usize).next_power_of_two().max(2);
+    if !enabled || samples.len() < frame_len {
+        return Cow::Borrowed(samples);
+    }
+
+    let hop = frame_len / 2;
+    let window: Vec<f32> = (0..frame_len)
+        .map(|i| (0.5 * (1.0 - (2.0 * PI * i as f64 / frame_len as f64).cos())) as f32)
+        .collect();
+    // Padding by a hop at each end lets the first and last samples be
+    // covered by two frames like every other sample.
+    let frames = samples.len().div_ceil(hop) + 1;
+    let mut padded = vec![0.0; hop];
+    padded.extend_from_slice(samples);
+    padded.resize((frames + 1) * hop, 0.0);
+
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(frame_len);
+    let inverse = planner.plan_fft_inverse(frame_len);
+    let spectrum = |frame: usize| {
+        let mut buffer: Vec<Complex<f32>> = padded[frame * hop..frame * hop + frame_len]
+            .iter()
+            .zip(&window)
+            .map(|(&s, &w)| Complex::new(s * w, 0.0))
+            .collect();
+        forward.process(&mut buffer);
+        buffer
+    };
+
+    // Frames that reach into the padding would look quieter than the noise.
+    let interior = 1..samples.len() / hop;
+    let mut quietest: Vec<(usize, f32)> = interior
+        .map(|frame| {
+            let energy = padded[frame * hop..frame * hop + frame_len]
+                .iter()
+                .zip(&window)
+                .map(|(s, w)| (s * w) * (s * w))
+                .sum();
+            (frame, energy)
+        })
+        .collect();
+    quietest.sort_by(|a, b| a.1.total_cmp(&b.1));
+    quietest.truncate(((quietest.len() as f32 * config.noise_fraction).ceil() as usize).max(1));
+
+    let mut noise = vec![0.0f32; frame_len];
+    for &(frame, _) in &quietest {
+        for (level, bin) in noise.iter_mut().zip(spectrum(frame)) {
+            *level += bin.norm() / quietest.len() as f32;
+        }
+    }
+
+    let mut output = vec![0.0f32; padded.len()];
+    for frame in 0..frames {
+        let mut buffer = spectrum(frame);
+        for (bin, &level) in buffer.iter_mut().zip(&noise) {
+            let magnitude = bin.norm();
+            if magnitude > 0.0 {
+                let cleaned = (magnitude - config.over_subtraction * level).max(config.floor * magnitude);
+                *bin *= cleaned / magnitude;
+            }
+        }
+        inverse.process(&mut buffer);
+        for (out, bin) in output[frame * hop..frame * hop + frame_len].iter_mut().zip(&buffer) {
+            *out += bin.re / frame_len as f32;
+        }
+    }
+
+    output.drain(..hop);
+    output.truncate(samples.len());
+    Cow::Owned(output)
+}
+
+// How usable a recording is as a catalog reference, for ingest reports.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SignalQuality {
+    // 0 to 1, the product of the three factors below. Files under about 0.5
+    // are worth a listen before matches against them are trusted.
+    pub score: f64,
+    // Share of samples at full scale.
+    pub clipped_fraction: f64,
+    // Share of frames under the silence threshold (SilenceConfig).
+    pub silent_fraction: f64,
+}
+
+const CLIPPED_LEVEL: f32 = 0.999;
+// Clipping on this share of samples or more scores 0.
+const MAX_CLIPPED_FRACTION: f64 = 0.01;
+// Hash count from which a fingerprint is considered dense enough to match
+// reliably; fewer hashes lower the score proportionally.
+const GOOD_HASH_COUNT: usize = 100;
+
+pub fn assess_quality(samples: &[f32], sample_rate: u32, hash_count: usize, silence: &SilenceConfig) -> SignalQuality {
+    if samples.is_empty() {
+        return SignalQuality { score: 0.0, clipped_fraction: 0.0, silent_fraction: 1.0 };
+    }
+
+    let clipped = samples.iter().filter(|s| s.abs() >= CLIPPED_LEVEL).count();
+    let clipped_fraction = clipped as f64 / samples.len() as f64;
+
+    let frame_len = ((sample_rate as u64 * silence.frame_ms as u64 / 1000) as usize).max(1);
+    let threshold = 10f32.powf(silence.threshold_db / 20.0);
+    let frames = samples.len().div_ceil(frame_len);
+    let silent = samples
+        .chunks(frame_len)
+        .filter(|frame| frame.iter().map(|s| s * s).sum::<f32>() / (frame.len() as f32) < threshold * threshold)
+        .count();
+    let silent_fraction = silent as f64 / frames as f64;
+
+    let score = (1.0 - silent_fraction)
+        * (1.0 - (clipped_fraction / MAX_CLIPPED_FRACTION).min(1.0))
+        * (hash_count as f64 / GOOD_HASH_COUNT as f64).min(1.0);
+
+    SignalQuality { score, clipped_fraction, silent_fraction }
+}
+
+// How audio is brought to the fingerprinter's analysis rate. Each mode
+// gives slightly different hashes for the same recording, so it is part of
+// HashParams, but the analysis band is the same, so fingerprints made with
+// different modes can still be matched against each other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResampleQuality {
+    // Nearest input sample. Cheapest, but content above the new Nyquist
+    // frequency folds back into the fingerprint band.
+    Fast,
+    // Interpolates between the two nearest input samples. Barely slower than
+    // Fast, and averaging the neighbours takes the edge off the aliasing.
+    Linear,
+    // Windowed-sinc low-pass with 8 zero crossings on each side.
+    #[default]
+    Balanced,
+    // 32 zero crossings: a steeper cutoff, at about twice the cost.
+    High,
+}
+
+impl ResampleQuality {
+    pub const ALL: [ResampleQuality; 4] = [
+        ResampleQuality::Fast,
+        ResampleQuality::Linear,
+        ResampleQuality::Balanced,
+        ResampleQuality::High,
+    ];
+}
+
+// Passband edge, as a fraction of the lower rate's Nyquist frequency.
+const RESAMPLE_ROLLOFF: f64 = 0.95;
+// Kernel table entries per input sample.
+const KERNEL_STEPS: usize = 512;
+
+// Converts `samples` from `from_rate` to `to_rate`. The sinc modes filter out
+// everything the lower of the two rates can't represent, so bright material
+// doesn't alias. Written out here rather than taken from a crate so the
+// output, and with it every stored fingerprint, can't change with a
+// dependency upgrade.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32, quality: ResampleQuality) -> Vec<f32> {
+    if from_rate == to_rate || from_rate == 0 || to_rate == 0 || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let zero_crossings = match quality {
+        ResampleQuality::Fast if from_rate > to_rate => return decimate(samples, from_rate, to_rate),
+        // Upsampling has no samples to drop, so fast interpolates as well.
+        ResampleQuality::Fast | ResampleQuality::Linear => return interpolate(samples, from_rate, to_rate),
+        ResampleQuality::Balanced => 8,
+        ResampleQuality::High => 32,
+    };
+
+    let ratio = from_rate as f64 / to_rate as f64;
+    let (half_width, kernel) = sinc_filter(from_rate, to_rate, zero_crossings);
+    let out_len = (samples.len() as f64 / ratio) as usize;
+
+    (0..out_len).map(|i| sinc_sample(samples, 0, i as f64 * ratio, half_width, &kernel)).collect()
+}
+
+// Half width in input samples and kernel of the sinc low-pass for a
+// conversion between the two rates.
+fn sinc_filter(from_rate: u32, to_rate: u32, zero_crossings: u32) -> (f64, Vec<f32>) {
+    // Cutoff in cycles per input sample.
+    let cutoff = 0.5 * RESAMPLE_ROLLOFF * (to_rate as f64 / from_rate as f64).min(1.0);
+    let half_width = zero_crossings as f64 / (2.0 * cutoff);
+    (half_width, sinc_kernel(cutoff, half_width))
+}
+
+// The output sample centred on input position `center`. `samples` holds the
+// input from position `offset` on, up to the end of the input received so
+// far, and reaches back to the first sample the kernel covers.
+fn sinc_sample(samples: &[f32], offset: usize, center: f64, half_width: f64, kernel: &[f32]) -> f32 {
+    let first = (center - half_width).ceil().max(0.0) as usize;
+    let last = ((center + half_width).floor() as usize).min(offset + samples.len() - 1);
+    // Consecutive input samples are exactly KERNEL_STEPS table entries
+    // apart, so only the first position needs rounding.
+    let start = ((center - first as f64 + half_width) * KERNEL_STEPS as f64).round() as usize;
+    samples[first - offset..=last - offset]
+        .iter()
+        .zip(kernel[..=start.min(kernel.len() - 1)].iter().rev().step_by(KERNEL_STEPS))
+        .map(|(&sample, &weight)| sample * weight)
+        .sum()
+}
+
+// Streaming form of `resample`, for audio that arrives in chunks. Each output
+// sample is produced once all the input it depends on has arrived, and equals
+// what `resample` computes from the whole input. Input no later output needs
+// is dropped, so memory stays bounded however long the stream runs.
+pub struct Resampler {
+    method: ResampleMethod,
+    ratio: f64,
+    // Input from position `offset` on.
+    input: Vec<f32>,
+    offset: usize,
+    received: usize,
+    // Index of the next output sample.
+    next: usize,
+}
+
+enum ResampleMethod {
+    Copy,
+    Decimate,
+    Interpolate,
+    Sinc { half_width: f64, kernel: Vec<f32> },
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32, quality: ResampleQuality) -> Self {
+        let method = if from_rate == to_rate || from_rate == 0 || to_rate == 0 {
+            ResampleMethod::Copy
+        } else {
+            match quality {
+                ResampleQuality::Fast if from_rate > to_rate => ResampleMethod::Decimate,
+                ResampleQuality::Fast | ResampleQuality::Linear => ResampleMethod::Interpolate,
+                ResampleQuality::Balanced | ResampleQuality::High => {
+                    let zero_crossings = if quality == ResampleQuality::High { 32 } else { 8 };
+                    let (half_width, kernel) = sinc_filter(from_rate, to_rate, zero_crossings);
+                    ResampleMethod::Sinc { half_width, kernel }
+                }
+            }
+        };
+
+        Resampler {
+            method,
+            ratio: from_rate as f64 / to_rate.max(1) as f64,
+            input: Vec::new(),
+            offset: 0,
+            received: 0,
+            next: 0,
+        }
+    }
+
+    // The output samples `samples` completes.
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        if let ResampleMethod::Copy = self.method {
+            return samples.to_vec();
+        }
+        self.input.extend_from_slice(samples);
+        self.received += samples.len();
+        self.produce(false)
+    }
+
+    // The output samples left once the input has ended.
+    pub fn finish(&mut self) -> Vec<f32> {
+        match self.method {
+            ResampleMethod::Copy => Vec::new(),
+            _ => self.produce(true),
+        }
+    }
+
+    fn produce(&mut self, ended: bool) -> Vec<f32> {
+        let mut output = Vec::new();
+        if self.received == 0 {
+            return output;
+        }
+        let ratio = self.ratio;
+
+        loop {
+            let i = self.next;
+            // Same output length, and near the end the same clamping, as
+            // `resample` has for the input received so far.
+            let sample = match &self.method {
+                ResampleMethod::Copy => unreachable!("copying needs no state"),
+                ResampleMethod::Decimate => {
+                    let ratio = ratio as f32;
+                    let index = (i as f32 * ratio) as usize;
+                    if i >= (self.received as f32 / ratio) as usize || (!ended && index >= self.received) {
+                        break;
+                    }
+                    self.input.get(index - self.offset).copied().unwrap_or(0.0)
+                }
+                ResampleMethod::Interpolate => {
+                    let position = i as f64 * ratio;
+                    let index = position as usize;
+                    if i >= (self.received as f64 / ratio) as usize || (!ended && index + 1 >= self.received) {
+                        break;
+                    }
+                    let fraction = (position - index as f64) as f32;
+                    let end = self.received - 1;
+                    let current = self.input[index.min(end) - self.offset];
+                    let next = self.input[(index + 1).min(end) - self.offset];
+                    current + (next - current) * fraction
+                }
+                ResampleMethod::Sinc { half_width, kernel } => {
+                    let center = i as f64 * ratio;
+                    if i >= (self.received as f64 / ratio) as usize
+                        || (!ended && (center + half_width).floor() as usize >= self.received)
+                    {
+                        break;
+                    }
+                    sinc_sample(&self.input, self.offset, center, *half_width, kernel)
+                }
+            };
+            output.push(sample);
+            self.next += 1;
+        }
+
+        self.drop_consumed();
+        output
+    }
+
+    // Drops the input before the first sample the next output reads.
+    fn drop_consumed(&mut self) {
+        let position = self.next as f64 * self.ratio;
+        let first = match &self.method {
+            ResampleMethod::Copy => return,
+            ResampleMethod::Decimate => (self.next as f32 * self.ratio as f32) as usize,
+            ResampleMethod::Interpolate => position as usize,
+            ResampleMethod::Sinc { half_width, .. } => (position - half_width).ceil().max(0.0) as usize,
+        };
+        let drop = first.min(self.received).saturating_sub(self.offset);
+        self.input.drain(..drop);
+        self.offset += drop;
+    }
+}
+
+// A Blackman-windowed sinc low-pass from -half_width to +half_width input
+// samples, sampled KERNEL_STEPS times per input sample. Scaled for unity
+// gain at DC.
+fn sinc_kernel(cutoff: f64, half_width: f64) -> Vec<f32> {
+    let len = (2.0 * half_width * KERNEL_STEPS as f64).ceil() as usize + 1;
+
+    (0..len)
+        .map(|step| {
+            let distance = (step as f64 / KERNEL_STEPS as f64 - half_width).abs();
+            if distance > half_width {
+                return 0.0;
+            }
+            let x = 2.0 * cutoff * distance;
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let position = distance / half_width;
+            let window = 0.42 + 0.5 * (PI * position).cos() + 0.08 * (2.0 * PI * position).cos();
+            (2.0 * cutoff * sinc * window) as f32
+        })
+        .collect()
+}
+
+// Linear interpolation, what ResampleQuality::Linear uses.
+fn interpolate(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    let ratio = from_rate as f64 / to_rate as f64;
+    let out_len = (samples.len() as f64 / ratio) as usize;
+
+    (0..out_len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index.min(samples.len() - 1)];
+            let next = samples[(index + 1).min(samples.len() - 1)];
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+// Nearest-sample downsampling, what ResampleQuality::Fast uses.
+fn decimate(samples: &[f32], original_rate: u32, target_rate: u32) -> Vec<f32> {
     if original_rate <= target_rate {
         return samples.to_vec();
     }
@@ -0,0 +1,68 @@
+use anyhow::{anyhow, Result};
+use std::io::{Cursor, Read};
+
+use crate::config::BatchConfig;
+
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// Extracts the file entries of a ZIP archive in central-directory order.
+//
+// Entry names that would escape the archive root are rejected, and sizes are
+// enforced on the bytes actually inflated rather than on the sizes declared in
+// the headers, so a crafted archive can't lie its way past the limits.
+pub fn extract_zip(archive_data: &[u8], config: &BatchConfig) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive_data))
+        .map_err(|e| anyhow!("Failed to read ZIP archive: {}", e))?;
+
+    if archive.len() > config.max_entries {
+        return Err(anyhow!(
+            "ZIP archive has {} entries, limit is {}",
+            archive.len(),
+            config.max_entries
+        ));
+    }
+
+    let mut entries = Vec::new();
+    let mut total_bytes = 0u64;
+
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index(index)
+            .map_err(|e| anyhow!("Failed to read ZIP entry {}: {}", index, e))?;
+
+        if entry.is_dir() {
+            continue;
+        }
+
+        let name = entry
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("ZIP entry '{}' has an unsafe path", entry.name()))?
+            .to_string_lossy()
+            .into_owned();
+
+        let compressed_size = entry.compressed_size().max(1);
+        let limit = config.max_entry_bytes.min(config.max_total_bytes - total_bytes);
+
+        let mut data = Vec::new();
+        entry
+            .take(limit + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| anyhow!("Failed to extract ZIP entry '{}': {}", name, e))?;
+
+        let size = data.len() as u64;
+        if size > limit {
+            return Err(anyhow!("ZIP entry '{}' exceeds the extraction size limit", name));
+        }
+        if size / compressed_size > config.max_compression_ratio {
+            return Err(anyhow!("ZIP entry '{}' has a suspicious compression ratio", name));
+        }
+
+        total_bytes += size;
+        entries.push(ArchiveEntry { name, data });
+    }
+
+    Ok(entries)
+}
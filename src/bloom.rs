@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+// A fixed-size Bloom filter over fingerprint hashes. It can answer "definitely
+// not indexed" without touching SQLite; a positive only means "maybe".
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hash_count: u32,
+    capacity: usize,
+    // Insertions that set at least one new bit. A hash whose bits were all
+    // set already (a duplicate, or a false positive) adds nothing to the
+    // fill, so it doesn't count towards saturation.
+    inserted: usize,
+}
+
+impl BloomFilter {
+    // Sized so that `capacity` insertions give roughly `false_positive_rate`.
+    pub fn with_capacity(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hash_count = ((bit_count / capacity as f64) * ln2).round().clamp(1.0, 16.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; (bit_count as usize).div_ceil(64)],
+            hash_count,
+            capacity,
+            inserted: 0,
+        }
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        let mut added = false;
+        for bit in self.bit_indexes(hash) {
+            let word = &mut self.bits[bit / 64];
+            added |= *word & (1 << (bit % 64)) == 0;
+            *word |= 1 << (bit % 64);
+        }
+        if added {
+            self.inserted += 1;
+        }
+    }
+
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.bit_indexes(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // More insertions than the filter was sized for: it still never gives a
+    // false negative, but false positives climb and it should be rebuilt.
+    pub fn is_saturated(&self) -> bool {
+        self.inserted > self.capacity
+    }
+
+    // Double hashing (Kirsch–Mitzenmacher) over two halves of a 64-bit mix.
+    fn bit_indexes(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let mixed = splitmix64(hash);
+        let h1 = mixed & 0xFFFF_FFFF;
+        let h2 = (mixed >> 32) | 1;
+        let bit_count = self.bits.len() as u64 * 64;
+
+        (0..self.hash_count as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+}
+
+// The catalog's filter together with what a rebuild from committed postings
+// would miss: the hashes of writes whose transactions are still open, and
+// anything inserted while the rebuild's query runs. Each write registers its
+// hashes before committing and finishes once the commit has landed, so a
+// swapped-in filter never drops a song that is about to become findable.
+#[derive(Debug)]
+pub struct HashFilter {
+    filter: Option<BloomFilter>,
+    min_capacity: usize,
+    false_positive_rate: f64,
+    in_flight: HashMap<u64, Vec<u64>>,
+    next_write: u64,
+    // Some while a rebuild is running: seeded with the in-flight hashes when
+    // it starts, then every hash inserted until it finishes.
+    rebuild_log: Option<Vec<u64>>,
+}
+
+impl HashFilter {
+    // Empty until the first rebuild; rebuilt filters are sized for twice the
+    // hashes they start with, and at least `min_capacity`.
+    pub fn new(min_capacity: usize, false_positive_rate: f64) -> Self {
+        HashFilter {
+            filter: None,
+            min_capacity,
+            false_positive_rate,
+            in_flight: HashMap::new(),
+            next_write: 0,
+            rebuild_log: None,
+        }
+    }
+
+    // True until the first rebuild, since nothing can be ruled out yet.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.may_contain(hash))
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.filter.as_ref().is_some_and(BloomFilter::is_saturated)
+    }
+
+    // Adds a write's hashes ahead of its commit. They stay covered across any
+    // rebuild until `finish_write` is called with the returned id.
+    pub fn begin_write(&mut self, hashes: &[u64]) -> u64 {
+        if let Some(filter) = self.filter.as_mut() {
+            for &hash in hashes {
+                filter.insert(hash);
+            }
+        }
+        if let Some(log) = self.rebuild_log.as_mut() {
+            log.extend_from_slice(hashes);
+        }
+
+        let id = self.next_write;
+        self.next_write += 1;
+        self.in_flight.insert(id, hashes.to_vec());
+        id
+    }
+
+    pub fn finish_write(&mut self, id: u64) {
+        self.in_flight.remove(&id);
+    }
+
+    // Call before reading the committed hashes. Rebuilds must not overlap.
+    pub fn begin_rebuild(&mut self) {
+        self.rebuild_log = Some(self.in_flight.values().flatten().copied().collect());
+    }
+
+    // Leaves the current filter in place; it already holds everything logged.
+    pub fn abort_rebuild(&mut self) {
+        self.rebuild_log = None;
+    }
+
+    // Swaps in a filter over `committed` plus everything begin_rebuild and
+    // later writes logged, whether or not those writes have committed since.
+    pub fn finish_rebuild(&mut self, committed: &[u64]) {
+        let logged = self.rebuild_log.take().unwrap_or_default();
+
+        let mut filter = BloomFilter::with_capacity(
+            (committed.len() * 2).max(self.min_capacity),
+            self.false_positive_rate,
+        );
+        for &hash in committed.iter().chain(&logged) {
+            filter.insert(hash);
+        }
+        self.filter = Some(filter);
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
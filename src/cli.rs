@@ -0,0 +1,253 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::database::{Database, MatchRate};
+use crate::evaluation::{self, EvaluationReport};
+
+#[derive(Parser)]
+#[command(name = "music-matcher", about = "Audio fingerprinting and matching service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP service (the default)
+    Serve {
+        /// Verify the hash index before serving and repair any inconsistencies
+        #[arg(long)]
+        repair_on_start: bool,
+    },
+    /// Print catalog and match statistics straight from the database
+    Stats,
+    /// Check stored fingerprints and the hash index for consistency
+    Verify {
+        /// Fix orphaned and mismatched index entries
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Run a labeled query corpus against the catalog and report accuracy
+    Evaluate {
+        /// CSV manifest of `query,expected_song_id` rows; leave the id empty
+        /// for queries that shouldn't match
+        #[arg(long)]
+        corpus: PathBuf,
+        /// Print the full report, including per-query outcomes, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send synthesized clips to a running service's /match endpoint and
+    /// report latency percentiles and error rates
+    #[cfg(feature = "loadtest")]
+    Loadtest {
+        /// Base URL of the service, e.g. http://localhost:3000
+        #[arg(long)]
+        target: String,
+        /// Requests per second to send
+        #[arg(long, default_value_t = 10)]
+        rps: u32,
+        /// Length of each synthesized clip in seconds
+        #[arg(long, default_value_t = 10.0)]
+        clip_len: f32,
+        /// How long to keep sending, in seconds
+        #[arg(long, default_value_t = 30)]
+        duration: u64,
+        /// Requests allowed to be outstanding before new ones are skipped
+        #[arg(long, default_value_t = 256)]
+        max_in_flight: usize,
+    },
+}
+
+pub async fn run_stats(db: &Database) -> Result<()> {
+    let stats = db.catalog_stats(10).await?;
+
+    println!("Songs:            {}", stats.songs);
+    println!("Index postings:   {}", stats.postings);
+    println!("Distinct hashes:  {}", stats.distinct_hashes);
+    println!("Database size:    {}", format_bytes(stats.database_bytes));
+    println!("Matches (24h):    {}", format_rate(&stats.matches_24h));
+    println!("Matches (7d):     {}", format_rate(&stats.matches_7d));
+
+    if !stats.top_artists.is_empty() {
+        println!();
+        println!("Top artists:");
+        for (artist, songs) in &stats.top_artists {
+            println!("  {:>6}  {}", songs, artist);
+        }
+    }
+
+    Ok(())
+}
+
+// Exits non-zero when problems remain, so it can gate scripts and cron jobs.
+pub async fn run_verify(db: &Database, repair: bool) -> Result<()> {
+    let report = db.verify_catalog().await?;
+
+    println!("Songs checked:        {}", report.songs_checked);
+    println!("Unparsable:           {}", report.unparsable.len());
+    println!("Index mismatches:     {}", report.mismatched.len());
+    println!("Orphaned postings:    {}", report.orphaned_postings);
+
+    for (song_id, error) in &report.unparsable {
+        println!("  song {}: unparsable fingerprint: {}", song_id, error);
+    }
+    for song_id in &report.mismatched {
+        println!("  song {}: index postings don't match fingerprint", song_id);
+    }
+
+    if report.is_consistent() {
+        println!("Catalog is consistent.");
+        return Ok(());
+    }
+
+    if !repair {
+        println!("Run with --repair to fix index problems.");
+        std::process::exit(1);
+    }
+
+    db.repair_catalog(&report).await?;
+    println!(
+        "Repaired: removed {} orphaned postings, rebuilt {} songs.",
+        report.orphaned_postings,
+        report.mismatched.len()
+    );
+
+    if !report.unparsable.is_empty() {
+        println!(
+            "{} songs with unparsable fingerprints were removed from the index; re-ingest or delete them.",
+            report.unparsable.len()
+        );
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+pub async fn run_evaluate(db: &Database, config: &Config, corpus: &Path, json: bool) -> Result<()> {
+    let queries = evaluation::load_manifest(corpus)?;
+    let report = evaluation::evaluate(db, config, &queries).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_evaluation(&report);
+    }
+
+    Ok(())
+}
+
+fn print_evaluation(report: &EvaluationReport) {
+    println!("Queries:          {} ({} positive, {} negative)", report.queries, report.positives, report.negatives);
+    println!("Errors:           {}", report.errors);
+    println!("Correct:          {}", report.correct);
+    println!("Wrong:            {}", report.wrong);
+    println!("Missed:           {}", report.missed);
+    println!("Precision:        {:.3}", report.precision);
+    println!("Recall:           {:.3}", report.recall);
+
+    println!();
+    println!("Threshold  Accepted  Precision  Recall");
+    for point in &report.curve {
+        println!(
+            "{:>9.2}  {:>8}  {:>9.3}  {:>6.3}",
+            point.threshold, point.accepted, point.precision, point.recall
+        );
+    }
+
+    println!();
+    println!("Confidence  Predictions  Correct");
+    for bucket in &report.calibration {
+        println!(
+            "{:>9.2}+  {:>11}  {:>7}",
+            bucket.min_confidence, bucket.predictions, bucket.correct
+        );
+    }
+
+    println!();
+    println!("Tier       Predictions  Correct  Precision");
+    for bucket in &report.tiers {
+        println!(
+            "{:<9}  {:>11}  {:>7}  {:>9.3}",
+            bucket.tier.as_str(),
+            bucket.predictions,
+            bucket.correct,
+            bucket.precision
+        );
+    }
+
+    for outcome in &report.outcomes {
+        if let Some(error) = &outcome.error {
+            println!("  {}: {}", outcome.path, error);
+        }
+    }
+}
+
+#[cfg(feature = "loadtest")]
+pub async fn run_loadtest(options: &crate::loadtest::LoadTestOptions) -> Result<()> {
+    println!(
+        "Sending {} req/s of {:.0}s clips to {} for {}s...",
+        options.rps, options.clip_secs, options.target, options.duration_secs
+    );
+    let report = crate::loadtest::run(options).await?;
+
+    println!(
+        "Sent:             {} ({:.1} req/s)",
+        report.sent,
+        report.sent as f64 / report.sending.as_secs_f64()
+    );
+    println!("Elapsed:          {:.1}s (until the last response)", report.elapsed.as_secs_f64());
+    println!("Succeeded:        {}", report.succeeded);
+    println!(
+        "Errors:           {} ({:.2}%)",
+        report.errors(),
+        report.errors() as f64 * 100.0 / report.sent.max(1) as f64
+    );
+    for (status, count) in &report.failed {
+        println!("  HTTP {}:        {}", status, count);
+    }
+    if report.transport_errors > 0 {
+        println!("  transport:       {}", report.transport_errors);
+    }
+    if report.skipped > 0 {
+        println!("Skipped:          {} (max in flight reached)", report.skipped);
+    }
+
+    println!();
+    for (label, p) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)] {
+        match report.percentile(p) {
+            Some(latency) => println!("Latency {}:      {:.1} ms", label, latency.as_secs_f64() * 1000.0),
+            None => println!("Latency {}:      -", label),
+        }
+    }
+
+    Ok(())
+}
+
+fn format_rate(rate: &MatchRate) -> String {
+    if rate.requests == 0 {
+        return "no requests".to_string();
+    }
+
+    format!(
+        "{} of {} requests matched ({:.1}%)",
+        rate.matched,
+        rate.requests,
+        rate.matched as f64 * 100.0 / rate.requests as f64
+    )
+}
+
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
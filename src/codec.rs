@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+
+use crate::fingerprint::{AudioFingerprint, HashParams};
+
+// Binary form of a fingerprint, as stored in fingerprint_data columns:
+//
+//   "MMFP", codec version (1 byte)
+//   hash format (varint), duration (f64, little-endian)
+//   hashing settings: length (varint) and their JSON, which keeps serde's
+//     defaults for settings added later
+//   hash count (varint), flags (1 byte: 1 = offsets, 2 = weights)
+//   hashes (varint each)
+//   offsets, if flagged (zigzag varint difference from the previous offset)
+//   weights, if flagged (1 byte each)
+//
+// Hashes keep their order, so a fingerprint decodes to exactly what was
+// encoded. Offsets of consecutive hashes are close, so most deltas take one
+// byte; the whole is about half the size of the JSON.
+const MAGIC: &[u8; 4] = b"MMFP";
+const CODEC_VERSION: u8 = 1;
+const HAS_OFFSETS: u8 = 1;
+const HAS_WEIGHTS: u8 = 2;
+
+pub fn encode(fingerprint: &AudioFingerprint) -> Vec<u8> {
+    let params = serde_json::to_vec(&fingerprint.params).unwrap_or_default();
+    let count = fingerprint.hashes.len();
+    let has_offsets = fingerprint.has_offsets();
+    let has_weights = fingerprint.has_weights();
+
+    let mut out = Vec::with_capacity(32 + params.len() + count * 6);
+    out.extend_from_slice(MAGIC);
+    out.push(CODEC_VERSION);
+    put_varint(&mut out, fingerprint.version as u64);
+    out.extend_from_slice(&fingerprint.duration.to_le_bytes());
+    put_varint(&mut out, params.len() as u64);
+    out.extend_from_slice(&params);
+    put_varint(&mut out, count as u64);
+    out.push(if has_offsets { HAS_OFFSETS } else { 0 } | if has_weights { HAS_WEIGHTS } else { 0 });
+
+    for &hash in &fingerprint.hashes {
+        put_varint(&mut out, hash);
+    }
+    if has_offsets {
+        let mut previous = 0i64;
+        for &offset in &fingerprint.offsets {
+            put_varint(&mut out, zigzag(offset as i64 - previous));
+            previous = offset as i64;
+        }
+    }
+    if has_weights {
+        out.extend_from_slice(&fingerprint.weights);
+    }
+
+    out
+}
+
+// Reads either form a fingerprint may be stored in: binary, or the JSON
+// that rows written before the binary form still hold.
+pub fn decode(data: &[u8]) -> Result<AudioFingerprint> {
+    if !data.starts_with(MAGIC) {
+        return serde_json::from_slice(data).map_err(|e| anyhow!("Failed to parse fingerprint JSON: {}", e));
+    }
+
+    let mut reader = Reader { data, pos: MAGIC.len() };
+    let codec_version = reader.byte()?;
+    if codec_version != CODEC_VERSION {
+        return Err(anyhow!("Unknown fingerprint codec version {}", codec_version));
+    }
+
+    let version = u32::try_from(reader.varint()?).map_err(|_| anyhow!("Fingerprint hash format out of range"))?;
+    let duration = f64::from_le_bytes(reader.take(8)?.try_into().unwrap_or_default());
+    let params_len = reader.length()?;
+    let params: HashParams = serde_json::from_slice(reader.take(params_len)?)
+        .map_err(|e| anyhow!("Failed to parse fingerprint hashing settings: {}", e))?;
+    // Every hash takes at least a byte, which bounds the allocations below by
+    // the input size.
+    let count = reader.length()?;
+    let flags = reader.byte()?;
+
+    let mut hashes = Vec::with_capacity(count);
+    for _ in 0..count {
+        hashes.push(reader.varint()?);
+    }
+    let mut offsets = Vec::new();
+    if flags & HAS_OFFSETS != 0 {
+        offsets.reserve(count);
+        let mut previous = 0i64;
+        for _ in 0..count {
+            previous = previous.wrapping_add(unzigzag(reader.varint()?));
+            offsets.push(u32::try_from(previous).map_err(|_| anyhow!("Fingerprint offset out of range"))?);
+        }
+    }
+    let weights = if flags & HAS_WEIGHTS != 0 { reader.take(count)?.to_vec() } else { Vec::new() };
+
+    if reader.pos != data.len() {
+        return Err(anyhow!("Trailing bytes after fingerprint"));
+    }
+
+    Ok(AudioFingerprint { hashes, offsets, weights, duration, params, version })
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len());
+        let end = end.ok_or_else(|| anyhow!("Truncated fingerprint"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!("Malformed varint in fingerprint"))
+    }
+
+    // A count or length, which can't exceed the bytes left.
+    fn length(&mut self) -> Result<usize> {
+        let value = self.varint()?;
+        let left = self.data.len() - self.pos;
+        if value > left as u64 {
+            return Err(anyhow!("Truncated fingerprint"));
+        }
+        Ok(value as usize)
+    }
+}
@@ -0,0 +1,713 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::audio::{ResampleQuality, SilenceMode};
+use crate::fingerprint::HashParams;
+use crate::scoring::{ScorerKind, TierThresholds};
+
+const CONFIG_PATH_ENV: &str = "MUSIC_MATCHER_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "music-matcher.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub database_url: String,
+    pub bind_addr: SocketAddr,
+    pub http: HttpConfig,
+    pub matching: MatchingConfig,
+    pub hashing: HashParams,
+    pub batch: BatchConfig,
+    pub encryption: EncryptionConfig,
+    pub scheduler: SchedulerConfig,
+    pub jobs: JobsConfig,
+    pub snapshots: SnapshotConfig,
+    pub unidentified: UnidentifiedConfig,
+    pub uploads: UploadConfig,
+    pub limits: AudioLimits,
+    pub silence: SilenceConfig,
+    pub denoise: DenoiseConfig,
+    pub admin: AdminConfig,
+    #[cfg(feature = "ffmpeg")]
+    pub ffmpeg: FfmpegConfig,
+    #[cfg(feature = "media-bridge")]
+    pub media_bridge: MediaBridgeConfig,
+    #[cfg(feature = "remote-fetch")]
+    pub remote_fetch: RemoteFetchConfig,
+    #[cfg(feature = "sync")]
+    pub sync: SyncConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            database_url: "songs.db".to_string(),
+            bind_addr: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            matching: MatchingConfig::default(),
+            http: HttpConfig::default(),
+            hashing: HashParams::default(),
+            batch: BatchConfig::default(),
+            encryption: EncryptionConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            jobs: JobsConfig::default(),
+            snapshots: SnapshotConfig::default(),
+            unidentified: UnidentifiedConfig::default(),
+            uploads: UploadConfig::default(),
+            limits: AudioLimits::default(),
+            silence: SilenceConfig::default(),
+            denoise: DenoiseConfig::default(),
+            admin: AdminConfig::default(),
+            #[cfg(feature = "ffmpeg")]
+            ffmpeg: FfmpegConfig::default(),
+            #[cfg(feature = "media-bridge")]
+            media_bridge: MediaBridgeConfig::default(),
+            #[cfg(feature = "remote-fetch")]
+            remote_fetch: RemoteFetchConfig::default(),
+            #[cfg(feature = "sync")]
+            sync: SyncConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MatchingConfig {
+    pub scorer: ScorerKind,
+    // How many index candidates the verification stage scores.
+    pub candidates: usize,
+    // The same for /match-all, which pages through every match it finds.
+    pub match_all_candidates: usize,
+    // Seconds of each /match clip that are fingerprinted; the rest is
+    // dropped. None fingerprints the whole clip.
+    pub max_clip_secs: Option<f64>,
+    // Resampling for match queries, which are latency-bound, where ingest
+    // uses hashing.resample. None uses hashing.resample for both.
+    pub resample: Option<ResampleQuality>,
+    pub tiers: TierThresholds,
+    // Alternative scorers a /match request can opt into by name, to compare
+    // them with `scorer` on live traffic.
+    pub experiments: HashMap<String, ScorerKind>,
+    pub pitch_shift: PitchShiftConfig,
+    // Frames a hash's time difference may be off by and still count as
+    // shared, so recordings played slightly faster or slower still hit. Every
+    // extra frame multiplies the index lookups; 0 matches exactly.
+    pub time_diff_tolerance: u16,
+}
+
+impl Default for MatchingConfig {
+    fn default() -> Self {
+        MatchingConfig {
+            scorer: ScorerKind::default(),
+            candidates: 20,
+            match_all_candidates: 1000,
+            max_clip_secs: None,
+            resample: None,
+            tiers: TierThresholds::default(),
+            experiments: HashMap::new(),
+            pitch_shift: PitchShiftConfig::default(),
+            time_diff_tolerance: 0,
+        }
+    }
+}
+
+// Queries played faster or slower than the catalog recording, as in DJ sets
+// and vinyl captures, are also tried with their pitch scaled back by every
+// multiple of `step_percent` up to `max_percent` either way. Each one costs
+// another index lookup and scoring pass; 0 turns it off.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct PitchShiftConfig {
+    pub max_percent: f64,
+    pub step_percent: f64,
+}
+
+impl Default for PitchShiftConfig {
+    fn default() -> Self {
+        PitchShiftConfig { max_percent: 0.0, step_percent: 0.5 }
+    }
+}
+
+// Shifts tried either way at most.
+const MAX_PITCH_SHIFT_STEPS: f64 = 24.0;
+
+const MAX_TIME_DIFF_TOLERANCE: u16 = 3;
+
+impl PitchShiftConfig {
+    // The shifts to try besides none, nearest first.
+    pub fn shifts(&self) -> Vec<f64> {
+        let steps = (self.max_percent / self.step_percent + 1e-9).floor() as i32;
+        (1..=steps)
+            .flat_map(|k| [k as f64 * self.step_percent, -k as f64 * self.step_percent])
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    pub max_archive_bytes: usize,
+    pub max_entries: usize,
+    pub max_entry_bytes: u64,
+    pub max_total_bytes: u64,
+    pub max_compression_ratio: u64,
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_archive_bytes: 100 * 1024 * 1024,
+            max_entries: 200,
+            max_entry_bytes: 50 * 1024 * 1024,
+            max_total_bytes: 512 * 1024 * 1024,
+            max_compression_ratio: 100,
+            concurrency: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    pub key_env: String,
+    // Command whose stdout is the key, e.g. a KMS decrypt call. Takes
+    // precedence over key_env when set.
+    pub key_command: Option<Vec<String>>,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        EncryptionConfig {
+            enabled: false,
+            key_env: "MUSIC_MATCHER_DB_KEY".to_string(),
+            key_command: None,
+        }
+    }
+}
+
+impl EncryptionConfig {
+    pub fn resolve_key(&self) -> Result<Option<String>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let key = match &self.key_command {
+            Some(command) => {
+                let (program, args) = command
+                    .split_first()
+                    .ok_or_else(|| anyhow!("encryption.key_command is empty"))?;
+                let output = std::process::Command::new(program)
+                    .args(args)
+                    .output()
+                    .map_err(|e| anyhow!("Failed to run key command {}: {}", program, e))?;
+                if !output.status.success() {
+                    return Err(anyhow!("Key command {} exited with {}", program, output.status));
+                }
+                String::from_utf8(output.stdout)
+                    .map_err(|_| anyhow!("Key command {} produced non-UTF-8 output", program))?
+            }
+            None => std::env::var(&self.key_env)
+                .map_err(|_| anyhow!("Database encryption enabled but {} is not set", self.key_env))?,
+        };
+
+        let key = key.trim().to_string();
+        if key.is_empty() {
+            return Err(anyhow!("Database encryption key is empty"));
+        }
+
+        Ok(Some(key))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub enabled: bool,
+    pub backup_dir: PathBuf,
+    pub backup_retention: usize,
+    pub dedupe_threshold: f64,
+    pub jobs: Vec<JobConfig>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            enabled: false,
+            backup_dir: PathBuf::from("backups"),
+            backup_retention: 7,
+            dedupe_threshold: 0.9,
+            jobs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    pub spool_dir: PathBuf,
+    pub max_attempts: u32,
+    pub retry_base_secs: u64,
+    pub poll_interval_ms: u64,
+    // Concurrent ingest workers; defaults to the number of physical cores.
+    pub workers: Option<usize>,
+    pub max_file_bytes: u64,
+    // Directories that server-side imports may read from. Empty disables
+    // directory and manifest imports.
+    pub import_roots: Vec<PathBuf>,
+    // Throttles that keep ingestion from starving live matching; unset means
+    // unlimited.
+    pub max_files_per_minute: Option<u32>,
+    pub max_concurrent_fingerprints: Option<usize>,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            spool_dir: PathBuf::from("spool"),
+            max_attempts: 5,
+            retry_base_secs: 2,
+            poll_interval_ms: 500,
+            workers: None,
+            max_file_bytes: 500 * 1024 * 1024,
+            import_roots: Vec::new(),
+            max_files_per_minute: None,
+            max_concurrent_fingerprints: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    // Environment variable holding the bearer token for /admin endpoints.
+    // When it is unset the admin endpoints stay open, as before tokens
+    // existed, except for those that always require one.
+    pub token_env: String,
+    // Environment variable holding a token that only reads the sync change
+    // log, for edges mirroring this catalog.
+    pub sync_token_env: String,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            token_env: "MUSIC_MATCHER_ADMIN_TOKEN".to_string(),
+            sync_token_env: "MUSIC_MATCHER_SYNC_TOKEN".to_string(),
+        }
+    }
+}
+
+impl AdminConfig {
+    pub fn resolve_token(&self) -> Option<String> {
+        resolve_env_token(&self.token_env)
+    }
+
+    pub fn resolve_sync_token(&self) -> Option<String> {
+        resolve_env_token(&self.sync_token_env)
+    }
+}
+
+fn resolve_env_token(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+// Bounds on what a single decoded input may cost, checked while decoding so
+// a crafted file is rejected before it exhausts memory.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct AudioLimits {
+    // Decoded samples per input byte. PCM yields at most 1; compressed
+    // codecs at typical bitrates stay well under 16.
+    pub max_samples_per_byte: u64,
+    pub max_duration_secs: u64,
+    pub max_spectrogram_bytes: u64,
+}
+
+impl Default for AudioLimits {
+    fn default() -> Self {
+        AudioLimits {
+            max_samples_per_byte: 64,
+            max_duration_secs: 30 * 60,
+            max_spectrogram_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+// Silence removal for /match queries; see audio::remove_silence. The mode is
+// the default for requests that don't pass `silence`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SilenceConfig {
+    pub mode: SilenceMode,
+    // RMS level, in dB below full scale, under which a frame is silent.
+    pub threshold_db: f32,
+    pub frame_ms: u32,
+    pub min_gap_ms: u32,
+}
+
+impl Default for SilenceConfig {
+    fn default() -> Self {
+        SilenceConfig {
+            mode: SilenceMode::Off,
+            threshold_db: -45.0,
+            frame_ms: 20,
+            min_gap_ms: 300,
+        }
+    }
+}
+
+// Spectral-subtraction noise reduction for /match queries; see
+// audio::denoise. `enabled` is the default for requests that don't pass
+// `denoise`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct DenoiseConfig {
+    pub enabled: bool,
+    // Analysis frame length, rounded up to a power of two in samples.
+    pub frame_ms: u32,
+    // Share of the quietest frames the noise spectrum is averaged over.
+    pub noise_fraction: f32,
+    // Multiple of the noise spectrum subtracted from each frame.
+    pub over_subtraction: f32,
+    // Share of each bin's magnitude that is always kept, which avoids the
+    // "musical noise" of bins zeroed at random.
+    pub floor: f32,
+}
+
+impl Default for DenoiseConfig {
+    fn default() -> Self {
+        DenoiseConfig {
+            enabled: false,
+            frame_ms: 40,
+            noise_fraction: 0.1,
+            over_subtraction: 2.0,
+            floor: 0.05,
+        }
+    }
+}
+
+// Request timeout and body size limit for every route, with overrides for
+// individual routes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    // Unset means requests never time out.
+    pub timeout_secs: Option<u64>,
+    pub max_body_bytes: usize,
+    // Keyed by the route as registered, e.g. "/match" or "/songs/:id/audio".
+    pub routes: HashMap<String, RouteLimits>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct RouteLimits {
+    pub timeout_secs: Option<u64>,
+    pub max_body_bytes: Option<usize>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout_secs: None,
+            max_body_bytes: 2 * 1024 * 1024,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn timeout_for(&self, path: &str) -> Option<Duration> {
+        self.routes
+            .get(path)
+            .and_then(|route| route.timeout_secs)
+            .or(self.timeout_secs)
+            .map(Duration::from_secs)
+    }
+
+    pub fn body_limit_for(&self, path: &str) -> usize {
+        self.routes
+            .get(path)
+            .and_then(|route| route.max_body_bytes)
+            .unwrap_or(self.max_body_bytes)
+    }
+}
+
+// Limits for `Content-Encoding: gzip` / `zstd` request bodies.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    pub max_decompressed_bytes: usize,
+    pub max_compression_ratio: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            max_decompressed_bytes: 100 * 1024 * 1024,
+            max_compression_ratio: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    pub dir: PathBuf,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            dir: PathBuf::from("snapshots"),
+        }
+    }
+}
+
+// Unmatched /match uploads kept for labeling, so audio the catalog is
+// missing can be added from what listeners actually send.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UnidentifiedConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    // Length of the audio kept with each entry, from the start of the clip.
+    pub snippet_secs: f64,
+    // Captures stop while the queue holds this many entries.
+    pub max_entries: i64,
+}
+
+impl Default for UnidentifiedConfig {
+    fn default() -> Self {
+        UnidentifiedConfig {
+            enabled: false,
+            dir: PathBuf::from("unidentified"),
+            snippet_secs: 15.0,
+            max_entries: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobConfig {
+    pub name: String,
+    pub kind: JobKind,
+    // Six- or seven-field cron expression (seconds first), e.g. "0 0 3 * * *".
+    pub cron: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Maintenance,
+    Backup,
+    Retention,
+    Dedupe,
+}
+
+// The decoder of last resort for uploads the native decoders reject.
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FfmpegConfig {
+    pub path: String,
+    pub timeout_secs: u64,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        FfmpegConfig {
+            path: "ffmpeg".to_string(),
+            timeout_secs: 60,
+        }
+    }
+}
+
+#[cfg(feature = "media-bridge")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MediaBridgeConfig {
+    pub ffmpeg_path: String,
+    pub ytdlp_path: Option<String>,
+    pub timeout_secs: u64,
+    pub max_output_bytes: usize,
+    pub max_duration_secs: u32,
+    pub allowed_schemes: Vec<String>,
+    // Empty allows every host that resolves only to public addresses. A
+    // listed host may also resolve to a private one.
+    pub allowed_hosts: Vec<String>,
+}
+
+#[cfg(feature = "media-bridge")]
+impl Default for MediaBridgeConfig {
+    fn default() -> Self {
+        MediaBridgeConfig {
+            ffmpeg_path: "ffmpeg".to_string(),
+            ytdlp_path: None,
+            timeout_secs: 120,
+            max_output_bytes: 200 * 1024 * 1024,
+            max_duration_secs: 900,
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+// Downloads for /add-song/fetch. Hosts on the server's own network
+// (loopback, private and link-local addresses) are refused unless listed in
+// allowed_hosts.
+#[cfg(feature = "remote-fetch")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RemoteFetchConfig {
+    pub timeout_secs: u64,
+    pub max_bytes: usize,
+    pub allowed_schemes: Vec<String>,
+    // Empty allows every host with a public address. A listed host may also
+    // resolve to a private one.
+    pub allowed_hosts: Vec<String>,
+}
+
+#[cfg(feature = "remote-fetch")]
+impl Default for RemoteFetchConfig {
+    fn default() -> Self {
+        RemoteFetchConfig {
+            timeout_secs: 60,
+            max_bytes: 200 * 1024 * 1024,
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    // Base URL of the central server to mirror the catalog from; unset
+    // means this instance keeps its own catalog.
+    pub upstream: Option<String>,
+    pub interval_secs: u64,
+    // Change log entries requested per page (the server caps it at 1000).
+    pub page_size: u32,
+    // Environment variable holding the upstream's sync token (its
+    // admin.sync_token_env); its admin token also works.
+    pub token_env: String,
+    pub timeout_secs: u64,
+    // Refuse catalog writes until promoted through the admin API, so this
+    // instance can take over from `upstream` if it fails.
+    pub standby: bool,
+}
+
+#[cfg(feature = "sync")]
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            upstream: None,
+            interval_secs: 300,
+            page_size: 100,
+            token_env: "MUSIC_MATCHER_SYNC_TOKEN".to_string(),
+            timeout_secs: 60,
+            standby: false,
+        }
+    }
+}
+
+impl Config {
+    // Hash settings for match queries: `hashing` with matching.resample.
+    pub fn match_hashing(&self) -> HashParams {
+        HashParams {
+            resample: self.matching.resample.unwrap_or(self.hashing.resample),
+            ..self.hashing
+        }
+    }
+
+    // Reads the TOML file named by MUSIC_MATCHER_CONFIG, falling back to
+    // ./music-matcher.toml. A missing default file just means "use defaults".
+    pub fn load() -> Result<Self> {
+        match std::env::var(CONFIG_PATH_ENV) {
+            Ok(path) => Self::from_file(Path::new(&path)),
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))
+            }
+            Err(_) => Ok(Config::default()),
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config file {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| anyhow!("Failed to parse config file {}: {}", path.display(), e))?;
+
+        if let Err(e) = config.hashing.analysis.validate() {
+            return Err(anyhow!("Invalid hashing.analysis in {}: {}", path.display(), e));
+        }
+        if let Some(band) = config.hashing.band_pass {
+            if band.low_hz >= band.high_hz {
+                return Err(anyhow!(
+                    "Invalid hashing.band_pass in {}: low_hz must be below high_hz",
+                    path.display()
+                ));
+            }
+        }
+
+        if let Some(secs) = config.matching.max_clip_secs {
+            if !secs.is_finite() || secs <= 0.0 {
+                return Err(anyhow!(
+                    "Invalid matching.max_clip_secs in {}: must be a positive number of seconds",
+                    path.display()
+                ));
+            }
+        }
+
+        let pitch_shift = &config.matching.pitch_shift;
+        if !(0.0..=20.0).contains(&pitch_shift.max_percent)
+            || pitch_shift.step_percent.is_nan()
+            || pitch_shift.step_percent <= 0.0
+            || pitch_shift.max_percent / pitch_shift.step_percent > MAX_PITCH_SHIFT_STEPS
+        {
+            return Err(anyhow!(
+                "Invalid matching.pitch_shift in {}: max_percent must be between 0 and 20, step_percent positive, \
+                 and max_percent at most {} steps",
+                path.display(),
+                MAX_PITCH_SHIFT_STEPS
+            ));
+        }
+
+        if config.matching.time_diff_tolerance > MAX_TIME_DIFF_TOLERANCE {
+            return Err(anyhow!(
+                "Invalid matching.time_diff_tolerance in {}: must be at most {} frames",
+                path.display(),
+                MAX_TIME_DIFF_TOLERANCE
+            ));
+        }
+
+        let denoise = &config.denoise;
+        if denoise.noise_fraction.is_nan()
+            || denoise.noise_fraction <= 0.0
+            || denoise.noise_fraction > 1.0
+            || !(0.0..=1.0).contains(&denoise.floor)
+            || !(0.0..=f32::MAX).contains(&denoise.over_subtraction)
+        {
+            return Err(anyhow!(
+                "Invalid denoise in {}: noise_fraction must be in (0, 1], floor in [0, 1] and over_subtraction non-negative",
+                path.display()
+            ));
+        }
+
+        #[cfg(feature = "sync")]
+        if config.sync.standby && config.sync.upstream.is_none() {
+            return Err(anyhow!("Invalid sync in {}: standby needs an upstream", path.display()));
+        }
+
+        Ok(config)
+    }
+}
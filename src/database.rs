@@ -1,32 +1,415 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool, sqlite::SqliteConnectOptions};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool, sqlite::SqliteConnectOptions};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tracing::info;
 
-use crate::fingerprint::{AudioFingerprint, calculate_similarity};
+use crate::bloom::HashFilter;
+use crate::codec;
+use crate::fingerprint::{algorithm_versions_of, time_diff_variants, AudioFingerprint, HashParams, ALGORITHM_VERSION};
+use crate::scoring::{match_evidence, scorer_for, MatchEvidence, ScorerKind, SimilarityScorer, MATCH_THRESHOLD};
+
+// SQLite caps bound parameters per statement; stay well below it.
+const HASH_CHUNK_SIZE: usize = 500;
+
+// The hash filter is sized for twice the distinct hashes indexed when it is
+// built (and at least this many), so it absorbs growth before a rebuild.
+const HASH_FILTER_MIN_CAPACITY: usize = 1 << 20;
+const HASH_FILTER_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+// Songs read per query while looking for duplicates.
+const DEDUPE_BATCH_SIZE: i64 = 100;
 
 #[derive(Clone)]
 pub struct Database {
     pool: SqlitePool,
+    scorer: Arc<dyn SimilarityScorer>,
+    candidate_limit: usize,
+    // Frames a hash's Δt may be off by and still count as shared.
+    time_diff_tolerance: u16,
+    // Every hash in song_hashes, so queries sharing none of them skip the
+    // index lookup entirely. Empty until init() has built it; hashes are only
+    // ever added, so deletions just leave harmless false positives.
+    hash_filter: Arc<RwLock<HashFilter>>,
+    // Held for the whole of a rebuild so two never overlap.
+    hash_filter_rebuild: Arc<tokio::sync::Mutex<()>>,
+}
+
+// A write's hashes registered with the hash filter. Keep it alive until the
+// transaction that inserts their postings has committed (or failed).
+struct PendingHashes {
+    filter: Arc<RwLock<HashFilter>>,
+    id: u64,
+}
+
+impl Drop for PendingHashes {
+    fn drop(&mut self) {
+        self.filter.write().unwrap_or_else(|e| e.into_inner()).finish_write(self.id);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    pub song_id: i64,
+    pub title: String,
+    pub artist: String,
+    pub confidence: f64,
+    pub evidence: MatchEvidence,
+    // How far above the song the query was pitched, in percent, when it only
+    // matched with its pitch scaled back (find_pitch_shifted_matches).
+    pub pitch_shift_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct IngestJob {
+    pub id: i64,
+    pub title: String,
+    pub artist: String,
+    pub audio_path: String,
+    pub status: String,
+    pub attempts: i64,
+    pub max_attempts: i64,
+    pub last_error: Option<String>,
+    pub song_id: Option<i64>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub import_id: Option<i64>,
+    // Spooled uploads are removed once ingested; imported files are not ours
+    // to delete.
+    pub delete_after: bool,
+    // Set for re-fingerprinting: the job replaces this song's fingerprint
+    // instead of adding a new song.
+    pub target_song_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SongSummary {
+    pub id: i64,
+    pub title: String,
+    pub artist: String,
+    pub duration: f64,
+    pub version: i64,
+    pub hash_count: i64,
+    pub year: Option<i64>,
+    #[sqlx(json)]
+    pub tags: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl SongSummary {
+    pub const FIELDS: [&'static str; 9] = [
+        "id", "title", "artist", "duration", "version", "hash_count", "year", "tags", "created_at",
+    ];
+}
+
+const SONG_SUMMARY_SELECT: &str = r#"
+    SELECT id, title, artist, duration, version, year, tags, created_at,
+           (SELECT COUNT(*) FROM song_hashes WHERE song_hashes.song_id = songs.id) AS hash_count
+    FROM songs
+"#;
+
+#[cfg(feature = "graphql")]
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct MatchRecord {
+    pub id: i64,
+    pub song_id: Option<i64>,
+    pub song_version: Option<i64>,
+    pub confidence: Option<f64>,
+    pub experiment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// One row of match history as exported. Title and artist are the song's
+// current ones, null if it has since been deleted.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct MatchHistoryEntry {
+    pub id: i64,
+    pub song_id: Option<i64>,
+    pub song_version: Option<i64>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub confidence: Option<f64>,
+    // The experiment that served the request; null for the default scorer.
+    pub experiment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// A fingerprint a song carried before it was replaced.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SongVersion {
+    pub version: i64,
+    pub duration: f64,
+    pub archived_at: DateTime<Utc>,
+}
+
+pub struct NewIngestJob<'a> {
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub audio_path: &'a str,
+    pub max_attempts: u32,
+    pub import_id: Option<i64>,
+    pub delete_after: bool,
+    pub target_song_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportProgress {
+    pub import_id: i64,
+    pub source: String,
+    pub total: i64,
+    pub completed: i64,
+    pub failed: i64,
+    pub remaining: i64,
+    pub started_at: DateTime<Utc>,
+    pub eta_secs: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CatalogStats {
+    pub songs: i64,
+    pub postings: i64,
+    pub distinct_hashes: i64,
+    pub database_bytes: i64,
+    pub top_artists: Vec<(String, i64)>,
+    pub matches_24h: MatchRate,
+    pub matches_7d: MatchRate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchRate {
+    pub requests: i64,
+    pub matched: i64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub songs_checked: usize,
+    // Songs whose stored fingerprint no longer parses, with the parse error.
+    pub unparsable: Vec<(i64, String)>,
+    // Songs whose index postings don't match their fingerprint.
+    pub mismatched: Vec<i64>,
+    // Postings that reference a song that no longer exists.
+    pub orphaned_postings: i64,
+}
+
+impl VerifyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.unparsable.is_empty() && self.mismatched.is_empty() && self.orphaned_postings == 0
+    }
+}
+
+// A song whose fingerprint was generated by an older algorithm or with
+// hashing settings other than the current ones.
+#[derive(Debug, Clone, Serialize)]
+pub struct StaleSong {
+    pub id: i64,
+    pub title: String,
+    pub artist: String,
+    pub fingerprint_version: i64,
+    pub params_digest: Option<String>,
+    pub outdated_algorithm: bool,
+    pub outdated_params: bool,
+    // The imported file the song was ingested from, if it is still on disk.
+    // Songs uploaded over HTTP have no original to re-fingerprint from.
+    pub original: Option<String>,
+    pub refingerprint_pending: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MatchPageQuery {
+    // Index candidates to verify; the pool every page is cut from.
+    pub candidates: usize,
+    // Applied on top of MATCH_THRESHOLD.
+    pub min_score: f64,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+// Catalog metadata beyond title and artist, for scoping matches with a
+// SongFilter. Tags are stored trimmed and lowercased.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SongMetadata {
+    pub year: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl SongMetadata {
+    fn tags_json(&self) -> Result<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for tag in &self.tags {
+            let tag = tag.trim().to_lowercase();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        Ok(serde_json::to_string(&tags)?)
+    }
+}
+
+// Restricts matching to songs whose metadata passes every condition that is
+// set. It is applied while picking index candidates, so songs outside it
+// never take up the candidate limit. A year bound excludes songs with no
+// year.
+#[derive(Debug, Clone, Default)]
+pub struct SongFilter {
+    // Any of these artists, ignoring ASCII case.
+    pub artists: Vec<String>,
+    pub tag: Option<String>,
+    pub year_from: Option<i64>,
+    pub year_to: Option<i64>,
+}
+
+impl SongFilter {
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.tag.is_none() && self.year_from.is_none() && self.year_to.is_none()
+    }
+
+    // Appends the filter as a condition on `song_id`.
+    fn push_condition(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        if self.is_empty() {
+            return;
+        }
+
+        query.push(" AND song_id IN (SELECT id FROM songs WHERE 1 = 1");
+        if !self.artists.is_empty() {
+            query.push(" AND artist COLLATE NOCASE IN (");
+            let mut artists = query.separated(", ");
+            for artist in &self.artists {
+                artists.push_bind(artist.clone());
+            }
+            artists.push_unseparated(")");
+        }
+        if let Some(tag) = &self.tag {
+            query.push(" AND EXISTS (SELECT 1 FROM json_each(songs.tags) WHERE json_each.value = ");
+            query.push_bind(tag.trim().to_lowercase());
+            query.push(")");
+        }
+        if let Some(year_from) = self.year_from {
+            query.push(" AND year >= ").push_bind(year_from);
+        }
+        if let Some(year_to) = self.year_to {
+            query.push(" AND year <= ").push_bind(year_to);
+        }
+        query.push(")");
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchPage {
+    pub matches: Vec<MatchResult>,
+    // Matches at or above min_score across all pages.
+    pub total: usize,
+}
+
+// One page of the catalog change log, as served to edge instances. `songs`
+// holds the current state of every song added or changed in the page and
+// `deleted` the ids removed; a song appears in at most one of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogChanges {
+    // Pass back as `since` to fetch the next page.
+    pub cursor: i64,
+    // Newest change the server has. A client whose cursor is ahead of it is
+    // talking to a different (or rebuilt) catalog and must start over.
+    pub latest: i64,
+    pub more: bool,
+    pub songs: Vec<SyncedSong>,
+    pub deleted: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedSong {
+    pub id: i64,
+    pub title: String,
+    pub artist: String,
+    pub version: i64,
+    #[serde(default)]
+    pub fingerprint_version: i64,
+    #[serde(default)]
+    pub year: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub fingerprint: AudioFingerprint,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct CatalogSnapshot {
+    pub id: i64,
+    pub path: String,
+    pub song_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+// An unmatched upload waiting to be labeled. The snippet is the start of the
+// clip as a WAV file; the fingerprint covers the whole clip.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct UnidentifiedClip {
+    pub id: i64,
+    pub duration: f64,
+    pub hash_count: i64,
+    #[serde(skip)]
+    pub snippet_path: String,
+    pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SongRecord {
-    id: i64,
-    title: String,
-    artist: String,
-    fingerprint_data: String,
-    duration: f64,
+#[derive(Debug, Serialize)]
+pub struct MaintenanceReport {
+    pub size_before: i64,
+    pub size_after: i64,
+    pub reclaimed_bytes: i64,
+    pub integrity_ok: bool,
+    pub integrity_findings: Vec<String>,
+    // Fingerprints rewritten from JSON in the binary form.
+    pub encoded_fingerprints: usize,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self> {
-        let options = SqliteConnectOptions::from_str(database_url)?
+    pub async fn new(database_url: &str, encryption_key: Option<&str>) -> Result<Self> {
+        let mut options = SqliteConnectOptions::from_str(database_url)?
             .create_if_missing(true);
+
+        if let Some(key) = encryption_key {
+            if !cfg!(feature = "sqlcipher") {
+                return Err(anyhow!("Database encryption requires building with the sqlcipher feature"));
+            }
+            // sqlx always issues the `key` pragma first, as SQLCipher requires.
+            options = options.pragma("key", format!("'{}'", key.replace('\'', "''")));
+        }
         
         let pool = SqlitePool::connect_with(options).await?;
         
-        Ok(Database { pool })
+        Ok(Database {
+            pool,
+            scorer: Arc::from(scorer_for(ScorerKind::default())),
+            candidate_limit: 20,
+            time_diff_tolerance: 0,
+            hash_filter: Arc::new(RwLock::new(HashFilter::new(
+                HASH_FILTER_MIN_CAPACITY,
+                HASH_FILTER_FALSE_POSITIVE_RATE,
+            ))),
+            hash_filter_rebuild: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    pub fn with_candidate_limit(mut self, limit: usize) -> Self {
+        self.candidate_limit = limit.max(1);
+        self
+    }
+
+    pub fn with_time_diff_tolerance(mut self, tolerance: u16) -> Self {
+        self.time_diff_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_scorer(mut self, kind: ScorerKind) -> Self {
+        self.scorer = Arc::from(scorer_for(kind));
+        self
     }
 
     pub async fn init(&self) -> Result<()> {
@@ -36,7 +419,7 @@ impl Database {
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 title TEXT NOT NULL,
                 artist TEXT NOT NULL,
-                fingerprint_data TEXT NOT NULL,
+                fingerprint_data BLOB NOT NULL,
                 duration REAL NOT NULL,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP
             )
@@ -54,138 +437,1888 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
-        Ok(())
-    }
+        // Inverted index: one posting per (hash, song, anchor frame). Hashes
+        // are u64 below 2^48, so INTEGER holds them as they are; format 1
+        // postings (below 2^32) stay until their songs are re-fingerprinted.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS song_hashes (
+                hash INTEGER NOT NULL,
+                song_id INTEGER NOT NULL,
+                offset INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_song_hashes_hash ON song_hashes(hash);
+            CREATE INDEX IF NOT EXISTS idx_song_hashes_song ON song_hashes(song_id);
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
 
-    pub async fn add_song(
-        &self,
-        title: &str,
-        artist: &str,
-        fingerprint: &AudioFingerprint,
-    ) -> Result<i64> {
-        let fingerprint_json = serde_json::to_string(fingerprint)?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS ingest_jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                title TEXT NOT NULL,
+                artist TEXT NOT NULL,
+                audio_path TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                max_attempts INTEGER NOT NULL,
+                last_error TEXT,
+                song_id INTEGER,
+                next_attempt_at TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_ingest_jobs_status ON ingest_jobs(status, next_attempt_at);
 
-        let result = sqlx::query(
+            CREATE TABLE IF NOT EXISTS ingest_imports (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                source TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
             r#"
-            INSERT INTO songs (title, artist, fingerprint_data, duration)
-            VALUES (?1, ?2, ?3, ?4)
+            CREATE TABLE IF NOT EXISTS match_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                song_id INTEGER,
+                confidence REAL,
+                created_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_match_history_created ON match_history(created_at);
             "#,
         )
-        .bind(title)
-        .bind(artist)
-        .bind(&fingerprint_json)
-        .bind(fingerprint.duration)
         .execute(&self.pool)
         .await?;
 
-        Ok(result.last_insert_rowid())
-    }
+        // Previous fingerprints of songs whose audio was replaced. `version`
+        // is the version number the fingerprint had while it was current.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS song_versions (
+                song_id INTEGER NOT NULL,
+                version INTEGER NOT NULL,
+                fingerprint_data BLOB NOT NULL,
+                duration REAL NOT NULL,
+                archived_at TEXT NOT NULL,
+                PRIMARY KEY (song_id, version)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
 
-    pub async fn find_match(
-        &self,
-        query_fingerprint: &AudioFingerprint,
-    ) -> Result<Option<(i64, String, String, f64)>> {
-        let rows = sqlx::query(
+        sqlx::query(
             r#"
-            SELECT id, title, artist, fingerprint_data, duration
-            FROM songs
+            CREATE TABLE IF NOT EXISTS catalog_snapshots (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path TEXT NOT NULL,
+                song_count INTEGER NOT NULL,
+                created_at TEXT NOT NULL
+            )
             "#,
         )
-        .fetch_all(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        let mut best_match = None;
-        let mut best_similarity = 0.0;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS unidentified_clips (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                fingerprint_data BLOB NOT NULL,
+                duration REAL NOT NULL,
+                hash_count INTEGER NOT NULL,
+                snippet_path TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
 
-        for row in rows {
-            let id: i64 = row.get("id");
-            let title: String = row.get("title");
-            let artist: String = row.get("artist");
-            let fingerprint_data: String = row.get("fingerprint_data");
-
-            if let Ok(stored_fingerprint) = serde_json::from_str::<AudioFingerprint>(&fingerprint_data) {
-                let similarity = calculate_similarity(query_fingerprint, &stored_fingerprint);
-                
-                if similarity > best_similarity && similarity > 0.3 {
-                    best_similarity = similarity;
-                    best_match = Some((id, title, artist, similarity));
-                }
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS catalog_revision (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                revision INTEGER NOT NULL
+            );
+            INSERT OR IGNORE INTO catalog_revision (id, revision) VALUES (1, 0);
+
+            CREATE TRIGGER IF NOT EXISTS songs_revision_insert AFTER INSERT ON songs
+            BEGIN UPDATE catalog_revision SET revision = revision + 1 WHERE id = 1; END;
+            CREATE TRIGGER IF NOT EXISTS songs_revision_update AFTER UPDATE ON songs
+            BEGIN UPDATE catalog_revision SET revision = revision + 1 WHERE id = 1; END;
+            CREATE TRIGGER IF NOT EXISTS songs_revision_delete AFTER DELETE ON songs
+            BEGIN UPDATE catalog_revision SET revision = revision + 1 WHERE id = 1; END;
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Change log for edge sync: one entry per write to a song, so a
+        // client can ask for everything after the last entry it applied.
+        // Songs stored before the log existed are logged once as added.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS catalog_changes (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                song_id INTEGER NOT NULL,
+                deleted INTEGER NOT NULL
+            );
+            INSERT INTO catalog_changes (song_id, deleted)
+            SELECT id, 0 FROM songs WHERE NOT EXISTS (SELECT 1 FROM catalog_changes) ORDER BY id;
+
+            CREATE TRIGGER IF NOT EXISTS songs_changes_insert AFTER INSERT ON songs
+            BEGIN INSERT INTO catalog_changes (song_id, deleted) VALUES (NEW.id, 0); END;
+            CREATE TRIGGER IF NOT EXISTS songs_changes_update AFTER UPDATE ON songs
+            BEGIN INSERT INTO catalog_changes (song_id, deleted) VALUES (NEW.id, 0); END;
+            CREATE TRIGGER IF NOT EXISTS songs_changes_delete AFTER DELETE ON songs
+            BEGIN INSERT INTO catalog_changes (song_id, deleted) VALUES (OLD.id, 1); END;
+
+            CREATE TABLE IF NOT EXISTS sync_state (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                upstream TEXT NOT NULL,
+                cursor INTEGER NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        self.ensure_column("songs", "version", "INTEGER NOT NULL DEFAULT 1").await?;
+        self.ensure_column("match_history", "song_version", "INTEGER").await?;
+        self.ensure_column("match_history", "experiment", "TEXT").await?;
+        self.ensure_column("ingest_jobs", "import_id", "INTEGER").await?;
+        self.ensure_column("ingest_jobs", "delete_after", "INTEGER NOT NULL DEFAULT 1").await?;
+        self.ensure_column("ingest_jobs", "target_song_id", "INTEGER").await?;
+        self.ensure_column("songs", "fingerprint_version", "INTEGER NOT NULL DEFAULT 0").await?;
+        self.ensure_column("songs", "params_digest", "TEXT").await?;
+        self.ensure_column("song_versions", "fingerprint_version", "INTEGER NOT NULL DEFAULT 0").await?;
+        self.ensure_column("songs", "year", "INTEGER").await?;
+        self.ensure_column("songs", "tags", "TEXT NOT NULL DEFAULT '[]'").await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_ingest_jobs_import ON ingest_jobs(import_id)")
+            .execute(&self.pool)
+            .await?;
+
+        let indexed = self.index_unindexed_songs().await?;
+        if indexed > 0 {
+            info!("Built index postings for {} songs that had none", indexed);
+        }
+        self.backfill_params_digests().await?;
+
+        self.rebuild_hash_filter().await?;
+
+        Ok(())
+    }
+
+    async fn rebuild_hash_filter(&self) -> Result<()> {
+        let _rebuilding = self.hash_filter_rebuild.lock().await;
+        self.rebuild_hash_filter_locked().await
+    }
+
+    async fn rebuild_hash_filter_locked(&self) -> Result<()> {
+        // The query below only sees committed postings; writes still in
+        // flight are carried over by the filter itself.
+        self.hash_filter.write().unwrap_or_else(|e| e.into_inner()).begin_rebuild();
+
+        let hashes: Vec<u64> = match sqlx::query_scalar::<_, i64>("SELECT DISTINCT hash FROM song_hashes")
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(hashes) => hashes.into_iter().map(|hash| hash as u64).collect(),
+            Err(e) => {
+                // Keep the old filter, which already covers everything logged.
+                self.hash_filter.write().unwrap_or_else(|e| e.into_inner()).abort_rebuild();
+                return Err(e.into());
+            }
+        };
+
+        self.hash_filter.write().unwrap_or_else(|e| e.into_inner()).finish_rebuild(&hashes);
+        Ok(())
+    }
+
+    // Called before the postings are committed, so a concurrent query can
+    // never be filtered out for a song it could already find in the index.
+    async fn add_to_hash_filter(&self, fingerprint: &AudioFingerprint) -> Result<PendingHashes> {
+        let (id, saturated) = {
+            let mut filter = self.hash_filter.write().unwrap_or_else(|e| e.into_inner());
+            (filter.begin_write(&fingerprint.hashes), filter.is_saturated())
+        };
+        let pending = PendingHashes {
+            filter: self.hash_filter.clone(),
+            id,
+        };
+
+        // A rebuild already under way will pick these hashes up.
+        if saturated {
+            if let Ok(_rebuilding) = self.hash_filter_rebuild.try_lock() {
+                self.rebuild_hash_filter_locked().await?;
             }
         }
 
-        Ok(best_match)
+        Ok(pending)
     }
 
-    pub async fn find_all_matches(
-        &self,
-        query_fingerprint: &AudioFingerprint,
-    ) -> Result<Vec<(i64, String, String, f64)>> {
+    // Adds a column to a table created by an older version of the service.
+    async fn ensure_column(&self, table: &str, column: &str, declaration: &str) -> Result<()> {
+        let columns: Vec<String> = sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .fetch_all(&self.pool)
+            .await?;
+
+        if !columns.iter().any(|name| name == column) {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, declaration))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Builds postings for songs stored before the inverted index existed.
+    async fn index_unindexed_songs(&self) -> Result<usize> {
         let rows = sqlx::query(
             r#"
-            SELECT id, title, artist, fingerprint_data, duration
+            SELECT id, fingerprint_data
             FROM songs
+            WHERE id NOT IN (SELECT DISTINCT song_id FROM song_hashes)
             "#,
         )
         .fetch_all(&self.pool)
         .await?;
 
-        let mut matches = Vec::new();
+        let mut indexed = 0;
+
+        for row in rows {
+            let id: i64 = row.get("id");
+            let fingerprint_data: Vec<u8> = row.get("fingerprint_data");
+
+            if let Ok(fingerprint) = codec::decode(&fingerprint_data) {
+                let mut tx = self.pool.begin().await?;
+                insert_postings(&mut tx, id, &fingerprint).await?;
+                tx.commit().await?;
+                indexed += 1;
+            }
+        }
+
+        Ok(indexed)
+    }
+
+    // Records the hashing settings of songs stored before they were tracked.
+    // An unparsable fingerprint is left alone for verify to report.
+    async fn backfill_params_digests(&self) -> Result<()> {
+        let rows: Vec<(i64, Vec<u8>)> =
+            sqlx::query_as("SELECT id, fingerprint_data FROM songs WHERE params_digest IS NULL")
+                .fetch_all(&self.pool)
+                .await?;
+
+        for (id, fingerprint_data) in rows {
+            if let Ok(fingerprint) = codec::decode(&fingerprint_data) {
+                sqlx::query("UPDATE songs SET params_digest = ?1 WHERE id = ?2")
+                    .bind(fingerprint.params.digest())
+                    .bind(id)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn verify_catalog(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport {
+            orphaned_postings: sqlx::query_scalar(
+                "SELECT COUNT(*) FROM song_hashes WHERE song_id NOT IN (SELECT id FROM songs)",
+            )
+            .fetch_one(&self.pool)
+            .await?,
+            ..VerifyReport::default()
+        };
+
+        let rows = sqlx::query("SELECT id, fingerprint_data FROM songs ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
 
         for row in rows {
             let id: i64 = row.get("id");
-            let title: String = row.get("title");
-            let artist: String = row.get("artist");
-            let fingerprint_data: String = row.get("fingerprint_data");
-
-            if let Ok(stored_fingerprint) = serde_json::from_str::<AudioFingerprint>(&fingerprint_data) {
-                let similarity = calculate_similarity(query_fingerprint, &stored_fingerprint);
-                
-                if similarity > 0.3 {
-                    matches.push((id, title, artist, similarity));
+            let fingerprint_data: Vec<u8> = row.get("fingerprint_data");
+            report.songs_checked += 1;
+
+            let fingerprint = match codec::decode(&fingerprint_data) {
+                Ok(fingerprint) => fingerprint,
+                Err(e) => {
+                    report.unparsable.push((id, e.to_string()));
+                    continue;
                 }
+            };
+
+            let mut expected = expected_postings(&fingerprint);
+            expected.sort_unstable();
+
+            let mut actual: Vec<(i64, i64)> = sqlx::query_as(
+                "SELECT hash, offset FROM song_hashes WHERE song_id = ?1",
+            )
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await?;
+            actual.sort_unstable();
+
+            if actual != expected {
+                report.mismatched.push(id);
             }
         }
 
-        // Sort by similarity in descending order
-        matches.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(report)
+    }
 
-        Ok(matches)
+    // Deletes orphaned postings and rebuilds postings for mismatched songs.
+    // Unparsable fingerprints can't be rebuilt without the original audio, so
+    // their postings are dropped to keep them out of candidate retrieval and
+    // the rows are left for an operator to re-ingest or delete.
+    pub async fn repair_catalog(&self, report: &VerifyReport) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let mut pending = Vec::new();
+
+        sqlx::query("DELETE FROM song_hashes WHERE song_id NOT IN (SELECT id FROM songs)")
+            .execute(&mut *tx)
+            .await?;
+
+        for (song_id, _) in &report.unparsable {
+            sqlx::query("DELETE FROM song_hashes WHERE song_id = ?1")
+                .bind(song_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        for &song_id in &report.mismatched {
+            let fingerprint_data: Vec<u8> = sqlx::query_scalar("SELECT fingerprint_data FROM songs WHERE id = ?1")
+                .bind(song_id)
+                .fetch_one(&mut *tx)
+                .await?;
+            let fingerprint = codec::decode(&fingerprint_data)?;
+
+            sqlx::query("DELETE FROM song_hashes WHERE song_id = ?1")
+                .bind(song_id)
+                .execute(&mut *tx)
+                .await?;
+            pending.push(self.add_to_hash_filter(&fingerprint).await?);
+            insert_postings(&mut tx, song_id, &fingerprint).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
     }
 
-    pub async fn get_all_songs(&self) -> Result<Vec<(i64, String, String)>> {
-        let rows = sqlx::query(
-            r#"
-            SELECT id, title, artist
-            FROM songs
-            ORDER BY created_at DESC
-            "#,
-        )
-        .fetch_all(&self.pool)
-        .await?;
+    pub async fn add_song(
+        &self,
+        title: &str,
+        artist: &str,
+        fingerprint: &AudioFingerprint,
+    ) -> Result<i64> {
+        self.add_song_with_metadata(title, artist, &SongMetadata::default(), fingerprint).await
+    }
 
-        let songs = rows
-            .into_iter()
-            .map(|row| {
-                let id: i64 = row.get("id");
-                let title: String = row.get("title");
-                let artist: String = row.get("artist");
-                (id, title, artist)
-            })
-            .collect();
+    pub async fn add_song_with_metadata(
+        &self,
+        title: &str,
+        artist: &str,
+        metadata: &SongMetadata,
+        fingerprint: &AudioFingerprint,
+    ) -> Result<i64> {
+        let mut tx = self.pool.begin().await?;
+        let (song_id, _pending) = self.insert_song(&mut tx, title, artist, metadata, fingerprint).await?;
+        tx.commit().await?;
 
-        Ok(songs)
+        Ok(song_id)
     }
 
-    pub async fn delete_song(&self, song_id: i64) -> Result<bool> {
+    // Inserts the song and its postings in `tx`. Hold on to the returned
+    // PendingHashes until `tx` has committed.
+    async fn insert_song(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        title: &str,
+        artist: &str,
+        metadata: &SongMetadata,
+        fingerprint: &AudioFingerprint,
+    ) -> Result<(i64, PendingHashes)> {
         let result = sqlx::query(
             r#"
-            DELETE FROM songs WHERE id = ?1
+            INSERT INTO songs (title, artist, fingerprint_data, duration, fingerprint_version, params_digest, year, tags)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
             "#,
         )
-        .bind(song_id)
-        .execute(&self.pool)
-        .await?;
-
+        .bind(title)
+        .bind(artist)
+        .bind(codec::encode(fingerprint))
+        .bind(fingerprint.duration)
+        .bind(ALGORITHM_VERSION)
+        .bind(fingerprint.params.digest())
+        .bind(metadata.year)
+        .bind(metadata.tags_json()?)
+        .execute(&mut **tx)
+        .await?;
+
+        let song_id = result.last_insert_rowid();
+        let pending = self.add_to_hash_filter(fingerprint).await?;
+        insert_postings(tx, song_id, fingerprint).await?;
+
+        Ok((song_id, pending))
+    }
+
+    // Replaces the song's year and tags. Returns false if the song doesn't
+    // exist.
+    pub async fn set_song_metadata(&self, song_id: i64, metadata: &SongMetadata) -> Result<bool> {
+        let result = sqlx::query("UPDATE songs SET year = ?1, tags = ?2 WHERE id = ?3")
+            .bind(metadata.year)
+            .bind(metadata.tags_json()?)
+            .bind(song_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Archives the song's current fingerprint and makes `fingerprint` the new
+    // current version. Returns the new version number, or None if the song
+    // doesn't exist.
+    pub async fn replace_song_fingerprint(&self, song_id: i64, fingerprint: &AudioFingerprint) -> Result<Option<i64>> {
+        let mut tx = self.pool.begin().await?;
+        let _pending = self.add_to_hash_filter(fingerprint).await?;
+        let version = swap_fingerprint(&mut tx, song_id, fingerprint, ALGORITHM_VERSION).await?;
+        tx.commit().await?;
+
+        Ok(version)
+    }
+
+    pub async fn list_song_versions(&self, song_id: i64) -> Result<Vec<SongVersion>> {
+        let versions = sqlx::query_as(
+            r#"
+            SELECT version, duration, archived_at
+            FROM song_versions
+            WHERE song_id = ?1
+            ORDER BY version DESC
+            "#,
+        )
+        .bind(song_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    // Makes an archived fingerprint current again. The fingerprint being
+    // replaced is archived in turn, so a restore can itself be undone.
+    pub async fn restore_song_version(&self, song_id: i64, version: i64) -> Result<Option<i64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let archived: Option<(Vec<u8>, i64)> = sqlx::query_as(
+            "SELECT fingerprint_data, fingerprint_version FROM song_versions WHERE song_id = ?1 AND version = ?2",
+        )
+        .bind(song_id)
+        .bind(version)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((fingerprint_data, fingerprint_version)) = archived else {
+            return Ok(None);
+        };
+        let fingerprint = codec::decode(&fingerprint_data)?;
+
+        let _pending = self.add_to_hash_filter(&fingerprint).await?;
+        let new_version = swap_fingerprint(&mut tx, song_id, &fingerprint, fingerprint_version).await?;
+        tx.commit().await?;
+
+        Ok(new_version)
+    }
+
+    // Two-stage matching: the inverted index cheaply ranks songs by how many
+    // distinct query hashes they share, then only the top candidates are
+    // loaded and verified with the configured scorer.
+    pub async fn find_all_matches(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+    ) -> Result<Vec<MatchResult>> {
+        self.score_candidates(query_fingerprint, self.candidate_limit, &SongFilter::default()).await
+    }
+
+    // find_all_matches among the songs `filter` lets through.
+    pub async fn find_filtered_matches(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        filter: &SongFilter,
+    ) -> Result<Vec<MatchResult>> {
+        self.score_candidates(query_fingerprint, self.candidate_limit, filter).await
+    }
+
+    // find_filtered_matches, also trying the query with its pitch scaled back
+    // by each of `shifts` percent. Candidates for those variants come from the
+    // index as usual, but are scored with hashes one bin or frame off counting
+    // as shared (AudioFingerprint::snap_to). Each song keeps the shift it
+    // scores best with.
+    pub async fn find_pitch_shifted_matches(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        filter: &SongFilter,
+        shifts: &[f64],
+    ) -> Result<Vec<MatchResult>> {
+        let mut best: HashMap<i64, MatchResult> = HashMap::new();
+        let mut keep = |m: MatchResult| match best.get(&m.song_id) {
+            Some(current) if current.confidence >= m.confidence => {}
+            _ => {
+                best.insert(m.song_id, m);
+            }
+        };
+
+        for m in self.score_candidates(query_fingerprint, self.candidate_limit, filter).await? {
+            keep(m);
+        }
+        for &percent in shifts {
+            let shifted = query_fingerprint.unshift_pitch(percent);
+            for mut m in self.score_tolerant_candidates(&shifted, self.candidate_limit, filter).await? {
+                m.pitch_shift_percent = Some(percent);
+                keep(m);
+            }
+        }
+
+        let mut matches: Vec<MatchResult> = best.into_values().collect();
+        sort_matches(&mut matches);
+        Ok(matches)
+    }
+
+    // One page of the matches found among the top `candidates` index
+    // candidates. Every page of a query scores the same candidate window, so
+    // consecutive pages neither repeat nor skip a song.
+    pub async fn find_matches_page(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        page: &MatchPageQuery,
+    ) -> Result<MatchPage> {
+        let mut matches = self
+            .score_candidates(query_fingerprint, page.candidates, &SongFilter::default())
+            .await?;
+        matches.retain(|m| m.confidence >= page.min_score);
+
+        let total = matches.len();
+        let matches = matches.into_iter().skip(page.offset).take(page.limit).collect();
+
+        Ok(MatchPage { matches, total })
+    }
+
+    // Matches above MATCH_THRESHOLD among the top `limit` candidates, best
+    // first (ties by song id, so paging is stable).
+    async fn score_candidates(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        limit: usize,
+        filter: &SongFilter,
+    ) -> Result<Vec<MatchResult>> {
+        let candidates = self.candidate_songs(query_fingerprint, limit, filter).await?;
+        self.score_songs(query_fingerprint, &candidates, false).await
+    }
+
+    // score_candidates, with the query snapped to each candidate.
+    async fn score_tolerant_candidates(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        limit: usize,
+        filter: &SongFilter,
+    ) -> Result<Vec<MatchResult>> {
+        let candidates = self.candidate_songs(query_fingerprint, limit, filter).await?;
+        self.score_songs(query_fingerprint, &candidates, true).await
+    }
+
+    async fn score_songs(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        candidates: &[i64],
+        tolerant: bool,
+    ) -> Result<Vec<MatchResult>> {
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut matches = Vec::new();
+
+        for chunk in candidates.chunks(HASH_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "SELECT id, title, artist, fingerprint_data, duration FROM songs WHERE id IN (",
+            );
+            let mut ids = query.separated(", ");
+            for song_id in chunk {
+                ids.push_bind(*song_id);
+            }
+            ids.push_unseparated(")");
+
+            for row in query.build().fetch_all(&self.pool).await? {
+                let id: i64 = row.get("id");
+                let title: String = row.get("title");
+                let artist: String = row.get("artist");
+                let fingerprint_data: Vec<u8> = row.get("fingerprint_data");
+
+                if let Ok(stored_fingerprint) = codec::decode(&fingerprint_data) {
+                    if !stored_fingerprint.comparable_with(query_fingerprint) {
+                        continue;
+                    }
+
+                    let query = if tolerant {
+                        Cow::Owned(query_fingerprint.snap_to(&stored_fingerprint))
+                    } else if self.time_diff_tolerance > 0 {
+                        Cow::Owned(query_fingerprint.snap_time_diffs(&stored_fingerprint, self.time_diff_tolerance))
+                    } else {
+                        Cow::Borrowed(query_fingerprint)
+                    };
+                    let similarity = self.scorer.score(&query, &stored_fingerprint);
+                    
+                    if similarity > MATCH_THRESHOLD {
+                        matches.push(MatchResult {
+                            song_id: id,
+                            title,
+                            artist,
+                            confidence: similarity,
+                            evidence: match_evidence(&query, &stored_fingerprint),
+                            pitch_shift_percent: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        sort_matches(&mut matches);
+        Ok(matches)
+    }
+
+    async fn candidate_songs(
+        &self,
+        query_fingerprint: &AudioFingerprint,
+        limit: usize,
+        filter: &SongFilter,
+    ) -> Result<Vec<i64>> {
+        // With a Δt tolerance every hash is also looked up with its Δt
+        // nudged, so a song shares a drifted hash in any of its forms.
+        let mut hashes: Vec<u64> = query_fingerprint
+            .hashes
+            .iter()
+            .flat_map(|&hash| time_diff_variants(hash, self.time_diff_tolerance))
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+
+        {
+            let filter = self.hash_filter.read().unwrap_or_else(|e| e.into_inner());
+            hashes.retain(|&hash| filter.may_contain(hash));
+        }
+        if hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut shared_counts: HashMap<i64, i64> = HashMap::new();
+        let versions = algorithm_versions_of(query_fingerprint.version);
+        let digests = query_fingerprint.params.comparable_digests();
+
+        for chunk in hashes.chunks(HASH_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "SELECT song_id, COUNT(DISTINCT hash) AS shared FROM song_hashes WHERE hash IN (",
+            );
+            let mut bound = query.separated(", ");
+            for &hash in chunk {
+                bound.push_bind(hash as i64);
+            }
+            bound.push_unseparated(")");
+            // Songs score_songs would skip as not comparable_with the query
+            // mustn't take candidate slots from ones it can score.
+            query.push(" AND song_id IN (SELECT id FROM songs WHERE fingerprint_version >= ");
+            query.push_bind(versions.start).push(" AND fingerprint_version < ").push_bind(versions.end);
+            query.push(" AND params_digest IN (");
+            let mut bound = query.separated(", ");
+            for digest in &digests {
+                bound.push_bind(digest.clone());
+            }
+            bound.push_unseparated("))");
+            filter.push_condition(&mut query);
+            query.push(" GROUP BY song_id");
+
+            for row in query.build().fetch_all(&self.pool).await? {
+                *shared_counts.entry(row.get("song_id")).or_default() += row.get::<i64, _>("shared");
+            }
+        }
+
+        let mut candidates: Vec<(i64, i64)> = shared_counts.into_iter().collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        candidates.truncate(limit);
+
+        Ok(candidates.into_iter().map(|(song_id, _)| song_id).collect())
+    }
+
+    pub async fn get_all_songs(&self) -> Result<Vec<SongSummary>> {
+        let songs = sqlx::query_as(&format!("{} ORDER BY created_at DESC, id DESC", SONG_SUMMARY_SELECT))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(songs)
+    }
+
+    pub async fn get_song(&self, song_id: i64) -> Result<Option<SongSummary>> {
+        let song = sqlx::query_as(&format!("{} WHERE id = ?1", SONG_SUMMARY_SELECT))
+            .bind(song_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(song)
+    }
+
+    #[cfg(feature = "graphql")]
+    pub async fn list_songs_page(&self, artist: Option<&str>, offset: i64, limit: i64) -> Result<(Vec<SongSummary>, i64)> {
+        let songs = sqlx::query_as(&format!(
+            "{} WHERE ?1 IS NULL OR artist = ?1 ORDER BY created_at DESC, id DESC LIMIT ?2 OFFSET ?3",
+            SONG_SUMMARY_SELECT
+        ))
+        .bind(artist)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM songs WHERE ?1 IS NULL OR artist = ?1")
+            .bind(artist)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok((songs, total))
+    }
+
+    #[cfg(feature = "graphql")]
+    pub async fn list_artists(&self, offset: i64, limit: i64) -> Result<(Vec<(String, i64)>, i64)> {
+        let artists = sqlx::query_as(
+            r#"
+            SELECT artist, COUNT(*) AS songs
+            FROM songs
+            GROUP BY artist
+            ORDER BY artist
+            LIMIT ?1 OFFSET ?2
+            "#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let total = sqlx::query_scalar("SELECT COUNT(DISTINCT artist) FROM songs")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok((artists, total))
+    }
+
+    #[cfg(feature = "graphql")]
+    pub async fn list_match_history(&self, song_id: Option<i64>, offset: i64, limit: i64) -> Result<(Vec<MatchRecord>, i64)> {
+        let records = sqlx::query_as(
+            r#"
+            SELECT id, song_id, song_version, confidence, experiment, created_at
+            FROM match_history
+            WHERE ?1 IS NULL OR song_id = ?1
+            ORDER BY id DESC
+            LIMIT ?2 OFFSET ?3
+            "#,
+        )
+        .bind(song_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM match_history WHERE ?1 IS NULL OR song_id = ?1")
+            .bind(song_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok((records, total))
+    }
+
+    // Bumped by triggers on every change to the songs table, whatever the
+    // code path (add, replace, rollback), so it can't miss a write.
+    pub async fn catalog_revision(&self) -> Result<i64> {
+        let revision = sqlx::query_scalar("SELECT revision FROM catalog_revision WHERE id = 1")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(revision)
+    }
+
+    // Changes logged after `since`, at most `limit` log entries' worth.
+    pub async fn catalog_changes(&self, since: i64, limit: i64) -> Result<CatalogChanges> {
+        let limit = limit.max(1);
+        let mut entries: Vec<(i64, i64, bool)> = sqlx::query_as(
+            "SELECT seq, song_id, deleted FROM catalog_changes WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+        )
+        .bind(since)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let more = entries.len() as i64 > limit;
+        entries.truncate(limit as usize);
+
+        let latest: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(seq), 0) FROM catalog_changes")
+            .fetch_one(&self.pool)
+            .await?;
+        let cursor = entries.last().map(|&(seq, _, _)| seq).unwrap_or(since);
+
+        // Only a song's last change in the page matters.
+        let mut last_change: HashMap<i64, bool> = HashMap::new();
+        for &(_, song_id, deleted) in &entries {
+            last_change.insert(song_id, deleted);
+        }
+
+        let mut deleted: Vec<i64> = last_change
+            .iter()
+            .filter(|&(_, &deleted)| deleted)
+            .map(|(&song_id, _)| song_id)
+            .collect();
+        deleted.sort_unstable();
+
+        let mut changed: Vec<i64> = last_change
+            .iter()
+            .filter(|&(_, &deleted)| !deleted)
+            .map(|(&song_id, _)| song_id)
+            .collect();
+        changed.sort_unstable();
+
+        // A song deleted after this page is simply missing here; its deletion
+        // arrives with a later page.
+        let mut songs = Vec::with_capacity(changed.len());
+        for chunk in changed.chunks(HASH_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                "SELECT id, title, artist, version, fingerprint_version, year, tags, fingerprint_data FROM songs WHERE id IN (",
+            );
+            let mut separated = query.separated(", ");
+            for id in chunk {
+                separated.push_bind(id);
+            }
+            separated.push_unseparated(") ORDER BY id");
+
+            for row in query.build().fetch_all(&self.pool).await? {
+                let id: i64 = row.get("id");
+                let fingerprint_data: Vec<u8> = row.get("fingerprint_data");
+                let fingerprint = codec::decode(&fingerprint_data)
+                    .map_err(|e| anyhow!("Failed to parse fingerprint of song {}: {}", id, e))?;
+                let tags: String = row.get("tags");
+                songs.push(SyncedSong {
+                    id,
+                    title: row.get("title"),
+                    artist: row.get("artist"),
+                    version: row.get("version"),
+                    fingerprint_version: row.get("fingerprint_version"),
+                    year: row.get("year"),
+                    tags: serde_json::from_str(&tags).unwrap_or_default(),
+                    fingerprint,
+                });
+            }
+        }
+
+        Ok(CatalogChanges {
+            cursor,
+            latest,
+            more,
+            songs,
+            deleted,
+        })
+    }
+
+    pub async fn song_ids(&self) -> Result<Vec<i64>> {
+        let ids = sqlx::query_scalar("SELECT id FROM songs ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(ids)
+    }
+
+    // Where sync from `upstream` left off; 0 for an upstream never synced
+    // from (or not the one last synced from).
+    pub async fn sync_cursor(&self, upstream: &str) -> Result<i64> {
+        let cursor = sqlx::query_scalar("SELECT cursor FROM sync_state WHERE id = 1 AND upstream = ?1")
+            .bind(upstream)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(cursor.unwrap_or(0))
+    }
+
+    // Mirrors a page of upstream changes, keeping upstream song ids, and
+    // records the new cursor in the same transaction.
+    pub async fn apply_catalog_changes(&self, upstream: &str, changes: &CatalogChanges) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let mut pending = Vec::new();
+
+        for song in &changes.songs {
+            sqlx::query(
+                r#"
+                INSERT INTO songs (id, title, artist, fingerprint_data, duration, version, fingerprint_version, params_digest, year, tags)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ON CONFLICT(id) DO UPDATE SET
+                    title = excluded.title,
+                    artist = excluded.artist,
+                    fingerprint_data = excluded.fingerprint_data,
+                    duration = excluded.duration,
+                    version = excluded.version,
+                    fingerprint_version = excluded.fingerprint_version,
+                    params_digest = excluded.params_digest,
+                    year = excluded.year,
+                    tags = excluded.tags
+                "#,
+            )
+            .bind(song.id)
+            .bind(&song.title)
+            .bind(&song.artist)
+            .bind(codec::encode(&song.fingerprint))
+            .bind(song.fingerprint.duration)
+            .bind(song.version)
+            .bind(song.fingerprint_version)
+            .bind(song.fingerprint.params.digest())
+            .bind(song.year)
+            .bind(serde_json::to_string(&song.tags)?)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query("DELETE FROM song_hashes WHERE song_id = ?1")
+                .bind(song.id)
+                .execute(&mut *tx)
+                .await?;
+            pending.push(self.add_to_hash_filter(&song.fingerprint).await?);
+            insert_postings(&mut tx, song.id, &song.fingerprint).await?;
+        }
+
+        for &song_id in &changes.deleted {
+            for statement in [
+                "DELETE FROM song_hashes WHERE song_id = ?1",
+                "DELETE FROM song_versions WHERE song_id = ?1",
+                "DELETE FROM songs WHERE id = ?1",
+            ] {
+                sqlx::query(statement).bind(song_id).execute(&mut *tx).await?;
+            }
+        }
+
+        sqlx::query("INSERT OR REPLACE INTO sync_state (id, upstream, cursor) VALUES (1, ?1, ?2)")
+            .bind(upstream)
+            .bind(changes.cursor)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // Songs fingerprinted by an older algorithm or with other hashing
+    // settings than `current`, which no longer match queries reliably.
+    pub async fn stale_songs(&self, current: &HashParams) -> Result<Vec<StaleSong>> {
+        let current_digest = current.digest();
+        let rows = sqlx::query(
+            r#"
+            SELECT id, title, artist, fingerprint_version, params_digest,
+                   (SELECT audio_path FROM ingest_jobs
+                    WHERE ingest_jobs.song_id = songs.id AND status = 'completed' AND delete_after = 0
+                    ORDER BY id DESC LIMIT 1) AS original,
+                   EXISTS (SELECT 1 FROM ingest_jobs
+                           WHERE target_song_id = songs.id AND status IN ('queued', 'running')) AS pending
+            FROM songs
+            WHERE fingerprint_version != ?1 OR params_digest IS NOT ?2
+            ORDER BY id
+            "#,
+        )
+        .bind(ALGORITHM_VERSION)
+        .bind(&current_digest)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let fingerprint_version: i64 = row.get("fingerprint_version");
+                let params_digest: Option<String> = row.get("params_digest");
+                let original: Option<String> = row.get("original");
+
+                StaleSong {
+                    id: row.get("id"),
+                    title: row.get("title"),
+                    artist: row.get("artist"),
+                    outdated_algorithm: fingerprint_version != ALGORITHM_VERSION,
+                    outdated_params: params_digest.as_deref() != Some(current_digest.as_str()),
+                    fingerprint_version,
+                    params_digest,
+                    original: original.filter(|path| Path::new(path).exists()),
+                    refingerprint_pending: row.get("pending"),
+                }
+            })
+            .collect())
+    }
+
+    pub async fn delete_song(&self, song_id: i64) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM song_hashes WHERE song_id = ?1")
+            .bind(song_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM song_versions WHERE song_id = ?1")
+            .bind(song_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM songs WHERE id = ?1
+            "#,
+        )
+        .bind(song_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn run_maintenance(&self) -> Result<MaintenanceReport> {
+        let integrity_findings: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .filter(|line: &String| line != "ok")
+            .collect();
+
+        let size_before = self.database_size().await?;
+
+        let encoded_fingerprints = self.encode_json_fingerprints().await?;
+        if encoded_fingerprints > 0 {
+            info!("Rewrote {} JSON fingerprints in binary form", encoded_fingerprints);
+        }
+
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+        let size_after = self.database_size().await?;
+
+        sqlx::query("ANALYZE").execute(&self.pool).await?;
+
+        Ok(MaintenanceReport {
+            size_before,
+            size_after,
+            reclaimed_bytes: size_before - size_after,
+            integrity_ok: integrity_findings.is_empty(),
+            integrity_findings,
+            encoded_fingerprints,
+        })
+    }
+
+    // Fingerprints stored before the binary form are JSON text; they still
+    // read fine, but take twice the space. Rows that don't parse are left as
+    // they are for verify to report. Rewriting a song counts as a catalog
+    // change, so standbys fetch each song once more.
+    async fn encode_json_fingerprints(&self) -> Result<usize> {
+        let mut encoded = 0;
+
+        for table in ["songs", "song_versions", "unidentified_clips"] {
+            let mut after = 0i64;
+            loop {
+                let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(&format!(
+                    "SELECT rowid, fingerprint_data FROM {} WHERE rowid > ?1 AND typeof(fingerprint_data) = 'text' ORDER BY rowid LIMIT ?2",
+                    table
+                ))
+                .bind(after)
+                .bind(HASH_CHUNK_SIZE as i64)
+                .fetch_all(&self.pool)
+                .await?;
+                let Some(&(last, _)) = rows.last() else {
+                    break;
+                };
+                after = last;
+
+                let mut tx = self.pool.begin().await?;
+                for (rowid, fingerprint_data) in rows {
+                    let Ok(fingerprint) = codec::decode(&fingerprint_data) else {
+                        continue;
+                    };
+                    sqlx::query(&format!("UPDATE {} SET fingerprint_data = ?1 WHERE rowid = ?2", table))
+                        .bind(codec::encode(&fingerprint))
+                        .bind(rowid)
+                        .execute(&mut *tx)
+                        .await?;
+                    encoded += 1;
+                }
+                tx.commit().await?;
+            }
+        }
+
+        Ok(encoded)
+    }
+
+    async fn database_size(&self) -> Result<i64> {
+        let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+            .fetch_one(&self.pool)
+            .await?;
+        let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(page_count * page_size)
+    }
+
+    pub async fn count_songs(&self) -> Result<i64> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM songs")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    // One row per match request; `best` is None when nothing matched, and
+    // `experiment` names the scorer variant that served it, if not the default.
+    pub async fn record_match(&self, best: Option<(i64, f64)>, experiment: Option<&str>) -> Result<()> {
+        // The matched version is recorded so a match can be traced back to the
+        // fingerprint that produced it after the song's audio is replaced.
+        sqlx::query(
+            r#"
+            INSERT INTO match_history (song_id, song_version, confidence, experiment, created_at)
+            VALUES (?1, (SELECT version FROM songs WHERE id = ?1), ?2, ?3, ?4)
+            "#,
+        )
+        .bind(best.map(|(song_id, _)| song_id))
+        .bind(best.map(|(_, confidence)| confidence))
+        .bind(experiment)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn latest_match_id(&self) -> Result<i64> {
+        let id = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM match_history")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(id)
+    }
+
+    // Match history in id order: rows after `after` up to and including
+    // `until`, so an export can resume from the last id it delivered.
+    pub async fn match_history_after(&self, after: i64, until: i64, limit: i64) -> Result<Vec<MatchHistoryEntry>> {
+        let entries = sqlx::query_as(
+            r#"
+            SELECT match_history.id, song_id, song_version, title, artist, confidence, experiment,
+                match_history.created_at
+            FROM match_history
+            LEFT JOIN songs ON songs.id = match_history.song_id
+            WHERE match_history.id > ?1 AND match_history.id <= ?2
+            ORDER BY match_history.id
+            LIMIT ?3
+            "#,
+        )
+        .bind(after)
+        .bind(until)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    pub async fn catalog_stats(&self, top_artist_count: i64) -> Result<CatalogStats> {
+        let songs = self.count_songs().await?;
+        let (postings, distinct_hashes): (i64, i64) =
+            sqlx::query_as("SELECT COUNT(*), COUNT(DISTINCT hash) FROM song_hashes")
+                .fetch_one(&self.pool)
+                .await?;
+
+        let top_artists = sqlx::query_as(
+            r#"
+            SELECT artist, COUNT(*) AS songs
+            FROM songs
+            GROUP BY artist
+            ORDER BY songs DESC, artist
+            LIMIT ?1
+            "#,
+        )
+        .bind(top_artist_count)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(CatalogStats {
+            songs,
+            postings,
+            distinct_hashes,
+            database_bytes: self.database_size().await?,
+            top_artists,
+            matches_24h: self.match_rate_since(Utc::now() - chrono::Duration::hours(24)).await?,
+            matches_7d: self.match_rate_since(Utc::now() - chrono::Duration::days(7)).await?,
+        })
+    }
+
+    async fn match_rate_since(&self, since: DateTime<Utc>) -> Result<MatchRate> {
+        let (requests, matched): (i64, i64) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*), COALESCE(SUM(song_id IS NOT NULL), 0)
+            FROM match_history
+            WHERE created_at >= ?1
+            "#,
+        )
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(MatchRate { requests, matched })
+    }
+
+    pub async fn backup_to(&self, path: &Path) -> Result<()> {
+        sqlx::query("VACUUM INTO ?1")
+            .bind(path.to_string_lossy().into_owned())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_snapshot(&self, dir: &Path) -> Result<CatalogSnapshot> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| anyhow!("Failed to create snapshot directory {}: {}", dir.display(), e))?;
+
+        let created_at = Utc::now();
+        let path = dir.join(format!("snapshot-{}.db", created_at.format("%Y%m%dT%H%M%S%.3fZ")));
+        let song_count = self.count_songs().await?;
+        self.backup_to(&path).await?;
+
+        let snapshot = sqlx::query_as(
+            r#"
+            INSERT INTO catalog_snapshots (path, song_count, created_at)
+            VALUES (?1, ?2, ?3)
+            RETURNING *
+            "#,
+        )
+        .bind(path.to_string_lossy().into_owned())
+        .bind(song_count)
+        .bind(created_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    pub async fn list_snapshots(&self) -> Result<Vec<CatalogSnapshot>> {
+        let snapshots = sqlx::query_as("SELECT * FROM catalog_snapshots ORDER BY id DESC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(snapshots)
+    }
+
+    pub async fn get_snapshot(&self, snapshot_id: i64) -> Result<Option<CatalogSnapshot>> {
+        let snapshot = sqlx::query_as("SELECT * FROM catalog_snapshots WHERE id = ?1")
+            .bind(snapshot_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(snapshot)
+    }
+
+    // Replaces the catalog (songs, their archived versions, and the hash
+    // index) with the snapshot's contents in one transaction. Job and match
+    // history are left alone: they record what happened, not catalog state.
+    pub async fn rollback_to_snapshot(&self, snapshot_id: i64) -> Result<Option<CatalogSnapshot>> {
+        let Some(snapshot) = self.get_snapshot(snapshot_id).await? else {
+            return Ok(None);
+        };
+        if !Path::new(&snapshot.path).exists() {
+            return Err(anyhow!("Snapshot file {} is missing", snapshot.path));
+        }
+
+        // ATTACH is per connection, so the whole rollback runs on one.
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("ATTACH DATABASE ?1 AS snapshot")
+            .bind(&snapshot.path)
+            .execute(&mut *conn)
+            .await?;
+
+        let result = restore_catalog_tables(&mut conn).await;
+
+        sqlx::query("DETACH DATABASE snapshot").execute(&mut *conn).await?;
+        result?;
+        drop(conn);
+
+        self.rebuild_hash_filter().await?;
+
+        Ok(Some(snapshot))
+    }
+
+    pub async fn count_unidentified(&self) -> Result<i64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM unidentified_clips")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    // Stores an unmatched clip's fingerprint and writes `snippet` (a WAV
+    // file) next to it under `dir`.
+    pub async fn capture_unidentified(
+        &self,
+        fingerprint: &AudioFingerprint,
+        snippet: &[u8],
+        dir: &Path,
+    ) -> Result<UnidentifiedClip> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| anyhow!("Failed to create unidentified directory {}: {}", dir.display(), e))?;
+
+        let created_at = Utc::now();
+        let path = dir.join(format!("clip-{}.wav", created_at.format("%Y%m%dT%H%M%S%.6fZ")));
+        tokio::fs::write(&path, snippet)
+            .await
+            .map_err(|e| anyhow!("Failed to write snippet {}: {}", path.display(), e))?;
+
+        let clip = sqlx::query_as(
+            r#"
+            INSERT INTO unidentified_clips (fingerprint_data, duration, hash_count, snippet_path, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            RETURNING id, duration, hash_count, snippet_path, created_at
+            "#,
+        )
+        .bind(codec::encode(fingerprint))
+        .bind(fingerprint.duration)
+        .bind(fingerprint.hashes.len() as i64)
+        .bind(path.to_string_lossy().into_owned())
+        .bind(created_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(clip)
+    }
+
+    pub async fn list_unidentified(&self, limit: i64) -> Result<Vec<UnidentifiedClip>> {
+        let clips = sqlx::query_as(
+            r#"
+            SELECT id, duration, hash_count, snippet_path, created_at
+            FROM unidentified_clips
+            ORDER BY id DESC
+            LIMIT ?1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clips)
+    }
+
+    pub async fn get_unidentified(&self, clip_id: i64) -> Result<Option<UnidentifiedClip>> {
+        let clip = sqlx::query_as(
+            "SELECT id, duration, hash_count, snippet_path, created_at FROM unidentified_clips WHERE id = ?1",
+        )
+        .bind(clip_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(clip)
+    }
+
+    // Removes the entry, returning it so the caller can delete its snippet.
+    pub async fn delete_unidentified(&self, clip_id: i64) -> Result<Option<UnidentifiedClip>> {
+        let clip = sqlx::query_as(
+            r#"
+            DELETE FROM unidentified_clips WHERE id = ?1
+            RETURNING id, duration, hash_count, snippet_path, created_at
+            "#,
+        )
+        .bind(clip_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(clip)
+    }
+
+    // Adds the clip to the catalog as a song under the given labels and takes
+    // it off the queue, in one transaction so the clip is never both a song
+    // and still queued, or neither. Returns the new song id and the removed
+    // entry.
+    pub async fn promote_unidentified(
+        &self,
+        clip_id: i64,
+        title: &str,
+        artist: &str,
+    ) -> Result<Option<(i64, UnidentifiedClip)>> {
+        let mut tx = self.pool.begin().await?;
+
+        let fingerprint_data: Option<Vec<u8>> =
+            sqlx::query_scalar("SELECT fingerprint_data FROM unidentified_clips WHERE id = ?1")
+                .bind(clip_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some(fingerprint_data) = fingerprint_data else {
+            return Ok(None);
+        };
+        let fingerprint = codec::decode(&fingerprint_data)?;
+
+        let clip: Option<UnidentifiedClip> = sqlx::query_as(
+            r#"
+            DELETE FROM unidentified_clips WHERE id = ?1
+            RETURNING id, duration, hash_count, snippet_path, created_at
+            "#,
+        )
+        .bind(clip_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(clip) = clip else {
+            return Ok(None);
+        };
+
+        let (song_id, _pending) =
+            self.insert_song(&mut tx, title, artist, &SongMetadata::default(), &fingerprint).await?;
+        tx.commit().await?;
+
+        Ok(Some((song_id, clip)))
+    }
+
+    // Pairs of songs scoring at least `threshold` against each other. Each
+    // song is only compared with the candidates the inverted index finds for
+    // it, songs are read DEDUPE_BATCH_SIZE at a time, and scoring runs on the
+    // blocking pool, so a large catalog neither costs O(n²) comparisons nor
+    // holds up request handling.
+    pub async fn find_duplicates(&self, threshold: f64) -> Result<Vec<(i64, i64, f64)>> {
+        let mut duplicates = Vec::new();
+        let mut after = 0i64;
+
+        loop {
+            let batch: Vec<(i64, Vec<u8>)> =
+                sqlx::query_as("SELECT id, fingerprint_data FROM songs WHERE id > ?1 ORDER BY id LIMIT ?2")
+                    .bind(after)
+                    .bind(DEDUPE_BATCH_SIZE)
+                    .fetch_all(&self.pool)
+                    .await?;
+            let Some(&(last_id, _)) = batch.last() else {
+                break;
+            };
+            after = last_id;
+
+            for (song_id, fingerprint_data) in batch {
+                let Ok(fingerprint) = codec::decode(&fingerprint_data) else {
+                    continue;
+                };
+
+                // The song finds itself too. Each pair is reported from its
+                // lower id, with that song as the query.
+                let candidates: Vec<i64> = self
+                    .candidate_songs(&fingerprint, self.candidate_limit + 1, &SongFilter::default())
+                    .await?
+                    .into_iter()
+                    .filter(|&id| id > song_id)
+                    .collect();
+                if candidates.is_empty() {
+                    continue;
+                }
+
+                let others = self.load_fingerprints(&candidates).await?;
+                let scorer = self.scorer.clone();
+                let found = tokio::task::spawn_blocking(move || {
+                    others
+                        .into_iter()
+                        .filter(|(_, other)| fingerprint.comparable_with(other))
+                        .map(|(other_id, other)| (song_id, other_id, scorer.score(&fingerprint, &other)))
+                        .filter(|&(_, _, similarity)| similarity >= threshold)
+                        .collect::<Vec<_>>()
+                })
+                .await?;
+                duplicates.extend(found);
+            }
+        }
+
+        duplicates.sort_by_key(|&(first, second, _)| (first, second));
+        Ok(duplicates)
+    }
+
+    // The fingerprints of `song_ids` that decode.
+    async fn load_fingerprints(&self, song_ids: &[i64]) -> Result<Vec<(i64, AudioFingerprint)>> {
+        let mut fingerprints = Vec::new();
+
+        for chunk in song_ids.chunks(HASH_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new("SELECT id, fingerprint_data FROM songs WHERE id IN (");
+            let mut ids = query.separated(", ");
+            for song_id in chunk {
+                ids.push_bind(*song_id);
+            }
+            ids.push_unseparated(")");
+
+            for row in query.build().fetch_all(&self.pool).await? {
+                let fingerprint_data: Vec<u8> = row.get("fingerprint_data");
+                if let Ok(fingerprint) = codec::decode(&fingerprint_data) {
+                    fingerprints.push((row.get("id"), fingerprint));
+                }
+            }
+        }
+
+        Ok(fingerprints)
+    }
+
+    pub async fn insert_ingest_job(&self, job: NewIngestJob<'_>) -> Result<i64> {
+        let now = Utc::now();
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO ingest_jobs
+                (title, artist, audio_path, status, max_attempts, next_attempt_at, created_at, updated_at,
+                 import_id, delete_after, target_song_id)
+            VALUES (?1, ?2, ?3, 'queued', ?4, ?5, ?5, ?5, ?6, ?7, ?8)
+            "#,
+        )
+        .bind(job.title)
+        .bind(job.artist)
+        .bind(job.audio_path)
+        .bind(job.max_attempts as i64)
+        .bind(now)
+        .bind(job.import_id)
+        .bind(job.delete_after)
+        .bind(job.target_song_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn create_import(&self, source: &str) -> Result<i64> {
+        let result = sqlx::query("INSERT INTO ingest_imports (source, created_at) VALUES (?1, ?2)")
+            .bind(source)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    // ETA extrapolates the import's average throughput so far.
+    pub async fn import_progress(&self, import_id: i64) -> Result<Option<ImportProgress>> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                i.source AS source,
+                i.created_at AS created_at,
+                COUNT(j.id) AS total,
+                COALESCE(SUM(j.status = 'completed'), 0) AS completed,
+                COALESCE(SUM(j.status IN ('dead_letter', 'discarded')), 0) AS failed
+            FROM ingest_imports i
+            LEFT JOIN ingest_jobs j ON j.import_id = i.id
+            WHERE i.id = ?1
+            GROUP BY i.id
+            "#,
+        )
+        .bind(import_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            let started_at: DateTime<Utc> = row.get("created_at");
+            let total: i64 = row.get("total");
+            let completed: i64 = row.get("completed");
+            let failed: i64 = row.get("failed");
+            let done = completed + failed;
+            let remaining = total - done;
+
+            let elapsed = (Utc::now() - started_at).num_milliseconds().max(0);
+            let eta_secs = (done > 0).then(|| elapsed * remaining / done / 1000);
+
+            ImportProgress {
+                import_id,
+                source: row.get("source"),
+                total,
+                completed,
+                failed,
+                remaining,
+                started_at,
+                eta_secs,
+            }
+        }))
+    }
+
+    // Jobs left `running` by a crash or restart go back to the queue.
+    pub async fn requeue_running_jobs(&self) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE ingest_jobs SET status = 'queued', updated_at = ?1 WHERE status = 'running'
+            "#,
+        )
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Files any job other than a discarded one has been created for.
+    pub async fn ingest_job_paths(&self) -> Result<HashSet<String>> {
+        let paths: Vec<String> =
+            sqlx::query_scalar("SELECT DISTINCT audio_path FROM ingest_jobs WHERE status != 'discarded'")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(paths.into_iter().collect())
+    }
+
+    pub async fn claim_next_job(&self) -> Result<Option<IngestJob>> {
+        let now = Utc::now();
+
+        let job = sqlx::query_as::<_, IngestJob>(
+            r#"
+            UPDATE ingest_jobs
+            SET status = 'running', attempts = attempts + 1, updated_at = ?1
+            WHERE id = (
+                SELECT id FROM ingest_jobs
+                WHERE status = 'queued' AND next_attempt_at <= ?1
+                ORDER BY next_attempt_at, id
+                LIMIT 1
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn complete_job(&self, job_id: i64, song_id: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE ingest_jobs
+            SET status = 'completed', song_id = ?2, last_error = NULL, updated_at = ?3
+            WHERE id = ?1
+            "#,
+        )
+        .bind(job_id)
+        .bind(song_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // With `retry_at` the job is requeued for that time; without it the job
+    // moves to the dead-letter state.
+    pub async fn fail_job(&self, job_id: i64, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<()> {
+        let now = Utc::now();
+
+        sqlx::query(
+            r#"
+            UPDATE ingest_jobs
+            SET status = ?2, last_error = ?3, next_attempt_at = ?4, updated_at = ?5
+            WHERE id = ?1
+            "#,
+        )
+        .bind(job_id)
+        .bind(if retry_at.is_some() { "queued" } else { "dead_letter" })
+        .bind(error)
+        .bind(retry_at.unwrap_or(now))
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn requeue_dead_letter_job(&self, job_id: i64) -> Result<bool> {
+        let now = Utc::now();
+
+        let result = sqlx::query(
+            r#"
+            UPDATE ingest_jobs
+            SET status = 'queued', attempts = 0, next_attempt_at = ?2, updated_at = ?2
+            WHERE id = ?1 AND status = 'dead_letter'
+            "#,
+        )
+        .bind(job_id)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
         Ok(result.rows_affected() > 0)
     }
-}
\ No newline at end of file
+
+    // Returns the audio path and whether the job owns it, so the caller can
+    // delete spooled files.
+    pub async fn discard_dead_letter_job(&self, job_id: i64) -> Result<Option<(String, bool)>> {
+        let discarded = sqlx::query_as(
+            r#"
+            UPDATE ingest_jobs
+            SET status = 'discarded', updated_at = ?2
+            WHERE id = ?1 AND status = 'dead_letter'
+            RETURNING audio_path, delete_after
+            "#,
+        )
+        .bind(job_id)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(discarded)
+    }
+
+    pub async fn list_jobs(&self, status: Option<&str>, limit: i64) -> Result<Vec<IngestJob>> {
+        let jobs = sqlx::query_as::<_, IngestJob>(
+            r#"
+            SELECT * FROM ingest_jobs
+            WHERE ?1 IS NULL OR status = ?1
+            ORDER BY id DESC
+            LIMIT ?2
+            "#,
+        )
+        .bind(status)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    pub async fn get_job(&self, job_id: i64) -> Result<Option<IngestJob>> {
+        let job = sqlx::query_as::<_, IngestJob>("SELECT * FROM ingest_jobs WHERE id = ?1")
+            .bind(job_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(job)
+    }
+}
+
+const CATALOG_TABLES: [&str; 3] = ["songs", "song_versions", "song_hashes"];
+
+async fn restore_catalog_tables(conn: &mut sqlx::SqliteConnection) -> Result<()> {
+    let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+
+    for table in CATALOG_TABLES {
+        // Copy only columns both sides have, so snapshots taken before a
+        // column was added still restore (the column gets its default).
+        let columns: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT name FROM pragma_table_info('{table}', 'main') WHERE name IN (SELECT name FROM pragma_table_info('{table}', 'snapshot'))"
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!("DELETE FROM main.{}", table)).execute(&mut *tx).await?;
+
+        if !columns.is_empty() {
+            let columns = columns.join(", ");
+            sqlx::query(&format!(
+                "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM snapshot.{table}"
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// `fingerprint_version` is the ALGORITHM_VERSION that produced `fingerprint`.
+async fn swap_fingerprint(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    song_id: i64,
+    fingerprint: &AudioFingerprint,
+    fingerprint_version: i64,
+) -> Result<Option<i64>> {
+    let current: Option<(Vec<u8>, f64, i64, i64)> =
+        sqlx::query_as("SELECT fingerprint_data, duration, version, fingerprint_version FROM songs WHERE id = ?1")
+            .bind(song_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+    let Some((fingerprint_data, duration, version, current_fingerprint_version)) = current else {
+        return Ok(None);
+    };
+
+    sqlx::query(
+        r#"
+        INSERT OR REPLACE INTO song_versions (song_id, version, fingerprint_data, duration, archived_at, fingerprint_version)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        "#,
+    )
+    .bind(song_id)
+    .bind(version)
+    .bind(&fingerprint_data)
+    .bind(duration)
+    .bind(Utc::now())
+    .bind(current_fingerprint_version)
+    .execute(&mut **tx)
+    .await?;
+
+    // Version numbers only ever grow, so a restored fingerprint gets a new
+    // number rather than reusing the one it was archived under.
+    let new_version: i64 = sqlx::query_scalar(
+        "SELECT MAX(version) + 1 FROM (SELECT version FROM songs WHERE id = ?1 UNION ALL SELECT version FROM song_versions WHERE song_id = ?1)",
+    )
+    .bind(song_id)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE songs SET fingerprint_data = ?1, duration = ?2, version = ?3, fingerprint_version = ?4, params_digest = ?5 WHERE id = ?6",
+    )
+    .bind(codec::encode(fingerprint))
+    .bind(fingerprint.duration)
+    .bind(new_version)
+    .bind(fingerprint_version)
+    .bind(fingerprint.params.digest())
+    .bind(song_id)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query("DELETE FROM song_hashes WHERE song_id = ?1")
+        .bind(song_id)
+        .execute(&mut **tx)
+        .await?;
+    insert_postings(tx, song_id, fingerprint).await?;
+
+    Ok(Some(new_version))
+}
+
+// By similarity in descending order, ties by song id.
+fn sort_matches(matches: &mut [MatchResult]) {
+    matches.sort_by(|a, b| {
+        b.confidence
+            .partial_cmp(&a.confidence)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.song_id.cmp(&b.song_id))
+    });
+}
+
+fn expected_postings(fingerprint: &AudioFingerprint) -> Vec<(i64, i64)> {
+    fingerprint
+        .hashes
+        .iter()
+        .enumerate()
+        .map(|(i, &hash)| (hash as i64, fingerprint.offsets.get(i).copied().unwrap_or(0) as i64))
+        .collect()
+}
+
+async fn insert_postings(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    song_id: i64,
+    fingerprint: &AudioFingerprint,
+) -> Result<()> {
+    let postings = expected_postings(fingerprint);
+
+    for chunk in postings.chunks(HASH_CHUNK_SIZE) {
+        let mut query = QueryBuilder::<Sqlite>::new("INSERT INTO song_hashes (hash, song_id, offset) ");
+        query.push_values(chunk, |mut row, &(hash, offset)| {
+            row.push_bind(hash).push_bind(song_id).push_bind(offset);
+        });
+        query.build().execute(&mut **tx).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Result};
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::io::Read;
+use tracing::warn;
+
+use crate::config::UploadConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+// Decodes `Content-Encoding: gzip` / `zstd` request bodies before any
+// extractor sees them, so handlers and their body limits work on the plain
+// upload. Like ZIP extraction, the limits are enforced on the bytes actually
+// inflated, and a body that inflates too far relative to its compressed size
+// is rejected outright.
+pub async fn decompress_request(State(config): State<UploadConfig>, request: Request, next: Next) -> Response {
+    let encoding = match request.headers().get(header::CONTENT_ENCODING) {
+        None => return next.run(request).await,
+        Some(value) => match value.to_str().map(|v| v.trim().to_ascii_lowercase()) {
+            Ok(v) if v == "identity" => return next.run(request).await,
+            Ok(v) if v == "gzip" || v == "x-gzip" => Encoding::Gzip,
+            Ok(v) if v == "zstd" => Encoding::Zstd,
+            _ => return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
+        },
+    };
+
+    let (mut parts, body) = request.into_parts();
+
+    let compressed = match to_bytes(body, config.max_decompressed_bytes).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+
+    let limits = config.clone();
+    let decoded = tokio::task::spawn_blocking(move || decode(encoding, &compressed, &limits)).await;
+
+    let decoded = match decoded {
+        Ok(Ok(decoded)) => decoded,
+        Ok(Err(e)) => {
+            warn!("Rejected compressed upload: {}", e);
+            return match e.downcast_ref::<LimitExceeded>() {
+                Some(_) => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+                None => StatusCode::BAD_REQUEST.into_response(),
+            };
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.insert(header::CONTENT_LENGTH, decoded.len().into());
+
+    next.run(Request::from_parts(parts, Body::from(decoded))).await
+}
+
+#[derive(Debug)]
+struct LimitExceeded(String);
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+fn decode(encoding: Encoding, compressed: &[u8], config: &UploadConfig) -> Result<Vec<u8>> {
+    let limit = config.max_decompressed_bytes as u64;
+    let mut decoded = Vec::new();
+
+    let result = match encoding {
+        Encoding::Gzip => flate2::read::MultiGzDecoder::new(compressed)
+            .take(limit + 1)
+            .read_to_end(&mut decoded),
+        Encoding::Zstd => zstd::stream::read::Decoder::new(compressed)?
+            .take(limit + 1)
+            .read_to_end(&mut decoded),
+    };
+    result.map_err(|e| anyhow!("Failed to decode {:?} body: {}", encoding, e))?;
+
+    let size = decoded.len() as u64;
+    if size > limit {
+        return Err(LimitExceeded(format!("decoded body exceeds {} bytes", limit)).into());
+    }
+    if size / (compressed.len() as u64).max(1) > config.max_compression_ratio {
+        return Err(LimitExceeded("body has a suspicious compression ratio".to_string()).into());
+    }
+
+    Ok(decoded)
+}
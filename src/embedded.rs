@@ -0,0 +1,125 @@
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::config::Config;
+use crate::database::{Database, MatchResult};
+use crate::fingerprint::AudioFingerprint;
+use crate::jobs::JobQueue;
+use crate::scoring::MatchTier;
+
+// The matcher as a library, for applications that ship it in-process (a
+// desktop app, say) instead of talking to the HTTP service. It owns the
+// catalog database and the ingest workers, which run on the caller's tokio
+// runtime.
+#[derive(Clone)]
+pub struct EmbeddedMatcher {
+    db: Database,
+    config: Arc<Config>,
+    jobs: JobQueue,
+}
+
+#[derive(Debug, Clone)]
+pub struct Identification {
+    pub best: MatchResult,
+    pub tier: MatchTier,
+}
+
+// Background rescanning of one folder; stops when dropped.
+pub struct FolderWatch(JoinHandle<()>);
+
+impl Drop for FolderWatch {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl EmbeddedMatcher {
+    // Opens (creating if missing) the catalog at `path` with default settings.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let config = Config {
+            database_url: format!("sqlite:{}", path.as_ref().display()),
+            ..Config::default()
+        };
+        Self::with_config(config).await
+    }
+
+    pub async fn with_config(config: Config) -> Result<Self> {
+        let encryption_key = config.encryption.resolve_key()?;
+        let db = Database::new(&config.database_url, encryption_key.as_deref())
+            .await?
+            .with_scorer(config.matching.scorer)
+            .with_candidate_limit(config.matching.candidates)
+            .with_time_diff_tolerance(config.matching.time_diff_tolerance);
+        db.init().await?;
+
+        let config = Arc::new(config);
+        let jobs = JobQueue::new(db.clone(), config.clone());
+        jobs.start_worker().await?;
+
+        Ok(EmbeddedMatcher { db, config, jobs })
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    // Best catalog match for the audio file at `path`, or None when nothing
+    // clears the match cutoff.
+    pub async fn identify(&self, path: impl AsRef<Path>) -> Result<Option<Identification>> {
+        let fingerprint = self.fingerprint_file(path.as_ref()).await?;
+        let best = self.db.find_all_matches(&fingerprint).await?.into_iter().next();
+
+        Ok(best.map(|best| Identification {
+            tier: self.config.matching.tiers.classify(best.confidence, &best.evidence),
+            best,
+        }))
+    }
+
+    // Fingerprints and stores one file right away, returning the song id.
+    pub async fn add_file(&self, path: impl AsRef<Path>, title: &str, artist: &str) -> Result<i64> {
+        let fingerprint = self.fingerprint_file(path.as_ref()).await?;
+        self.db.add_song(title, artist, &fingerprint).await
+    }
+
+    // Queues the audio files in `dir` that haven't been queued before for
+    // background ingestion, returning how many were new.
+    pub async fn scan_folder(&self, dir: impl AsRef<Path>) -> Result<usize> {
+        self.jobs.enqueue_new_files(dir.as_ref()).await
+    }
+
+    // Scans `dir` now and then every `interval`, until the returned handle
+    // is dropped.
+    pub fn watch_folder(&self, dir: impl Into<PathBuf>, interval: Duration) -> FolderWatch {
+        let matcher = self.clone();
+        let dir = dir.into();
+
+        FolderWatch(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = matcher.scan_folder(&dir).await {
+                    error!("Failed to scan {}: {}", dir.display(), e);
+                }
+            }
+        }))
+    }
+
+    async fn fingerprint_file(&self, path: &Path) -> Result<AudioFingerprint> {
+        let audio_data = tokio::fs::read(path)
+            .await
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let hashing = self.config.hashing;
+        let config = self.config.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let audio = crate::upload::decode_upload(&audio_data, &config, &crate::audio::Selection::default())?;
+            crate::fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &hashing)
+        })
+        .await
+        .map_err(|e| anyhow!("Fingerprint task failed: {}", e))?
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::database::Database;
+use crate::scoring::{MatchTier, MATCH_THRESHOLD};
+use crate::{audio, fingerprint};
+
+// Confidence thresholds the precision/recall curve is reported at. The first
+// is the cutoff find_all_matches already applies.
+const THRESHOLDS: [f64; 7] = [MATCH_THRESHOLD, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+
+// One manifest row: an audio file and the song it should match, or None for
+// a query that shouldn't match anything in the catalog.
+#[derive(Debug, Clone)]
+pub struct LabeledQuery {
+    pub path: PathBuf,
+    pub expected: Option<i64>,
+}
+
+// Reads a CSV manifest with a `query,expected_song_id` header. Query paths
+// are relative to the manifest's directory; an empty song id marks a
+// negative query.
+pub fn load_manifest(manifest: &Path) -> Result<Vec<LabeledQuery>> {
+    let base = manifest.parent().unwrap_or(Path::new("."));
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .comment(Some(b'#'))
+        .from_path(manifest)
+        .map_err(|e| anyhow!("Failed to read manifest {}: {}", manifest.display(), e))?;
+
+    let mut queries = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| anyhow!("Invalid manifest row {}: {}", line + 2, e))?;
+        let (Some(path), Some(expected)) = (record.get(0), record.get(1)) else {
+            return Err(anyhow!("Manifest row {} needs query and expected_song_id", line + 2));
+        };
+
+        let expected = match expected.trim() {
+            "" => None,
+            id => Some(
+                id.parse()
+                    .map_err(|_| anyhow!("Manifest row {}: invalid song id {:?}", line + 2, id))?,
+            ),
+        };
+
+        queries.push(LabeledQuery {
+            path: base.join(path.trim()),
+            expected,
+        });
+    }
+
+    if queries.is_empty() {
+        return Err(anyhow!("Manifest {} has no queries", manifest.display()));
+    }
+
+    Ok(queries)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryOutcome {
+    pub path: String,
+    pub expected: Option<i64>,
+    pub predicted: Option<i64>,
+    pub confidence: Option<f64>,
+    pub tier: Option<MatchTier>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl QueryOutcome {
+    fn is_correct(&self) -> bool {
+        self.predicted.is_some() && self.predicted == self.expected
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ThresholdPoint {
+    pub threshold: f64,
+    pub accepted: usize,
+    pub precision: f64,
+    pub recall: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfidenceBucket {
+    pub min_confidence: f64,
+    pub predictions: usize,
+    pub correct: usize,
+}
+
+// How often top matches in each tier were right, i.e. whether the tier
+// thresholds deliver the precision automation relies on.
+#[derive(Debug, Clone, Serialize)]
+pub struct TierBucket {
+    pub tier: MatchTier,
+    pub predictions: usize,
+    pub correct: usize,
+    pub precision: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluationReport {
+    pub queries: usize,
+    pub positives: usize,
+    pub negatives: usize,
+    pub errors: usize,
+    pub correct: usize,
+    // Top match was a different song than expected, or a negative matched.
+    pub wrong: usize,
+    // An expected song that produced no match at all.
+    pub missed: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub curve: Vec<ThresholdPoint>,
+    pub calibration: Vec<ConfidenceBucket>,
+    pub tiers: Vec<TierBucket>,
+    pub outcomes: Vec<QueryOutcome>,
+}
+
+// Runs every query through the same decode → fingerprint → match path as
+// POST /match, without recording anything in the match history.
+pub async fn evaluate(db: &Database, config: &Config, queries: &[LabeledQuery]) -> Result<EvaluationReport> {
+    let mut outcomes = Vec::with_capacity(queries.len());
+
+    for query in queries {
+        let result = async {
+            let data = std::fs::read(&query.path)?;
+            let audio = audio::decode_audio(&data, &config.limits)?;
+            let fingerprint = fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &config.hashing)?;
+            db.find_all_matches(&fingerprint).await
+        }
+        .await;
+
+        let (top, error) = match result {
+            Ok(matches) => (matches.into_iter().next(), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        outcomes.push(QueryOutcome {
+            path: query.path.display().to_string(),
+            expected: query.expected,
+            predicted: top.as_ref().map(|m| m.song_id),
+            confidence: top.as_ref().map(|m| m.confidence),
+            tier: top.as_ref().map(|m| config.matching.tiers.classify(m.confidence, &m.evidence)),
+            error,
+        });
+    }
+
+    Ok(summarize(outcomes))
+}
+
+fn summarize(outcomes: Vec<QueryOutcome>) -> EvaluationReport {
+    let positives = outcomes.iter().filter(|o| o.expected.is_some()).count();
+    let correct = outcomes.iter().filter(|o| o.is_correct()).count();
+    let predicted = outcomes.iter().filter(|o| o.predicted.is_some()).count();
+    let missed = outcomes
+        .iter()
+        .filter(|o| o.expected.is_some() && o.predicted.is_none())
+        .count();
+
+    let curve = THRESHOLDS
+        .iter()
+        .map(|&threshold| {
+            let accepted: Vec<&QueryOutcome> = outcomes
+                .iter()
+                .filter(|o| o.confidence.is_some_and(|c| c >= threshold))
+                .collect();
+            let correct = accepted.iter().filter(|o| o.is_correct()).count();
+
+            ThresholdPoint {
+                threshold,
+                accepted: accepted.len(),
+                precision: ratio(correct, accepted.len()),
+                recall: ratio(correct, positives),
+            }
+        })
+        .collect();
+
+    let calibration = THRESHOLDS
+        .iter()
+        .enumerate()
+        .map(|(i, &min_confidence)| {
+            let max_confidence = THRESHOLDS.get(i + 1).copied().unwrap_or(f64::INFINITY);
+            let in_bucket: Vec<&QueryOutcome> = outcomes
+                .iter()
+                .filter(|o| o.confidence.is_some_and(|c| c >= min_confidence && c < max_confidence))
+                .collect();
+
+            ConfidenceBucket {
+                min_confidence,
+                predictions: in_bucket.len(),
+                correct: in_bucket.iter().filter(|o| o.is_correct()).count(),
+            }
+        })
+        .collect();
+
+    let tiers = [MatchTier::Certain, MatchTier::Probable, MatchTier::Weak]
+        .into_iter()
+        .map(|tier| {
+            let in_tier: Vec<&QueryOutcome> = outcomes.iter().filter(|o| o.tier == Some(tier)).collect();
+            let correct = in_tier.iter().filter(|o| o.is_correct()).count();
+
+            TierBucket {
+                tier,
+                predictions: in_tier.len(),
+                correct,
+                precision: ratio(correct, in_tier.len()),
+            }
+        })
+        .collect();
+
+    EvaluationReport {
+        queries: outcomes.len(),
+        positives,
+        negatives: outcomes.len() - positives,
+        errors: outcomes.iter().filter(|o| o.error.is_some()).count(),
+        correct,
+        wrong: predicted - correct,
+        missed,
+        precision: ratio(correct, predicted),
+        recall: ratio(correct, positives),
+        curve,
+        calibration,
+        tiers,
+        outcomes,
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use crate::audio::{self, ChannelOutOfRange, DecodedAudio, LimitExceeded, Selection, UnsupportedFormat, WindowOutOfRange};
+use crate::config::{AudioLimits, FfmpegConfig};
+
+// Room for the WAV header on top of the samples the size limit allows.
+const HEADER_ALLOWANCE: usize = 64 * 1024;
+// ffmpeg's log is only read for the input's container and codec names.
+const MAX_LOG_BYTES: u64 = 64 * 1024;
+
+// Gives an upload that failed native decoding with UnsupportedFormat to
+// ffmpeg instead; any other result is returned as it is. If ffmpeg can't
+// decode it either, the upload is still unsupported and its error says why.
+pub fn fallback(
+    native: Result<DecodedAudio>,
+    audio_data: &[u8],
+    limits: &AudioLimits,
+    selection: &Selection,
+    config: &FfmpegConfig,
+) -> Result<DecodedAudio> {
+    let unsupported = match native {
+        Err(e) if e.is::<UnsupportedFormat>() => e,
+        other => return other,
+    };
+
+    match decode(audio_data, limits, selection, config) {
+        Ok(audio) => Ok(audio),
+        Err(e) if e.is::<LimitExceeded>() || e.is::<WindowOutOfRange>() || e.is::<ChannelOutOfRange>() => Err(e),
+        Err(e) => Err(UnsupportedFormat(format!("{} (ffmpeg fallback: {})", unsupported, e)).into()),
+    }
+}
+
+// Converts the upload to WAV with ffmpeg and decodes that natively, so the
+// selection and limits apply as they would to any other upload. The WAV
+// ffmpeg may write is capped at what the size limit allows for the upload.
+fn decode(audio_data: &[u8], limits: &AudioLimits, selection: &Selection, config: &FfmpegConfig) -> Result<DecodedAudio> {
+    let max_output = (audio_data.len() as u64)
+        .saturating_mul(limits.max_samples_per_byte)
+        .saturating_mul(2)
+        .saturating_add(HEADER_ALLOWANCE as u64);
+
+    let mut child = Command::new(&config.path)
+        .env_clear()
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .args(["-nostdin", "-nostats", "-hide_banner", "-loglevel", "info"])
+        .args(["-i", "pipe:0", "-map", "0:a:0", "-vn", "-f", "wav", "-acodec", "pcm_s16le", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start ffmpeg: {}", e))?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("ffmpeg stdin unavailable"))?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("ffmpeg stdout unavailable"))?;
+    let mut stderr = child.stderr.take().ok_or_else(|| anyhow!("ffmpeg stderr unavailable"))?;
+    let child = Mutex::new(child);
+
+    let (output, log, timed_out) = std::thread::scope(|scope| {
+        // ffmpeg may stop reading early, e.g. on an input it can't parse, so
+        // a failed write is left for its exit status to explain.
+        scope.spawn(move || {
+            let _ = stdin.write_all(audio_data);
+        });
+        // The log past the cap is still drained so ffmpeg never blocks on it.
+        let log = scope.spawn(move || {
+            let mut log = Vec::new();
+            let _ = (&mut stderr).take(MAX_LOG_BYTES).read_to_end(&mut log);
+            let _ = std::io::copy(&mut stderr, &mut std::io::sink());
+            String::from_utf8_lossy(&log).into_owned()
+        });
+        let (done, finished) = mpsc::channel::<()>();
+        let child = &child;
+        let watchdog = scope.spawn(move || {
+            let timed_out = finished.recv_timeout(Duration::from_secs(config.timeout_secs)).is_err();
+            if timed_out {
+                kill(child);
+            }
+            timed_out
+        });
+
+        let output = read_capped(stdout, max_output);
+        if output.is_err() {
+            kill(child);
+        }
+        let _ = done.send(());
+        (output, log.join().unwrap_or_default(), watchdog.join().unwrap_or(false))
+    });
+
+    let status = child
+        .into_inner()
+        .map_err(|_| anyhow!("ffmpeg process lock poisoned"))?
+        .wait()
+        .map_err(|e| anyhow!("Failed to wait for ffmpeg: {}", e))?;
+    if timed_out {
+        return Err(anyhow!("ffmpeg timed out after {}s", config.timeout_secs));
+    }
+    let mut output = output?;
+    if !status.success() {
+        let reason = log.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("");
+        return Err(anyhow!("ffmpeg exited with {}: {}", status, reason.trim()));
+    }
+
+    seal_wav(&mut output)?;
+    let mut audio = audio::decode_audio_selection(&output, limits, selection)?;
+    let (container, codec) = input_format(&log);
+    audio.source.container = container;
+    audio.source.codec = codec;
+    Ok(audio)
+}
+
+fn kill(child: &Mutex<Child>) {
+    if let Ok(mut child) = child.lock() {
+        let _ = child.kill();
+    }
+}
+
+fn read_capped(mut stdout: impl Read, max_bytes: u64) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    (&mut stdout)
+        .take(max_bytes + 1)
+        .read_to_end(&mut output)
+        .map_err(|e| anyhow!("Failed to read ffmpeg output: {}", e))?;
+    if output.len() as u64 > max_bytes {
+        return Err(LimitExceeded(format!(
+            "Audio decoded by ffmpeg exceeds the {} bytes allowed for this upload",
+            max_bytes
+        ))
+        .into());
+    }
+    Ok(output)
+}
+
+// ffmpeg can't seek back on a pipe to fill in the RIFF and data chunk sizes,
+// so it leaves them at 0xFFFFFFFF. The output is complete here, so they are
+// set from its length.
+fn seal_wav(wav: &mut [u8]) -> Result<()> {
+    let len = wav.len();
+    if len < 12 || &wav[..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(anyhow!("ffmpeg produced no WAV output"));
+    }
+    wav[4..8].copy_from_slice(&((len - 8) as u32).to_le_bytes());
+
+    let mut pos = 12;
+    while pos + 8 <= len {
+        let chunk_len = u32::from_le_bytes([wav[pos + 4], wav[pos + 5], wav[pos + 6], wav[pos + 7]]) as usize;
+        if &wav[pos..pos + 4] == b"data" {
+            wav[pos + 4..pos + 8].copy_from_slice(&((len - pos - 8) as u32).to_le_bytes());
+            return Ok(());
+        }
+        pos = pos.saturating_add(8).saturating_add(chunk_len).saturating_add(chunk_len & 1);
+    }
+
+    Err(anyhow!("ffmpeg produced no audio"))
+}
+
+// The input's container and audio codec as ffmpeg names them, from the lines
+// it logs before the output, e.g. `Input #0, asf, from 'pipe:0':` and
+// `Stream #0:0: Audio: wmav2 (a[1][0][0] / 0x0161), 44100 Hz, ...`.
+fn input_format(log: &str) -> (String, String) {
+    let input = log.split("Output #0").next().unwrap_or("");
+    let container = input
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Input #0, "))
+        .and_then(|rest| rest.split(", from").next())
+        .unwrap_or("ffmpeg");
+    let codec = input
+        .lines()
+        .find_map(|line| line.split_once("Audio: ").map(|(_, rest)| rest))
+        .and_then(|rest| rest.split([' ', ',']).next())
+        .unwrap_or("unknown");
+
+    (container.to_string(), codec.to_string())
+}
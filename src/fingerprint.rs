@@ -1,73 +1,671 @@
 use anyhow::{anyhow, Result};
-use ndarray::Array2;
-use rustfft::{FftPlanner, num_complex::Complex};
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use rayon::prelude::*;
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::audio::{BandPass, BandPassFilter, ResampleQuality, Resampler};
 
 const SAMPLE_RATE: u32 = 11025;
-const WINDOW_SIZE: usize = 1024;
-const HOP_SIZE: usize = 512;
-const FREQ_BINS: usize = 512;
+const MAX_HASH_WEIGHT: u8 = 8;
+// Frames quieter than this RMS (about -60 dBFS after peak normalization) are
+// silence or room tone, far below the strongest peaks that hashes are built
+// from, so they skip the FFT and stay zero in the spectrogram.
+const SILENT_FRAME_RMS: f32 = 0.001;
+// How long a block of peaks competing for `max_peaks` in a FingerprintBuilder
+// with PeakPicking::Strongest is: about as long as a query clip.
+const STREAM_BLOCK_SECS: f64 = 10.0;
+
+// Bumped whenever a change to the pipeline alters the hashes produced for
+// the same audio, so songs fingerprinted by an older build can be found and
+// re-fingerprinted. Stored per song; 0 means "before versions were tracked".
+// 2: multichannel audio is downmixed to mono instead of read interleaved.
+// 3: audio is resampled from its decoded rate instead of assumed 44.1 kHz.
+// 4: audio is low-pass filtered while downsampling (HashParams::resample).
+// 5: 64-bit hashes (HASH_FORMAT 2).
+pub const ALGORITHM_VERSION: i64 = 5;
+
+// The ALGORITHM_VERSIONs whose fingerprints use HASH_FORMAT `format`, so
+// stored songs can be filtered by format without decoding them. Extend it
+// whenever HASH_FORMAT is bumped.
+pub fn algorithm_versions_of(format: u32) -> std::ops::Range<i64> {
+    match format {
+        1 => 0..5,
+        2 => 5..i64::MAX,
+        _ => 0..0,
+    }
+}
+
+// Layout of the hash values themselves. Hashes in different formats mean
+// different things, so fingerprints are only compared within one format.
+// Bumping it also bumps ALGORITHM_VERSION, so /admin/stale-songs lists every
+// song that has to be re-fingerprinted before it can match again.
+// 1: 32 bits, `freq1 << 16 | freq2 << 8 | Δt`. Bins above 255 overflowed
+//    freq2 into freq1's field, so unrelated peak pairs could share a hash.
+// 2: 64 bits, see hash_peak_pair.
+pub const HASH_FORMAT: u32 = 2;
+
+// Telephony's rate. Lower source rates are upsampled to the analysis rate, but
+// leave the analysis band empty above their own Nyquist frequency, and below
+// 8 kHz too little of it is left to fingerprint.
+pub const MIN_SAMPLE_RATE: u32 = 8000;
+
+// Settings that change which hashes a given recording produces. They are
+// stored with every fingerprint, since hashes generated with different
+// settings can't be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HashParams {
+    // Frames per Δt unit in a hash. Larger steps tolerate small timing
+    // jitter between query and reference at the cost of more collisions.
+    pub time_delta_step: u32,
+    // Trades resampling cost against aliasing in the analysis band.
+    pub resample: ResampleQuality,
+    // Applied at the analysis rate, before the spectrogram. Left out of the
+    // serialized form when unset, so existing digests don't change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub band_pass: Option<BandPass>,
+    // Left out of the serialized form at its defaults, for the same reason.
+    #[serde(skip_serializing_if = "FingerprintConfig::is_default")]
+    pub analysis: FingerprintConfig,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            time_delta_step: 1,
+            resample: ResampleQuality::default(),
+            band_pass: None,
+            analysis: FingerprintConfig::default(),
+        }
+    }
+}
+
+// Spectrogram layout and how many hashes are made from it: a larger target
+// zone or fan-out finds noisy queries more often at the cost of index size.
+// Frame offsets and Δt are counted in hops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct FingerprintConfig {
+    // FFT window in samples at the 11025 Hz analysis rate.
+    pub window_size: usize,
+    pub hop_size: usize,
+    // Added after the other settings, so left out of the stored settings at
+    // their defaults to keep existing digests.
+    #[serde(skip_serializing_if = "PeakPicking::is_strongest")]
+    pub peaks: PeakPicking,
+    // Strongest spectral peaks kept for the whole recording, with `strongest`.
+    pub max_peaks: usize,
+    // Octave bands per frame, with `log_bands`.
+    #[serde(skip_serializing_if = "is_default_bands")]
+    pub bands: usize,
+    // Each peak is paired with up to `fan_out` of the next strongest peaks
+    // (or, with `log_bands`, the strongest peaks) that fall within
+    // `target_zone_frames` after it.
+    pub fan_out: usize,
+    pub target_zone_frames: usize,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        FingerprintConfig {
+            window_size: 1024,
+            hop_size: 512,
+            peaks: PeakPicking::default(),
+            max_peaks: 200,
+            bands: DEFAULT_BANDS,
+            fan_out: 5,
+            target_zone_frames: 10,
+        }
+    }
+}
+
+const DEFAULT_BANDS: usize = 6;
+
+fn is_default_bands(bands: &usize) -> bool {
+    *bands == DEFAULT_BANDS
+}
+
+// How the spectral peaks that hashes are built from are chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeakPicking {
+    // The `max_peaks` strongest local maxima of the whole recording. Loud low
+    // frequencies can take nearly all of them.
+    #[default]
+    Strongest,
+    // The strongest local maximum in each octave band of every frame, so the
+    // whole spectrum contributes. Each peak is paired with the strongest
+    // peaks in its target zone.
+    LogBands,
+}
+
+impl PeakPicking {
+    fn is_strongest(&self) -> bool {
+        *self == PeakPicking::Strongest
+    }
+}
+
+impl FingerprintConfig {
+    pub fn is_default(&self) -> bool {
+        *self == FingerprintConfig::default()
+    }
+
+    // Why these settings can't be used, if they can't. Hashes keep 16 bits
+    // each for Δt and the two frequency bins (see hash_peak_pair), so the
+    // target zone can span up to 65535 frames; windows stop at 4096 samples,
+    // which keeps bins far below that.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.window_size.is_power_of_two() || !(256..=4096).contains(&self.window_size) {
+            return Err("window_size must be a power of two from 256 to 4096".to_string());
+        }
+        if self.hop_size == 0 || self.hop_size > self.window_size {
+            return Err("hop_size must be between 1 and window_size".to_string());
+        }
+        if self.max_peaks == 0 || self.fan_out == 0 {
+            return Err("max_peaks and fan_out must be positive".to_string());
+        }
+        if self.bands == 0 || self.freq_bins() >> (self.bands - 1).min(usize::BITS as usize - 1) < 2 {
+            return Err("bands must be between 1 and log2(window_size / 2)".to_string());
+        }
+        if !(1..=u16::MAX as usize).contains(&self.target_zone_frames) {
+            return Err(format!("target_zone_frames must be between 1 and {}", u16::MAX));
+        }
+        Ok(())
+    }
+
+    fn freq_bins(&self) -> usize {
+        self.window_size / 2
+    }
+
+    // Bin edges of the `bands` octave bands: one band per octave down from
+    // the top of the spectrum, with the lowest band taking every bin below.
+    // Bin 0 and the top bin are never peaks.
+    fn band_edges(&self) -> Vec<usize> {
+        let mut edges = vec![1];
+        edges.extend((1..self.bands).rev().map(|k| self.freq_bins() >> k));
+        edges.push(self.freq_bins() - 1);
+        edges
+    }
+
+    fn frames_per_second(&self) -> f64 {
+        SAMPLE_RATE as f64 / self.hop_size as f64
+    }
+}
+
+impl HashParams {
+    // Whether fingerprints made with `self` and `other` can be compared. The
+    // resample mode only changes how closely the analysis band is
+    // reproduced, so a query resampled cheaply still finds songs ingested
+    // with a better filter, at a somewhat lower score.
+    pub fn comparable_with(&self, other: &HashParams) -> bool {
+        HashParams { resample: other.resample, ..*self } == *other
+    }
+
+    // The digest of every HashParams comparable_with `self`, one per
+    // resample mode.
+    pub fn comparable_digests(&self) -> Vec<String> {
+        ResampleQuality::ALL
+            .iter()
+            .map(|&resample| HashParams { resample, ..*self }.digest())
+            .collect()
+    }
+
+    // Short stable identifier of these settings: FNV-1a over their JSON
+    // form, so it survives restarts and rebuilds (unlike std's hasher).
+    pub fn digest(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        let hash = json.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        format!("{:016x}", hash)
+    }
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AudioFingerprint {
-    pub hashes: Vec<u32>,
+    pub hashes: Vec<u64>,
+    // Anchor time frame of each hash, parallel to `hashes`. Empty for
+    // fingerprints stored before offsets were recorded.
+    #[serde(default)]
+    pub offsets: Vec<u32>,
+    // Quantized strength of each hash's weaker peak, 1..=MAX_HASH_WEIGHT,
+    // parallel to `hashes`. Empty for older fingerprints, which score as if
+    // every hash had weight 1.
+    #[serde(default)]
+    pub weights: Vec<u8>,
     pub duration: f64,
+    #[serde(default)]
+    pub params: HashParams,
+    // HASH_FORMAT of `hashes`. Fingerprints stored before it was recorded
+    // all used format 1.
+    #[serde(default = "first_hash_format")]
+    pub version: u32,
 }
 
-pub fn generate_fingerprint(samples: &[f32]) -> Result<AudioFingerprint> {
+fn first_hash_format() -> u32 {
+    1
+}
+
+impl AudioFingerprint {
+    // Whether scoring `self` against `other` means anything: same hash format
+    // and comparable hashing settings.
+    pub fn comparable_with(&self, other: &AudioFingerprint) -> bool {
+        self.version == other.version && self.params.comparable_with(&other.params)
+    }
+
+    pub fn has_offsets(&self) -> bool {
+        !self.hashes.is_empty() && self.offsets.len() == self.hashes.len()
+    }
+
+    pub fn has_weights(&self) -> bool {
+        !self.hashes.is_empty() && self.weights.len() == self.hashes.len()
+    }
+
+    // Appends `other`, a later part of the same FingerprintBuilder stream.
+    // Their offsets already count from the start of the stream, so unlike
+    // merge_fingerprints this keeps them as they are.
+    pub fn append(&mut self, other: AudioFingerprint) {
+        self.hashes.extend(other.hashes);
+        self.offsets.extend(other.offsets);
+        self.weights.extend(other.weights);
+        self.duration += other.duration;
+    }
+
+    // What this fingerprint would be had its audio been pitched `percent`
+    // lower: every hash's frequency bins are scaled back by 1 + percent/100,
+    // so a query played fast (positive `percent`) lines up with the catalog
+    // again. Anchor offsets are stretched back by the same factor, so
+    // offset-aware scoring sees the hashes line up too; Δt is too coarse to
+    // scale and is left to snap_to.
+    pub fn unshift_pitch(&self, percent: f64) -> AudioFingerprint {
+        let factor = 1.0 + percent / 100.0;
+        let scale = |bin: u64| ((bin as f64 / factor).round() as u64).clamp(1, 0xffff);
+        let hashes = self
+            .hashes
+            .iter()
+            .map(|&hash| {
+                let (freq1, freq2, time_diff) = hash_fields(hash);
+                (scale(freq1) << 32) | (scale(freq2) << 16) | time_diff
+            })
+            .collect();
+        let offsets = self.offsets.iter().map(|&offset| (offset as f64 * factor).round() as u32).collect();
+        AudioFingerprint { hashes, offsets, ..self.clone() }
+    }
+
+    // Replaces each hash missing from `reference` with a hash of `reference`
+    // at most one step away in each field (bins and Δt), if there is one.
+    // Scaling bins back after a pitch shift rounds many of them to a
+    // neighbouring bin, and playing audio faster also shortens Δt; this lets
+    // those hashes count anyway.
+    pub fn snap_to(&self, reference: &AudioFingerprint) -> AudioFingerprint {
+        let known: std::collections::HashSet<u64> = reference.hashes.iter().copied().collect();
+        let hashes = self
+            .hashes
+            .iter()
+            .map(|&hash| neighbours(hash).find(|candidate| known.contains(candidate)).unwrap_or(hash))
+            .collect();
+        AudioFingerprint { hashes, ..self.clone() }
+    }
+
+    // snap_to for tempo drift alone: replaces each hash missing from
+    // `reference` with a hash of `reference` whose Δt is at most `tolerance`
+    // frames off, nearest first.
+    pub fn snap_time_diffs(&self, reference: &AudioFingerprint, tolerance: u16) -> AudioFingerprint {
+        let known: std::collections::HashSet<u64> = reference.hashes.iter().copied().collect();
+        let hashes = self
+            .hashes
+            .iter()
+            .map(|&hash| time_diff_variants(hash, tolerance).find(|candidate| known.contains(candidate)).unwrap_or(hash))
+            .collect();
+        AudioFingerprint { hashes, ..self.clone() }
+    }
+
+    pub fn weight(&self, index: usize) -> f64 {
+        if self.has_weights() {
+            self.weights[index] as f64
+        } else {
+            1.0
+        }
+    }
+
+    // Distinct hashes mapped to the strongest weight each one appears with.
+    pub fn weighted_hashes(&self) -> std::collections::HashMap<u64, f64> {
+        let mut weighted = std::collections::HashMap::new();
+        for (i, &hash) in self.hashes.iter().enumerate() {
+            let weight = self.weight(i);
+            let entry = weighted.entry(hash).or_insert(weight);
+            if *entry < weight {
+                *entry = weight;
+            }
+        }
+        weighted
+    }
+}
+
+pub fn generate_fingerprint(samples: &[f32], sample_rate: u32, params: &HashParams) -> Result<AudioFingerprint> {
     if samples.is_empty() {
         return Err(anyhow!("Empty audio samples"));
     }
+    if sample_rate < MIN_SAMPLE_RATE {
+        return Err(anyhow!("Sample rate {} Hz is below the {} Hz minimum", sample_rate, MIN_SAMPLE_RATE));
+    }
 
     let normalized = crate::audio::normalize_audio(samples);
-    let downsampled = crate::audio::downsample(&normalized, 44100, SAMPLE_RATE);
+    let mut downsampled = crate::audio::resample(&normalized, sample_rate, SAMPLE_RATE, params.resample);
+    if let Some(band) = params.band_pass {
+        downsampled = crate::audio::band_pass(&downsampled, SAMPLE_RATE, band);
+    }
     
-    let spectrogram = compute_spectrogram(&downsampled)?;
-    let peaks = find_spectral_peaks(&spectrogram);
-    let hashes = generate_hashes(&peaks);
+    let (spectrogram, silent_frames) = compute_spectrogram(&downsampled, &params.analysis)?;
+    let peaks = find_spectral_peaks(&spectrogram, &params.analysis);
+    let (hashes, offsets, weights) = generate_hashes(&peaks, params);
     
-    let duration = samples.len() as f64 / 44100.0;
+    let duration = samples.len() as f64 / sample_rate as f64;
+    debug!(
+        "Fingerprinted {:.1}s: {} frames ({} silent), {} peaks, {} hashes",
+        duration,
+        spectrogram.dim().1,
+        silent_frames,
+        peaks.len(),
+        hashes.len()
+    );
     
-    Ok(AudioFingerprint { hashes, duration })
+    Ok(AudioFingerprint { hashes, offsets, weights, duration, params: *params, version: HASH_FORMAT })
+}
+
+// Streaming form of generate_fingerprint, for audio that arrives in chunks,
+// such as a live capture or a radio stream. Resampling, band-pass filtering
+// and the STFT keep their state between chunks, so where the chunks split the
+// audio doesn't matter, and each chunk returns the hashes it settled. Memory
+// stays bounded however long the stream runs.
+//
+// It differs from fingerprinting the whole recording at once in three ways.
+// The audio isn't peak-normalized, as its loudest sample isn't known yet, so
+// it should already be at full scale. Hash weights are relative to the
+// strongest peak so far. And with PeakPicking::Strongest, peaks compete for
+// `max_peaks` within blocks of STREAM_BLOCK_SECS, each hashed like a separate
+// clip once it ends. PeakPicking::LogBands picks peaks frame by frame anyway,
+// and a peak is hashed as soon as its target zone has passed. So a normalized
+// recording gives the same hashes and offsets either way with LogBands, or
+// with Strongest if it is no longer than one block.
+pub struct FingerprintBuilder {
+    params: HashParams,
+    sample_rate: u32,
+    resampler: Resampler,
+    band_pass: Option<BandPassFilter>,
+    fft: Arc<dyn Fft<f64>>,
+    window: Vec<f64>,
+    buffer: Vec<Complex<f64>>,
+    scratch: Vec<Complex<f64>>,
+    edges: Vec<usize>,
+    // Analysis-rate samples from `samples_start` on.
+    samples: Vec<f32>,
+    samples_start: usize,
+    // Spectrogram frames from `frames_start` on: the ones peak detection
+    // still needs.
+    frames: VecDeque<Array1<f64>>,
+    frames_start: usize,
+    next_peak_frame: usize,
+    // Detected peaks not yet hashed as anchors, in time order.
+    pending: Vec<SpectralPeak>,
+    strongest: f64,
+    settled: (Vec<u64>, Vec<u32>, Vec<u8>),
+}
+
+impl FingerprintBuilder {
+    pub fn new(sample_rate: u32, params: &HashParams) -> Result<Self> {
+        if sample_rate < MIN_SAMPLE_RATE {
+            return Err(anyhow!("Sample rate {} Hz is below the {} Hz minimum", sample_rate, MIN_SAMPLE_RATE));
+        }
+
+        let analysis = &params.analysis;
+        let fft = FftPlanner::new().plan_fft_forward(analysis.window_size);
+        let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+        let edges = if analysis.peaks == PeakPicking::LogBands { analysis.band_edges() } else { Vec::new() };
+
+        Ok(FingerprintBuilder {
+            params: *params,
+            sample_rate,
+            resampler: Resampler::new(sample_rate, SAMPLE_RATE, params.resample),
+            band_pass: params.band_pass.map(|band| BandPassFilter::new(SAMPLE_RATE, band)),
+            fft,
+            window: hann_window(analysis.window_size),
+            buffer: vec![Complex::new(0.0, 0.0); analysis.window_size],
+            scratch,
+            edges,
+            samples: Vec::new(),
+            samples_start: 0,
+            frames: VecDeque::new(),
+            frames_start: 0,
+            // Frame 0 has no frame before it to compare with.
+            next_peak_frame: 1,
+            pending: Vec::new(),
+            strongest: 0.0,
+            settled: Default::default(),
+        })
+    }
+
+    // Adds the next chunk of mono audio at the builder's sample rate and
+    // returns the hashes it settled. Their offsets count frames from the
+    // start of the stream; the duration is the chunk's.
+    pub fn push(&mut self, samples: &[f32]) -> AudioFingerprint {
+        let resampled = self.resampler.push(samples);
+        self.analyse(resampled);
+        self.detect_peaks(false);
+        self.hash_pending(false);
+        self.take_settled(samples.len() as f64 / self.sample_rate as f64)
+    }
+
+    // Ends the stream and returns the hashes that were still waiting for the
+    // audio after them.
+    pub fn finish(mut self) -> AudioFingerprint {
+        let resampled = self.resampler.finish();
+        self.analyse(resampled);
+
+        // generate_fingerprint leaves the last frame empty when it ends
+        // exactly on the last sample.
+        let (window_size, hop_size) = (self.params.analysis.window_size, self.params.analysis.hop_size.max(1));
+        let total = self.samples_start + self.samples.len();
+        if total >= window_size && (total - window_size).is_multiple_of(hop_size) {
+            if let Some(last) = self.frames.back_mut() {
+                last.fill(0.0);
+            }
+        }
+
+        self.detect_peaks(true);
+        self.hash_pending(true);
+        self.take_settled(0.0)
+    }
+
+    // Filters `samples`, which are at the analysis rate, and adds the frames
+    // they complete.
+    fn analyse(&mut self, mut samples: Vec<f32>) {
+        if let Some(filter) = &mut self.band_pass {
+            filter.process(&mut samples);
+        }
+        self.samples.extend(samples);
+
+        let window_size = self.params.analysis.window_size;
+        let hop_size = self.params.analysis.hop_size.max(1);
+        loop {
+            let start = (self.frames_start + self.frames.len()) * hop_size - self.samples_start;
+            let Some(frame) = self.samples.get(start..start + window_size) else {
+                break;
+            };
+            let mut magnitudes = Array1::zeros(self.params.analysis.freq_bins());
+            let (buffer, scratch) = (&mut self.buffer, &mut self.scratch);
+            analyse_frame(frame, &self.window, self.fft.as_ref(), buffer, scratch, magnitudes.iter_mut());
+            self.frames.push_back(magnitudes);
+        }
+
+        let next_start = (self.frames_start + self.frames.len()) * hop_size;
+        let consumed = next_start.saturating_sub(self.samples_start).min(self.samples.len());
+        self.samples.drain(..consumed);
+        self.samples_start += consumed;
+    }
+
+    // Finds the peaks of every frame whose neighbours are known. Until the
+    // stream ends, the newest frame might be the one generate_fingerprint
+    // leaves empty, so the frame before it waits too.
+    fn detect_peaks(&mut self, ended: bool) {
+        let lookahead = if ended { 1 } else { 2 };
+        let block_frames = ((STREAM_BLOCK_SECS * self.params.analysis.frames_per_second()) as usize).max(1);
+
+        while self.next_peak_frame + lookahead < self.frames_start + self.frames.len() {
+            let t = self.next_peak_frame;
+            let i = t - self.frames_start;
+            let frames = [self.frames[i - 1].view(), self.frames[i].view(), self.frames[i + 1].view()];
+            let first = self.pending.len();
+            frame_peaks(frames, t, &self.params.analysis, &self.edges, &mut self.pending);
+            self.strongest = self.pending[first..].iter().fold(self.strongest, |s, p| s.max(p.magnitude));
+            self.next_peak_frame += 1;
+
+            if self.params.analysis.peaks == PeakPicking::Strongest && self.next_peak_frame.is_multiple_of(block_frames) {
+                self.hash_pending(true);
+            }
+        }
+
+        while self.frames_start + 1 < self.next_peak_frame {
+            self.frames.pop_front();
+            self.frames_start += 1;
+        }
+    }
+
+    // Hashes the pending peaks whose target zone has passed, or all of them
+    // at the end of a block or the stream.
+    fn hash_pending(&mut self, all: bool) {
+        let zone = self.params.analysis.target_zone_frames;
+        let (hashes, offsets, weights) = match self.params.analysis.peaks {
+            PeakPicking::Strongest => {
+                if !all {
+                    return;
+                }
+                let mut peaks = std::mem::take(&mut self.pending);
+                peaks.sort_by(|a, b| b.magnitude.partial_cmp(&a.magnitude).unwrap());
+                peaks.truncate(self.params.analysis.max_peaks);
+                pair_peaks(&peaks, peaks.len(), &self.params, self.strongest)
+            }
+            PeakPicking::LogBands => {
+                let ready = self
+                    .pending
+                    .iter()
+                    .take_while(|p| all || p.time_frame + zone < self.next_peak_frame)
+                    .count();
+                let settled = pair_peaks(&self.pending, ready, &self.params, self.strongest);
+                self.pending.drain(..ready);
+                settled
+            }
+        };
+
+        self.settled.0.extend(hashes);
+        self.settled.1.extend(offsets);
+        self.settled.2.extend(weights);
+    }
+
+    fn take_settled(&mut self, duration: f64) -> AudioFingerprint {
+        let (hashes, offsets, weights) = std::mem::take(&mut self.settled);
+        AudioFingerprint { hashes, offsets, weights, duration, params: self.params, version: HASH_FORMAT }
+    }
+}
+
+// What generate_fingerprint does to audio decoded at `sample_rate` before
+// analysis, in order, for ingest reports.
+pub fn preprocessing(sample_rate: u32, params: &HashParams) -> Vec<String> {
+    let mut steps = vec!["peak-normalized".to_string()];
+    if sample_rate != SAMPLE_RATE {
+        steps.push(format!(
+            "resampled from {} Hz to {} Hz ({})",
+            sample_rate,
+            SAMPLE_RATE,
+            format!("{:?}", params.resample).to_lowercase()
+        ));
+    }
+    if let Some(band) = params.band_pass {
+        steps.push(format!("band-pass filtered to {}-{} Hz", band.low_hz, band.high_hz));
+    }
+    steps
 }
 
-fn compute_spectrogram(samples: &[f32]) -> Result<Array2<f64>> {
+// Bytes the spectrogram of `sample_count` samples at `sample_rate` will occupy
+// with the `analysis` layout.
+pub fn spectrogram_bytes(sample_count: usize, sample_rate: u32, analysis: &FingerprintConfig) -> u64 {
+    let downsampled = sample_count as u64 * SAMPLE_RATE as u64 / sample_rate.max(1) as u64;
+    let frames = downsampled.saturating_sub(analysis.window_size as u64) / analysis.hop_size.max(1) as u64 + 1;
+    frames * analysis.freq_bins() as u64 * std::mem::size_of::<f64>() as u64
+}
+
+// Returns the spectrogram and how many frames were skipped as silent. Frames
+// are independent, so their FFTs run in parallel, each thread reusing its own
+// buffers; the result is the same as computing them in order.
+fn compute_spectrogram(samples: &[f32], analysis: &FingerprintConfig) -> Result<(Array2<f64>, usize)> {
+    let window_size = analysis.window_size;
+    let hop_size = analysis.hop_size.max(1);
+    let freq_bins = analysis.freq_bins();
     let mut planner = FftPlanner::new();
-    let fft = planner.plan_fft_forward(WINDOW_SIZE);
+    let fft = planner.plan_fft_forward(window_size);
+    let window = hann_window(window_size);
     
-    let num_frames = (samples.len().saturating_sub(WINDOW_SIZE)) / HOP_SIZE + 1;
-    let mut spectrogram = Array2::zeros((FREQ_BINS, num_frames));
+    let num_frames = (samples.len().saturating_sub(window_size)) / hop_size + 1;
+    let mut spectrogram = Array2::zeros((freq_bins, num_frames));
+    // Frames start every hop before the last full window; when that leaves
+    // room for one more frame it stays zero.
+    let frames = samples.len().saturating_sub(window_size).div_ceil(hop_size).min(num_frames);
     
-    for (frame_idx, start) in (0..samples.len().saturating_sub(WINDOW_SIZE))
-        .step_by(HOP_SIZE)
+    let silent_frames = spectrogram
+        .axis_iter_mut(Axis(1))
+        .into_par_iter()
+        .take(frames)
         .enumerate()
-    {
-        if frame_idx >= num_frames {
-            break;
-        }
-        
-        let mut buffer: Vec<Complex<f64>> = samples[start..start + WINDOW_SIZE]
-            .iter()
-            .map(|&x| Complex::new(x as f64, 0.0))
-            .collect();
-        
-        apply_hann_window(&mut buffer);
-        fft.process(&mut buffer);
-        
-        for (freq_idx, &complex) in buffer.iter().take(FREQ_BINS).enumerate() {
-            let magnitude = complex.norm();
-            spectrogram[[freq_idx, frame_idx]] = magnitude;
-        }
-    }
+        .map_init(
+            || {
+                let buffer = vec![Complex::new(0.0, 0.0); window_size];
+                let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+                (buffer, scratch)
+            },
+            |(buffer, scratch), (frame_idx, mut column)| {
+                let start = frame_idx * hop_size;
+                let frame = &samples[start..start + window_size];
+                analyse_frame(frame, &window, fft.as_ref(), buffer, scratch, column.iter_mut()) as usize
+            },
+        )
+        .sum();
     
-    Ok(spectrogram)
+    Ok((spectrogram, silent_frames))
 }
 
-fn apply_hann_window(buffer: &mut [Complex<f64>]) {
-    let buffer_len = buffer.len();
-    for (i, sample) in buffer.iter_mut().enumerate() {
-        let window_val = 0.5 * (1.0 - (2.0 * std::f64::consts::PI * i as f64 / (buffer_len - 1) as f64).cos());
-        *sample *= window_val;
+// Writes the magnitudes of one frame's spectrum, leaving them untouched (zero)
+// and returning true if the frame is silent.
+fn analyse_frame<'a>(
+    frame: &[f32],
+    window: &[f64],
+    fft: &dyn Fft<f64>,
+    buffer: &mut [Complex<f64>],
+    scratch: &mut [Complex<f64>],
+    magnitudes: impl Iterator<Item = &'a mut f64>,
+) -> bool {
+    let energy = frame.iter().map(|&x| x * x).sum::<f32>() / frame.len() as f32;
+    if energy < SILENT_FRAME_RMS * SILENT_FRAME_RMS {
+        return true;
+    }
+
+    for ((slot, &x), &w) in buffer.iter_mut().zip(frame).zip(window) {
+        *slot = Complex::new(x as f64, 0.0) * w;
+    }
+    fft.process_with_scratch(buffer, scratch);
+
+    for (magnitude, complex) in magnitudes.zip(buffer.iter()) {
+        *magnitude = complex.norm();
     }
+    false
+}
+
+fn hann_window(len: usize) -> Vec<f64> {
+    (0..len)
+        .map(|i| 0.5 * (1.0 - (2.0 * std::f64::consts::PI * i as f64 / (len - 1) as f64).cos()))
+        .collect()
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -77,67 +675,237 @@ struct SpectralPeak {
     magnitude: f64,
 }
 
-fn find_spectral_peaks(spectrogram: &Array2<f64>) -> Vec<SpectralPeak> {
+// Strongest first with PeakPicking::Strongest, in time order (then frequency)
+// with PeakPicking::LogBands.
+fn find_spectral_peaks(spectrogram: &Array2<f64>, analysis: &FingerprintConfig) -> Vec<SpectralPeak> {
     let mut peaks = Vec::new();
-    let (freq_bins, time_frames) = spectrogram.dim();
+    let time_frames = spectrogram.dim().1;
+    let edges = if analysis.peaks == PeakPicking::LogBands { analysis.band_edges() } else { Vec::new() };
     
     for t in 1..time_frames - 1 {
-        for f in 1..freq_bins - 1 {
-            let current = spectrogram[[f, t]];
-            
-            if current > 0.1 && 
-               current > spectrogram[[f-1, t]] &&
-               current > spectrogram[[f+1, t]] &&
-               current > spectrogram[[f, t-1]] &&
-               current > spectrogram[[f, t+1]] {
-                peaks.push(SpectralPeak {
-                    freq_bin: f,
-                    time_frame: t,
-                    magnitude: current,
-                });
-            }
-        }
+        let frames = [spectrogram.column(t - 1), spectrogram.column(t), spectrogram.column(t + 1)];
+        frame_peaks(frames, t, analysis, &edges, &mut peaks);
     }
     
-    peaks.sort_by(|a, b| b.magnitude.partial_cmp(&a.magnitude).unwrap());
-    peaks.truncate(200);
+    if analysis.peaks == PeakPicking::Strongest {
+        peaks.sort_by(|a, b| b.magnitude.partial_cmp(&a.magnitude).unwrap());
+        peaks.truncate(analysis.max_peaks);
+    }
     
     peaks
 }
 
-fn generate_hashes(peaks: &[SpectralPeak]) -> Vec<u32> {
+// Adds the peaks of frame `t`, in order of frequency, given it and the frames
+// either side of it: every local maximum with PeakPicking::Strongest, the
+// strongest one in each band of `edges` with PeakPicking::LogBands.
+fn frame_peaks(
+    [before, frame, after]: [ArrayView1<f64>; 3],
+    t: usize,
+    analysis: &FingerprintConfig,
+    edges: &[usize],
+    peaks: &mut Vec<SpectralPeak>,
+) {
+    let freq_bins = frame.len();
+    let is_peak = |f: usize| {
+        let current = frame[f];
+        current > 0.1 &&
+            current > frame[f-1] &&
+            current > frame[f+1] &&
+            current > before[f] &&
+            current > after[f]
+    };
+    let peak = |f: usize| SpectralPeak {
+        freq_bin: f,
+        time_frame: t,
+        magnitude: frame[f],
+    };
+    
+    match analysis.peaks {
+        PeakPicking::Strongest => peaks.extend((1..freq_bins - 1).filter(|&f| is_peak(f)).map(peak)),
+        PeakPicking::LogBands => {
+            for band in edges.windows(2) {
+                let strongest = (band[0]..band[1])
+                    .filter(|&f| is_peak(f))
+                    .max_by(|&a, &b| frame[a].total_cmp(&frame[b]));
+                peaks.extend(strongest.map(peak));
+            }
+        }
+    }
+}
+
+fn generate_hashes(peaks: &[SpectralPeak], params: &HashParams) -> (Vec<u64>, Vec<u32>, Vec<u8>) {
+    let strongest = peaks.iter().map(|p| p.magnitude).fold(0.0, f64::max);
+    pair_peaks(peaks, peaks.len(), params, strongest)
+}
+
+// Hashes the first `anchors` peaks with their partners, which may come from
+// anywhere later in `peaks`. Weights are relative to `strongest`.
+fn pair_peaks(
+    peaks: &[SpectralPeak],
+    anchors: usize,
+    params: &HashParams,
+    strongest: f64,
+) -> (Vec<u64>, Vec<u32>, Vec<u8>) {
     let mut hashes = Vec::new();
+    let mut offsets = Vec::new();
+    let mut weights = Vec::new();
+
+    let zone = params.analysis.target_zone_frames;
+    let fan_out = params.analysis.fan_out;
     
-    for (i, &peak1) in peaks.iter().enumerate() {
-        for &peak2 in peaks.iter().skip(i + 1).take(5) {
-            if peak2.time_frame > peak1.time_frame && peak2.time_frame <= peak1.time_frame + 10 {
-                let freq1 = peak1.freq_bin as u32;
-                let freq2 = peak2.freq_bin as u32;
-                let time_diff = (peak2.time_frame - peak1.time_frame) as u32;
-                
-                let hash = (freq1 << 16) | (freq2 << 8) | time_diff;
-                hashes.push(hash);
+    for (i, &peak1) in peaks.iter().enumerate().take(anchors) {
+        let later = &peaks[i + 1..];
+        let partners: Vec<SpectralPeak> = match params.analysis.peaks {
+            // Strongest first, so these are the next strongest peaks.
+            PeakPicking::Strongest => later.iter().take(fan_out).copied().collect(),
+            // In time order, so the target zone is a contiguous run.
+            PeakPicking::LogBands => {
+                let mut zone_peaks: Vec<SpectralPeak> = later
+                    .iter()
+                    .skip_while(|p| p.time_frame == peak1.time_frame)
+                    .take_while(|p| p.time_frame <= peak1.time_frame + zone)
+                    .copied()
+                    .collect();
+                zone_peaks.sort_by(|a, b| b.magnitude.total_cmp(&a.magnitude));
+                zone_peaks.truncate(fan_out);
+                zone_peaks
+            }
+        };
+
+        for peak2 in partners {
+            if peak2.time_frame > peak1.time_frame && peak2.time_frame <= peak1.time_frame + zone {
+                let time_diff = (peak2.time_frame - peak1.time_frame) as u32 / params.time_delta_step.max(1);
+                hashes.push(hash_peak_pair(peak1.freq_bin, peak2.freq_bin, time_diff));
+                offsets.push(peak1.time_frame as u32);
+                weights.push(quantize_weight(peak1.magnitude.min(peak2.magnitude), strongest));
             }
         }
     }
     
-    hashes
+    (hashes, offsets, weights)
+}
+
+// Bits 63..48 are zero, 47..32 hold the anchor's frequency bin, 31..16 the
+// paired peak's bin and 15..0 the quantized Δt. Bins stay below 2048 (half
+// the largest window) and Δt below 2^16 (the largest target zone), so no
+// field can spill into another. Anchor bins start at 1, so every hash is at
+// least 2^32 and never equals a format 1 hash. The top bits are left free so
+// hashes stay positive as SQLite's signed 64-bit integers.
+fn hash_peak_pair(freq1: usize, freq2: usize, time_diff: u32) -> u64 {
+    ((freq1 as u64 & 0xffff) << 32) | ((freq2 as u64 & 0xffff) << 16) | (time_diff as u64 & 0xffff)
+}
+
+// The anchor bin, paired bin and Δt of a hash_peak_pair hash.
+fn hash_fields(hash: u64) -> (u64, u64, u64) {
+    (hash >> 32 & 0xffff, hash >> 16 & 0xffff, hash & 0xffff)
+}
+
+// `hash` itself first, then the hashes with any of its fields one higher or
+// lower.
+fn neighbours(hash: u64) -> impl Iterator<Item = u64> {
+    let (freq1, freq2, time_diff) = hash_fields(hash);
+    let near = |value: u64, low: u64| {
+        [value, value.wrapping_sub(1), value + 1].into_iter().filter(move |v| (low..=0xffff).contains(v))
+    };
+    near(freq1, 1).flat_map(move |freq1| {
+        near(freq2, 1).flat_map(move |freq2| {
+            near(time_diff, 0).map(move |time_diff| (freq1 << 32) | (freq2 << 16) | time_diff)
+        })
+    })
 }
 
+// `hash` itself first, then the hashes with Δt up to `tolerance` frames
+// higher or lower, nearest first.
+pub fn time_diff_variants(hash: u64, tolerance: u16) -> impl Iterator<Item = u64> {
+    let time_diff = (hash & 0xffff) as i64;
+    let base = hash & !0xffff;
+    std::iter::once(time_diff)
+        .chain((1..=tolerance as i64).flat_map(move |step| [time_diff - step, time_diff + step]))
+        .filter(|candidate| (0..=0xffff).contains(candidate))
+        .map(move |candidate| base | candidate as u64)
+}
+
+// One weight step per halving of magnitude below the strongest peak, so a
+// dominant peak pair weighs MAX_HASH_WEIGHT and a barely-detected one 1.
+fn quantize_weight(magnitude: f64, strongest: f64) -> u8 {
+    if strongest <= 0.0 || magnitude <= 0.0 {
+        return 1;
+    }
+
+    let steps_below = (strongest / magnitude).log2().floor().max(0.0);
+    (MAX_HASH_WEIGHT as f64 - steps_below).max(1.0) as u8
+}
+
+// Treats the fingerprints as consecutive segments of one capture: each
+// segment's offsets are shifted by the frames spanned by the segments before
+// it, so offset-aware scoring sees one continuous recording.
+pub fn merge_fingerprints(fingerprints: &[AudioFingerprint]) -> AudioFingerprint {
+    let keep_offsets = fingerprints.iter().all(|f| f.has_offsets() || f.hashes.is_empty());
+    let keep_weights = fingerprints.iter().all(|f| f.has_weights() || f.hashes.is_empty());
+    let params = fingerprints.first().map(|f| f.params).unwrap_or_default();
+    let version = fingerprints.first().map(|f| f.version).unwrap_or(HASH_FORMAT);
+    let frames_per_second = params.analysis.frames_per_second();
+
+    let mut seen = std::collections::HashSet::new();
+    let mut hashes = Vec::new();
+    let mut offsets = Vec::new();
+    let mut weights = Vec::new();
+    let mut elapsed = 0.0;
+
+    for fingerprint in fingerprints {
+        let shift = (elapsed * frames_per_second) as u32;
+
+        for (i, &hash) in fingerprint.hashes.iter().enumerate() {
+            let offset = if keep_offsets { fingerprint.offsets[i] + shift } else { 0 };
+            if seen.insert((hash, offset)) {
+                hashes.push(hash);
+                if keep_offsets {
+                    offsets.push(offset);
+                }
+                if keep_weights {
+                    weights.push(fingerprint.weights[i]);
+                }
+            }
+        }
+
+        elapsed += fingerprint.duration;
+    }
+
+    AudioFingerprint { hashes, offsets, weights, duration: elapsed, params, version }
+}
+
+// Jaccard similarity over distinct hashes, weighted by hash strength: shared
+// weight (min of the two sides) over union weight (max of the two sides).
+// With unweighted fingerprints this is plain |A ∩ B| / |A ∪ B|.
 pub fn calculate_similarity(fingerprint1: &AudioFingerprint, fingerprint2: &AudioFingerprint) -> f64 {
     if fingerprint1.hashes.is_empty() || fingerprint2.hashes.is_empty() {
         return 0.0;
     }
     
-    let set1: std::collections::HashSet<_> = fingerprint1.hashes.iter().collect();
-    let set2: std::collections::HashSet<_> = fingerprint2.hashes.iter().collect();
+    let weights1 = fingerprint1.weighted_hashes();
+    let weights2 = fingerprint2.weighted_hashes();
+    
+    let mut intersection = 0.0;
+    let mut union = 0.0;
     
-    let intersection = set1.intersection(&set2).count();
-    let union = set1.union(&set2).count();
+    for (hash, &w1) in &weights1 {
+        match weights2.get(hash) {
+            Some(&w2) => {
+                intersection += w1.min(w2);
+                union += w1.max(w2);
+            }
+            None => union += w1,
+        }
+    }
+    union += weights2
+        .iter()
+        .filter(|(hash, _)| !weights1.contains_key(hash))
+        .map(|(_, &w2)| w2)
+        .sum::<f64>();
     
-    if union == 0 {
+    if union == 0.0 {
         0.0
     } else {
-        intersection as f64 / union as f64
+        intersection / union
     }
 }
\ No newline at end of file
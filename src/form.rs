@@ -0,0 +1,184 @@
+use axum::body::Bytes;
+use axum::extract::Multipart;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+// All parts of a multipart upload, in the order they were sent.
+pub struct MultipartForm {
+    fields: Vec<(String, Bytes)>,
+}
+
+impl MultipartForm {
+    pub async fn read(multipart: &mut Multipart) -> Result<Self, FormError> {
+        let mut fields = Vec::new();
+
+        while let Some(field) = multipart.next_field().await.map_err(FormError::malformed)? {
+            let name = field.name().unwrap_or("").to_string();
+            let data = field.bytes().await.map_err(FormError::malformed)?;
+            fields.push((name, data));
+        }
+
+        Ok(MultipartForm { fields })
+    }
+
+    // Like read, but each part named `streamed` is passed on chunk by chunk
+    // to a sender from `open` as it arrives instead of being buffered. `open`
+    // sees the fields read before the part. Those parts are recorded without
+    // their data, so `require` still sees them.
+    pub async fn read_streaming(
+        multipart: &mut Multipart,
+        streamed: &str,
+        mut open: impl FnMut(&MultipartForm, Option<&str>) -> mpsc::Sender<Bytes>,
+    ) -> Result<Self, FormError> {
+        let mut form = MultipartForm { fields: Vec::new() };
+
+        while let Some(mut field) = multipart.next_field().await.map_err(FormError::malformed)? {
+            let name = field.name().unwrap_or("").to_string();
+            if name != streamed {
+                let data = field.bytes().await.map_err(FormError::malformed)?;
+                form.fields.push((name, data));
+                continue;
+            }
+
+            // A receiver that has given up (its decoder failed) leaves the
+            // rest of the part to be read and discarded.
+            let mut chunks = Some(open(&form, field.content_type()));
+            while let Some(chunk) = field.chunk().await.map_err(FormError::malformed)? {
+                if let Some(sender) = &chunks {
+                    if sender.send(chunk).await.is_err() {
+                        chunks = None;
+                    }
+                }
+            }
+            form.fields.push((name, Bytes::new()));
+        }
+
+        Ok(form)
+    }
+
+    // Checks that every required field is present. The error lists what was
+    // received next to what was required, so a misnamed field is obvious.
+    pub fn require(&self, required: &[&'static str]) -> Result<(), FormError> {
+        let missing: Vec<&'static str> = required
+            .iter()
+            .copied()
+            .filter(|name| !self.fields.iter().any(|(field, _)| field == name))
+            .collect();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        Err(FormError::Invalid(FieldReport {
+            error: format!("Missing required multipart field(s): {}", missing.join(", ")),
+            missing,
+            required: required.to_vec(),
+            received: self.received(),
+        }))
+    }
+
+    pub fn bytes(&self, name: &str) -> Option<Bytes> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, data)| data.clone())
+    }
+
+    pub fn all_bytes(&self, name: &str) -> Vec<Bytes> {
+        self.fields
+            .iter()
+            .filter(|(field, _)| field == name)
+            .map(|(_, data)| data.clone())
+            .collect()
+    }
+
+    pub fn text(&self, name: &'static str) -> Result<Option<String>, FormError> {
+        match self.bytes(name) {
+            None => Ok(None),
+            Some(data) => self.utf8(name, data).map(Some),
+        }
+    }
+
+    pub fn all_text(&self, name: &'static str) -> Result<Vec<String>, FormError> {
+        self.all_bytes(name).into_iter().map(|data| self.utf8(name, data)).collect()
+    }
+
+    pub fn integer(&self, name: &'static str) -> Result<Option<i64>, FormError> {
+        match self.text(name)? {
+            None => Ok(None),
+            Some(text) => text
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| self.invalid(format!("Multipart field {} is not an integer", name))),
+        }
+    }
+
+    pub fn number(&self, name: &'static str) -> Result<Option<f64>, FormError> {
+        match self.text(name)? {
+            None => Ok(None),
+            Some(text) => text
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| self.invalid(format!("Multipart field {} is not a number", name))),
+        }
+    }
+
+    fn utf8(&self, name: &str, data: Bytes) -> Result<String, FormError> {
+        String::from_utf8(data.to_vec())
+            .map_err(|_| self.invalid(format!("Multipart field {} is not valid UTF-8 text", name)))
+    }
+
+    pub fn invalid(&self, error: String) -> FormError {
+        FormError::Invalid(FieldReport {
+            error,
+            missing: Vec::new(),
+            required: Vec::new(),
+            received: self.received(),
+        })
+    }
+
+    fn received(&self) -> Vec<String> {
+        let mut received: Vec<String> = Vec::new();
+        for (name, _) in &self.fields {
+            if !received.contains(name) {
+                received.push(name.clone());
+            }
+        }
+        received
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldReport {
+    pub error: String,
+    pub missing: Vec<&'static str>,
+    pub required: Vec<&'static str>,
+    pub received: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    // The body isn't parseable multipart at all, or is over the route's body
+    // limit (413).
+    Malformed(StatusCode, String),
+    Invalid(FieldReport),
+}
+
+impl FormError {
+    fn malformed(e: axum::extract::multipart::MultipartError) -> Self {
+        FormError::Malformed(e.status(), e.body_text())
+    }
+}
+
+impl IntoResponse for FormError {
+    fn into_response(self) -> Response {
+        match self {
+            FormError::Malformed(status, message) => (status, message).into_response(),
+            FormError::Invalid(report) => (StatusCode::UNPROCESSABLE_ENTITY, Json(report)).into_response(),
+        }
+    }
+}
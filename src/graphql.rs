@@ -0,0 +1,230 @@
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema, SimpleObject};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::database::{Database, MatchRecord, SongSummary, SongVersion};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+pub type CatalogSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+// Read-only view of the catalog. Depth and complexity limits keep a single
+// nested query from fanning out into thousands of database round trips.
+pub fn build_schema(db: Database) -> CatalogSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(db)
+        .limit_depth(8)
+        .limit_complexity(500)
+        .finish()
+}
+
+fn page(offset: Option<i64>, limit: Option<i64>) -> (i64, i64) {
+    (
+        offset.unwrap_or(0).max(0),
+        limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+    )
+}
+
+#[derive(SimpleObject)]
+pub struct SongPage {
+    total: i64,
+    items: Vec<Song>,
+}
+
+#[derive(SimpleObject)]
+pub struct ArtistPage {
+    total: i64,
+    items: Vec<Artist>,
+}
+
+#[derive(SimpleObject)]
+pub struct MatchPage {
+    total: i64,
+    items: Vec<Match>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn songs(
+        &self,
+        ctx: &Context<'_>,
+        artist: Option<String>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<SongPage> {
+        let (offset, limit) = page(offset, limit);
+        let (songs, total) = ctx
+            .data::<Database>()?
+            .list_songs_page(artist.as_deref(), offset, limit)
+            .await?;
+
+        Ok(SongPage { total, items: songs.into_iter().map(Song).collect() })
+    }
+
+    async fn song(&self, ctx: &Context<'_>, id: i64) -> Result<Option<Song>> {
+        Ok(ctx.data::<Database>()?.get_song(id).await?.map(Song))
+    }
+
+    async fn artists(&self, ctx: &Context<'_>, offset: Option<i64>, limit: Option<i64>) -> Result<ArtistPage> {
+        let (offset, limit) = page(offset, limit);
+        let (artists, total) = ctx.data::<Database>()?.list_artists(offset, limit).await?;
+
+        Ok(ArtistPage {
+            total,
+            items: artists
+                .into_iter()
+                .map(|(name, song_count)| Artist { name, song_count })
+                .collect(),
+        })
+    }
+
+    async fn match_history(
+        &self,
+        ctx: &Context<'_>,
+        song_id: Option<i64>,
+        offset: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<MatchPage> {
+        let (offset, limit) = page(offset, limit);
+        let (records, total) = ctx
+            .data::<Database>()?
+            .list_match_history(song_id, offset, limit)
+            .await?;
+
+        Ok(MatchPage { total, items: records.into_iter().map(Match).collect() })
+    }
+}
+
+pub struct Song(SongSummary);
+
+#[Object]
+impl Song {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+
+    async fn artist(&self) -> &str {
+        &self.0.artist
+    }
+
+    async fn duration(&self) -> f64 {
+        self.0.duration
+    }
+
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+
+    async fn hash_count(&self) -> i64 {
+        self.0.hash_count
+    }
+
+    async fn year(&self) -> Option<i64> {
+        self.0.year
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+
+    async fn created_at(&self) -> NaiveDateTime {
+        self.0.created_at
+    }
+
+    async fn versions(&self, ctx: &Context<'_>) -> Result<Vec<Version>> {
+        let versions = ctx.data::<Database>()?.list_song_versions(self.0.id).await?;
+        Ok(versions.into_iter().map(Version).collect())
+    }
+
+    async fn matches(&self, ctx: &Context<'_>, offset: Option<i64>, limit: Option<i64>) -> Result<MatchPage> {
+        let (offset, limit) = page(offset, limit);
+        let (records, total) = ctx
+            .data::<Database>()?
+            .list_match_history(Some(self.0.id), offset, limit)
+            .await?;
+
+        Ok(MatchPage { total, items: records.into_iter().map(Match).collect() })
+    }
+}
+
+pub struct Artist {
+    name: String,
+    song_count: i64,
+}
+
+#[Object]
+impl Artist {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn song_count(&self) -> i64 {
+        self.song_count
+    }
+
+    async fn songs(&self, ctx: &Context<'_>, offset: Option<i64>, limit: Option<i64>) -> Result<SongPage> {
+        let (offset, limit) = page(offset, limit);
+        let (songs, total) = ctx
+            .data::<Database>()?
+            .list_songs_page(Some(&self.name), offset, limit)
+            .await?;
+
+        Ok(SongPage { total, items: songs.into_iter().map(Song).collect() })
+    }
+}
+
+pub struct Version(SongVersion);
+
+#[Object]
+impl Version {
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+
+    async fn duration(&self) -> f64 {
+        self.0.duration
+    }
+
+    async fn archived_at(&self) -> DateTime<Utc> {
+        self.0.archived_at
+    }
+}
+
+pub struct Match(MatchRecord);
+
+#[Object]
+impl Match {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn confidence(&self) -> Option<f64> {
+        self.0.confidence
+    }
+
+    async fn song_version(&self) -> Option<i64> {
+        self.0.song_version
+    }
+
+    async fn experiment(&self) -> Option<&str> {
+        self.0.experiment.as_deref()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    // None for requests that matched nothing, or whose song was since deleted.
+    async fn song(&self, ctx: &Context<'_>) -> Result<Option<Song>> {
+        match self.0.song_id {
+            Some(song_id) => Ok(ctx.data::<Database>()?.get_song(song_id).await?.map(Song)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::MethodRouter;
+use axum::Router;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::HttpConfig;
+
+// Router::route with the timeout and body limit that `[http]` sets for the
+// path, so each endpoint can be sized for what it accepts.
+pub trait LimitedRoutes<S> {
+    fn limited_route(self, http: &HttpConfig, path: &str, method_router: MethodRouter<S>) -> Self;
+}
+
+impl<S: Clone + Send + Sync + 'static> LimitedRoutes<S> for Router<S> {
+    fn limited_route(self, http: &HttpConfig, path: &str, method_router: MethodRouter<S>) -> Self {
+        let method_router = method_router.layer(DefaultBodyLimit::max(http.body_limit_for(path)));
+        let method_router = match http.timeout_for(path) {
+            Some(timeout) => method_router.layer(middleware::from_fn_with_state(timeout, enforce_timeout)),
+            None => method_router,
+        };
+        self.route(path, method_router)
+    }
+}
+
+// The timeout covers reading the upload as well as processing it. When it
+// expires the handler is dropped, which rolls back any open transaction.
+async fn enforce_timeout(State(timeout): State<Duration>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("{} timed out after {}s", path, timeout.as_secs());
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Request timed out after {}s", timeout.as_secs()),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::database::{Database, IngestJob, NewIngestJob};
+
+static NEXT_SPOOL_FILE: AtomicU64 = AtomicU64::new(0);
+
+const AUDIO_EXTENSIONS: &[&str] = &["wav", "flac", "ogg", "oga", "opus", "mp4", "m4a", "aac", "mkv", "webm", "mov"];
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSource {
+    // Every audio file directly inside the directory; "Artist - Title.ext"
+    // file names are split into artist and title.
+    Directory(PathBuf),
+    // CSV with `path,title,artist` rows; relative paths resolve against the
+    // manifest's directory.
+    Manifest(PathBuf),
+}
+
+// Background ingestion: uploads are spooled to disk and recorded in the
+// `ingest_jobs` table, then fingerprinted by a worker. Transient failures
+// (database busy, spool I/O) are retried with exponential backoff. Jobs that
+// exhaust their retries, hit a permanent error such as corrupt audio, or
+// panic in the decoder move to `dead_letter` with the error and spooled file
+// kept for investigation, until an operator requeues or discards them.
+// Finished jobs stay in the table as history.
+#[derive(Clone)]
+pub struct JobQueue {
+    db: Database,
+    config: Arc<Config>,
+    throttle: Arc<Throttle>,
+}
+
+// Limits shared by all ingest workers so bulk imports leave CPU for live
+// matching: a minimum spacing between job starts and a cap on how many
+// fingerprint computations ingestion runs at once.
+struct Throttle {
+    start_interval: Option<std::time::Duration>,
+    next_start: Mutex<Instant>,
+    fingerprint_slots: Option<Semaphore>,
+}
+
+impl Throttle {
+    async fn wait_for_start(&self) {
+        let Some(interval) = self.start_interval else {
+            return;
+        };
+
+        let start_at = {
+            let mut next_start = self.next_start.lock().await;
+            let start_at = (*next_start).max(Instant::now());
+            *next_start = start_at + interval;
+            start_at
+        };
+
+        tokio::time::sleep_until(start_at).await;
+    }
+}
+
+impl JobQueue {
+    pub fn new(db: Database, config: Arc<Config>) -> Self {
+        let throttle = Throttle {
+            start_interval: config
+                .jobs
+                .max_files_per_minute
+                .filter(|&n| n > 0)
+                .map(|n| std::time::Duration::from_secs_f64(60.0 / n as f64)),
+            next_start: Mutex::new(Instant::now()),
+            fingerprint_slots: config
+                .jobs
+                .max_concurrent_fingerprints
+                .map(|n| Semaphore::new(n.max(1))),
+        };
+
+        JobQueue {
+            db,
+            config,
+            throttle: Arc::new(throttle),
+        }
+    }
+
+    pub async fn enqueue_add_song(&self, audio_data: &[u8], title: &str, artist: &str) -> Result<i64> {
+        let spool_dir = &self.config.jobs.spool_dir;
+        tokio::fs::create_dir_all(spool_dir).await?;
+
+        let audio_path = spool_dir.join(format!(
+            "upload-{}-{}.audio",
+            Utc::now().timestamp_millis(),
+            NEXT_SPOOL_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&audio_path, audio_data).await?;
+
+        let job_id = self
+            .db
+            .insert_ingest_job(NewIngestJob {
+                title,
+                artist,
+                audio_path: &audio_path.to_string_lossy(),
+                max_attempts: self.config.jobs.max_attempts,
+                import_id: None,
+                delete_after: true,
+                target_song_id: None,
+            })
+            .await?;
+
+        info!("Queued ingest job {} for '{}' by {}", job_id, title, artist);
+        Ok(job_id)
+    }
+
+    // Queues one job per file of a server-side directory or manifest. Files
+    // are read in place, so the source must live under a configured import
+    // root.
+    pub async fn enqueue_import(&self, source: &ImportSource) -> Result<(i64, usize)> {
+        let (path, entries) = match source {
+            ImportSource::Directory(dir) => {
+                let dir = self.check_import_root(dir)?;
+                let entries = scan_directory(&dir)?;
+                (dir, entries)
+            }
+            ImportSource::Manifest(manifest) => {
+                let manifest = self.check_import_root(manifest)?;
+                let entries = read_manifest(&manifest)?;
+                for (path, _, _) in &entries {
+                    self.check_import_root(path)?;
+                }
+                (manifest, entries)
+            }
+        };
+
+        let import_id = self.db.create_import(&path.to_string_lossy()).await?;
+
+        for (audio_path, title, artist) in &entries {
+            self.db
+                .insert_ingest_job(NewIngestJob {
+                    title,
+                    artist,
+                    audio_path: &audio_path.to_string_lossy(),
+                    max_attempts: self.config.jobs.max_attempts,
+                    import_id: Some(import_id),
+                    delete_after: false,
+                    target_song_id: None,
+                })
+                .await?;
+        }
+
+        info!("Queued import {} with {} files from {}", import_id, entries.len(), path.display());
+        Ok((import_id, entries.len()))
+    }
+
+    // Queues the audio files in `dir` that no earlier job has seen, so a
+    // folder can be rescanned as files are added to it. Unlike imports the
+    // directory isn't checked against the import roots; this is for library
+    // callers, not HTTP clients.
+    pub async fn enqueue_new_files(&self, dir: &Path) -> Result<usize> {
+        let dir = dir
+            .canonicalize()
+            .map_err(|e| anyhow!("Cannot access {}: {}", dir.display(), e))?;
+        let known = self.db.ingest_job_paths().await?;
+
+        let mut queued = 0;
+        for (audio_path, title, artist) in scan_directory(&dir)? {
+            let audio_path = audio_path.to_string_lossy();
+            if known.contains(audio_path.as_ref()) {
+                continue;
+            }
+
+            self.db
+                .insert_ingest_job(NewIngestJob {
+                    title: &title,
+                    artist: &artist,
+                    audio_path: &audio_path,
+                    max_attempts: self.config.jobs.max_attempts,
+                    import_id: None,
+                    delete_after: false,
+                    target_song_id: None,
+                })
+                .await?;
+            queued += 1;
+        }
+
+        if queued > 0 {
+            info!("Queued {} new files from {}", queued, dir.display());
+        }
+        Ok(queued)
+    }
+
+    // Queues a re-fingerprint of every stale song whose original file is
+    // still under an import root. Returns the queued job ids and the songs
+    // skipped for lack of an original.
+    pub async fn enqueue_refingerprint(&self) -> Result<(Vec<i64>, Vec<i64>)> {
+        let mut queued = Vec::new();
+        let mut skipped = Vec::new();
+
+        for song in self.db.stale_songs(&self.config.hashing).await? {
+            if song.refingerprint_pending {
+                continue;
+            }
+            let Some(original) = song.original.as_deref().and_then(|path| self.check_import_root(Path::new(path)).ok()) else {
+                skipped.push(song.id);
+                continue;
+            };
+
+            let job_id = self
+                .db
+                .insert_ingest_job(NewIngestJob {
+                    title: &song.title,
+                    artist: &song.artist,
+                    audio_path: &original.to_string_lossy(),
+                    max_attempts: self.config.jobs.max_attempts,
+                    import_id: None,
+                    delete_after: false,
+                    target_song_id: Some(song.id),
+                })
+                .await?;
+            queued.push(job_id);
+        }
+
+        info!("Queued {} re-fingerprint jobs, {} stale songs have no original", queued.len(), skipped.len());
+        Ok((queued, skipped))
+    }
+
+    fn check_import_root(&self, path: &Path) -> Result<PathBuf> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| anyhow!("Cannot access {}: {}", path.display(), e))?;
+
+        let allowed = self
+            .config
+            .jobs
+            .import_roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| path.starts_with(root));
+
+        if !allowed {
+            return Err(anyhow!("{} is outside the configured import roots", path.display()));
+        }
+
+        Ok(path)
+    }
+
+    pub async fn start_worker(&self) -> Result<()> {
+        let requeued = self.db.requeue_running_jobs().await?;
+        if requeued > 0 {
+            warn!("Requeued {} ingest jobs interrupted by a restart", requeued);
+        }
+
+        let workers = self.config.jobs.workers.unwrap_or_else(num_cpus::get_physical).max(1);
+        info!("Starting {} ingest workers", workers);
+
+        for _ in 0..workers {
+            let queue = self.clone();
+            tokio::spawn(async move {
+                let poll_interval = std::time::Duration::from_millis(queue.config.jobs.poll_interval_ms);
+                loop {
+                    match queue.db.claim_next_job().await {
+                        // Only a claimed job takes a start slot, so idle
+                        // polls don't push back the next real start.
+                        Ok(Some(job)) => {
+                            queue.throttle.wait_for_start().await;
+                            queue.run(job).await
+                        }
+                        Ok(None) => tokio::time::sleep(poll_interval).await,
+                        Err(e) => {
+                            error!("Failed to claim ingest job: {}", e);
+                            tokio::time::sleep(poll_interval).await;
+                        }
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn run(&self, job: IngestJob) {
+        match self.process(&job).await {
+            Ok(song_id) => {
+                info!("Ingest job {} completed as song {}", job.id, song_id);
+                if let Err(e) = self.db.complete_job(job.id, song_id).await {
+                    error!("Failed to mark ingest job {} completed: {}", job.id, e);
+                }
+                if job.delete_after {
+                    let _ = tokio::fs::remove_file(&job.audio_path).await;
+                }
+            }
+            Err(e) => {
+                let message = e.to_string();
+                let retry_at = if is_transient(&e) && job.attempts < job.max_attempts {
+                    let backoff = self.config.jobs.retry_base_secs as i64 * 2i64.pow(job.attempts.saturating_sub(1) as u32);
+                    Some(Utc::now() + Duration::seconds(backoff))
+                } else {
+                    None
+                };
+
+                match retry_at {
+                    Some(at) => warn!("Ingest job {} failed (attempt {}), retrying at {}: {}", job.id, job.attempts, at, message),
+                    None => error!("Ingest job {} dead-lettered after {} attempts: {}", job.id, job.attempts, message),
+                }
+
+                if let Err(e) = self.db.fail_job(job.id, &message, retry_at).await {
+                    error!("Failed to record failure of ingest job {}: {}", job.id, e);
+                }
+            }
+        }
+    }
+
+    async fn process(&self, job: &IngestJob) -> Result<i64> {
+        // Caps what any one worker holds in memory. Returned as a plain error
+        // rather than I/O so an oversized file is not retried.
+        let size = tokio::fs::metadata(&job.audio_path).await?.len();
+        if size > self.config.jobs.max_file_bytes {
+            return Err(anyhow!(
+                "{} is {} bytes, over the {} byte per-job limit",
+                job.audio_path,
+                size,
+                self.config.jobs.max_file_bytes
+            ));
+        }
+
+        let audio_data = tokio::fs::read(PathBuf::from(&job.audio_path)).await?;
+        let hashing = self.config.hashing;
+        let config = self.config.clone();
+
+        let _slot = match &self.throttle.fingerprint_slots {
+            Some(slots) => Some(slots.acquire().await?),
+            None => None,
+        };
+
+        // Decoding runs on its own task so a panicking decoder fails this job
+        // instead of taking down the worker loop.
+        let fingerprint = tokio::task::spawn_blocking(move || {
+            let audio = crate::upload::decode_upload(&audio_data, &config, &crate::audio::Selection::default())?;
+            crate::fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &hashing)
+        })
+        .await
+        .map_err(|e| match e.try_into_panic() {
+            Ok(panic) => anyhow!("Decoder panicked: {}", panic_message(panic.as_ref())),
+            Err(e) => anyhow!("Fingerprint task failed: {}", e),
+        })??;
+
+        match job.target_song_id {
+            Some(song_id) => {
+                self.db
+                    .replace_song_fingerprint(song_id, &fingerprint)
+                    .await?
+                    .ok_or_else(|| anyhow!("Song {} no longer exists", song_id))?;
+                Ok(song_id)
+            }
+            None => self.db.add_song(&job.title, &job.artist, &fingerprint).await,
+        }
+    }
+
+    pub async fn requeue(&self, job_id: i64) -> Result<bool> {
+        self.db.requeue_dead_letter_job(job_id).await
+    }
+
+    pub async fn discard(&self, job_id: i64) -> Result<bool> {
+        match self.db.discard_dead_letter_job(job_id).await? {
+            Some((audio_path, true)) => {
+                if let Err(e) = tokio::fs::remove_file(&audio_path).await {
+                    warn!("Failed to remove spooled audio {} for job {}: {}", audio_path, job_id, e);
+                }
+                Ok(true)
+            }
+            Some((_, false)) => Ok(true),
+            None => Ok(false),
+        }
+    }
+}
+
+fn scan_directory(dir: &Path) -> Result<Vec<(PathBuf, String, String)>> {
+    let mut entries = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_audio = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        if !path.is_file() || !is_audio {
+            continue;
+        }
+
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let (artist, title) = match stem.split_once(" - ") {
+            Some((artist, title)) => (artist.trim().to_string(), title.trim().to_string()),
+            None => ("Unknown".to_string(), stem.clone()),
+        };
+        entries.push((path, title, artist));
+    }
+
+    entries.sort();
+    Ok(entries)
+}
+
+fn read_manifest(manifest: &Path) -> Result<Vec<(PathBuf, String, String)>> {
+    let base = manifest.parent().unwrap_or(Path::new("."));
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(manifest)
+        .map_err(|e| anyhow!("Failed to read manifest {}: {}", manifest.display(), e))?;
+
+    let mut entries = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| anyhow!("Invalid manifest row {}: {}", line + 2, e))?;
+        let (Some(path), Some(title), Some(artist)) = (record.get(0), record.get(1), record.get(2)) else {
+            return Err(anyhow!("Manifest row {} needs path, title and artist", line + 2));
+        };
+        entries.push((base.join(path.trim()), title.trim().to_string(), artist.trim().to_string()));
+    }
+
+    Ok(entries)
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+fn is_transient(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<std::io::Error>().is_some() {
+        return true;
+    }
+
+    match error.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::PoolTimedOut) | Some(sqlx::Error::Io(_)) => true,
+        Some(sqlx::Error::Database(db_error)) => {
+            // SQLITE_BUSY and SQLITE_LOCKED, including their extended codes.
+            matches!(
+                db_error.code().as_deref().and_then(|c| c.parse::<i32>().ok()).map(|c| c & 0xff),
+                Some(5) | Some(6)
+            )
+        }
+        _ => false,
+    }
+}
+
+pub fn parse_status_filter(status: Option<&str>) -> Result<Option<&str>> {
+    match status {
+        None => Ok(None),
+        Some(s @ ("queued" | "running" | "completed" | "dead_letter" | "discarded")) => Ok(Some(s)),
+        Some(other) => Err(anyhow!("Unknown job status '{}'", other)),
+    }
+}
@@ -0,0 +1,38 @@
+pub mod admin;
+pub mod audio;
+pub mod batch;
+pub mod bloom;
+pub mod cli;
+pub mod codec;
+pub mod config;
+pub mod database;
+pub mod decompression;
+pub mod embedded;
+pub mod evaluation;
+#[cfg(feature = "ffmpeg")]
+pub mod ffmpeg;
+pub mod fingerprint;
+pub mod form;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod http_limits;
+pub mod jobs;
+#[cfg(feature = "loadtest")]
+pub mod loadtest;
+pub mod logging;
+#[cfg(feature = "media-bridge")]
+pub mod media_bridge;
+pub mod negotiation;
+#[cfg(any(feature = "media-bridge", feature = "remote-fetch"))]
+pub mod network;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+#[cfg(feature = "remote-fetch")]
+pub mod remote_fetch;
+pub mod scheduler;
+pub mod scoring;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "synth")]
+pub mod synth;
+pub mod upload;
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::synth;
+
+#[derive(Debug, Clone)]
+pub struct LoadTestOptions {
+    // Base URL of the service; clips are posted to `<target>/match`.
+    pub target: String,
+    pub rps: u32,
+    pub clip_secs: f32,
+    pub duration_secs: u64,
+    pub max_in_flight: usize,
+    // Distinct clips to rotate through, so the server can't get away with
+    // caching a single fingerprint.
+    pub clips: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadTestReport {
+    pub sent: usize,
+    pub succeeded: usize,
+    // Non-2xx responses by status code.
+    pub failed: BTreeMap<u16, usize>,
+    // Connection errors and timeouts.
+    pub transport_errors: usize,
+    // Requests not sent because max_in_flight were already outstanding,
+    // i.e. the server couldn't keep up with the requested rate.
+    pub skipped: usize,
+    // Time spent issuing requests, and until the last one completed.
+    pub sending: Duration,
+    pub elapsed: Duration,
+    // Latencies of every completed request, sorted ascending.
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadTestReport {
+    // Nearest-rank percentile over completed requests.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = ((p / 100.0) * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+
+    pub fn errors(&self) -> usize {
+        self.failed.values().sum::<usize>() + self.transport_errors
+    }
+}
+
+enum Outcome {
+    Status(u16, Duration),
+    TransportError,
+}
+
+// Open-loop load: requests go out on a fixed schedule whether or not earlier
+// ones have finished, so a slow server shows up as rising latency and
+// skipped requests rather than a quietly lower request rate.
+pub async fn run(options: &LoadTestOptions) -> Result<LoadTestReport> {
+    if options.rps == 0 {
+        return Err(anyhow!("--rps must be at least 1"));
+    }
+
+    let url = format!("{}/match", options.target.trim_end_matches('/'));
+    let clips: Vec<Vec<u8>> = (0..options.clips.max(1) as u64)
+        .map(|seed| synth::to_wav(&synth::melody(seed, options.clip_secs)))
+        .collect::<Result<_>>()?;
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()
+        .map_err(|e| anyhow!("Failed to build HTTP client: {}", e))?;
+
+    let in_flight = Arc::new(Semaphore::new(options.max_in_flight.max(1)));
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / options.rps as f64));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+
+    let mut report = LoadTestReport::default();
+    let mut tasks = JoinSet::new();
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(options.duration_secs);
+
+    while Instant::now() < deadline {
+        ticker.tick().await;
+
+        let Ok(permit) = in_flight.clone().try_acquire_owned() else {
+            report.skipped += 1;
+            continue;
+        };
+
+        let client = client.clone();
+        let url = url.clone();
+        let clip = clips[report.sent % clips.len()].clone();
+        report.sent += 1;
+
+        tasks.spawn(async move {
+            let _permit = permit;
+            let sent_at = Instant::now();
+            let part = reqwest::multipart::Part::bytes(clip).file_name("clip.wav");
+            let form = reqwest::multipart::Form::new().part("audio", part);
+
+            match client.post(&url).multipart(form).send().await {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    // Latency includes reading the body, as a client would.
+                    let _ = response.bytes().await;
+                    Outcome::Status(status, sent_at.elapsed())
+                }
+                Err(_) => Outcome::TransportError,
+            }
+        });
+
+        while let Some(joined) = tasks.try_join_next() {
+            record(&mut report, joined);
+        }
+    }
+
+    report.sending = started.elapsed();
+
+    while let Some(joined) = tasks.join_next().await {
+        record(&mut report, joined);
+    }
+
+    report.elapsed = started.elapsed();
+    report.latencies.sort_unstable();
+    Ok(report)
+}
+
+fn record(report: &mut LoadTestReport, joined: Result<Outcome, tokio::task::JoinError>) {
+    match joined {
+        Ok(Outcome::Status(status, latency)) => {
+            report.latencies.push(latency);
+            if (200..300).contains(&status) {
+                report.succeeded += 1;
+            } else {
+                *report.failed.entry(status).or_default() += 1;
+            }
+        }
+        Ok(Outcome::TransportError) | Err(_) => report.transport_errors += 1,
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{anyhow, Result};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+const DEFAULT_FILTER: &str = "info";
+
+// Lets the log filter be swapped while the process runs, e.g. to turn on
+// `music_matcher::fingerprint=debug` during an incident without a restart.
+#[derive(Clone)]
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogControl {
+    // Installs the global subscriber. The initial filter comes from RUST_LOG,
+    // falling back to `info`.
+    pub fn init() -> Self {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+        let (filter, handle) = reload::Layer::new(filter);
+
+        tracing_subscriber::registry().with(filter).with(fmt::layer()).init();
+
+        LogControl { handle }
+    }
+
+    pub fn current(&self) -> Result<String> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| anyhow!("Failed to read log filter: {}", e))
+    }
+
+    // Accepts the same directive syntax as RUST_LOG, e.g.
+    // "info,music_matcher::fingerprint=debug".
+    pub fn set(&self, directives: &str) -> Result<String> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| anyhow!("Invalid log filter {:?}: {}", directives, e))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| anyhow!("Failed to apply log filter: {}", e))?;
+        self.current()
+    }
+}
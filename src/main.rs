@@ -1,20 +1,45 @@
 use axum::{
-    extract::Multipart,
-    http::StatusCode,
-    response::Json,
-    routing::{get, post},
+    body::{Body, Bytes},
+    extract::{Multipart, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
     Router,
 };
+use futures_util::TryStreamExt;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tower_http::cors::CorsLayer;
-use tracing::{info, error};
+use tracing::{debug, info, error};
 
-mod audio;
-mod database;
-mod fingerprint;
-
-use database::Database;
+use clap::Parser;
+use music_matcher::admin::{self, AdminAuth};
+use music_matcher::cli::{self, Cli, Command};
+use music_matcher::config::Config;
+use music_matcher::database::{CatalogChanges, CatalogSnapshot, Database, ImportProgress, IngestJob, MaintenanceReport, MatchPageQuery, MatchResult, SongFilter, SongMetadata, SongSummary, SongVersion, UnidentifiedClip};
+use music_matcher::form::{FormError, MultipartForm};
+use music_matcher::http_limits::LimitedRoutes;
+use music_matcher::jobs::{self, ImportSource, JobQueue};
+use music_matcher::logging::LogControl;
+use music_matcher::negotiation::{Negotiated, ResponseFormat};
+use music_matcher::scheduler::{JobStatus, Scheduler};
+use music_matcher::scoring::{MatchEvidence, MatchTier, TierThresholds};
+use music_matcher::upload::{self, StreamingDecoder};
+use music_matcher::{audio, batch, decompression, fingerprint};
+#[cfg(feature = "graphql")]
+use music_matcher::graphql;
+#[cfg(feature = "media-bridge")]
+use music_matcher::media_bridge;
+#[cfg(feature = "profiling")]
+use music_matcher::profiling;
+#[cfg(any(feature = "media-bridge", feature = "remote-fetch"))]
+use music_matcher::network::UrlRejected;
+#[cfg(feature = "remote-fetch")]
+use music_matcher::remote_fetch;
+#[cfg(feature = "sync")]
+use music_matcher::sync;
 
 #[derive(Serialize, Deserialize)]
 struct SongMatch {
@@ -22,36 +47,444 @@ struct SongMatch {
     title: String,
     artist: String,
     confidence: f64,
+    tier: MatchTier,
+    #[serde(flatten)]
+    evidence: MatchEvidence,
+    // Set when the clip only matched with its pitch scaled back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pitch_shift_percent: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct MatchResponse {
+    matched: bool,
+    // Tier of the best match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tier: Option<MatchTier>,
+    matches: Vec<SongMatch>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    combined: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clips: Option<Vec<ClipMatch>>,
+    // The matching.experiments entry that scored this request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    experiment: Option<String>,
+    // Set when matching.max_clip_secs cut the clip short.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    truncation: Option<audio::Truncation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MatchAllResponse {
+    matched: bool,
+    total: usize,
+    offset: usize,
+    limit: usize,
+    matches: Vec<SongMatch>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClipMatch {
+    clip_index: usize,
     matched: bool,
     matches: Vec<SongMatch>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    truncation: Option<audio::Truncation>,
+}
+
+// What adding a song learned about the upload, so ingestion pipelines can
+// flag problem files right away.
+#[derive(Serialize)]
+struct IngestReport {
+    success: bool,
+    song_id: i64,
+    format: audio::SourceFormat,
+    duration: f64,
+    sample_rate: u32,
+    hash_count: usize,
+    quality: audio::SignalQuality,
+    // Catalog songs the upload already matched before it was added.
+    duplicates: Vec<SongMatch>,
+    preprocessing: Vec<String>,
 }
 
 #[derive(Clone)]
 struct AppState {
     db: Database,
+    config: Arc<Config>,
+    scheduler: Scheduler,
+    jobs: JobQueue,
+    log_control: LogControl,
+    admin: AdminAuth,
+    #[cfg(feature = "sync")]
+    mirror: Option<sync::Mirror>,
+}
+
+#[derive(Deserialize)]
+struct JobListQuery {
+    status: Option<String>,
+    limit: Option<i64>,
+}
+
+// Names a matching.experiments entry to score a /match request with.
+const EXPERIMENT_HEADER: &str = "x-mm-experiment";
+
+#[derive(Deserialize)]
+struct MatchQuery {
+    // Overrides silence.mode for this request.
+    silence: Option<audio::SilenceMode>,
+    // Overrides denoise.enabled for this request.
+    denoise: Option<bool>,
+    // Same as the X-MM-Experiment header, which takes precedence.
+    experiment: Option<String>,
+}
+
+// Seconds into the upload to start from, and how many to use. The
+// `offset_secs` and `duration_secs` multipart fields are the alternative.
+// `channel` takes that channel (from 1) instead of the downmix.
+#[derive(Deserialize)]
+struct SelectionQuery {
+    offset: Option<f64>,
+    duration: Option<f64>,
+    channel: Option<u16>,
+}
+
+impl SelectionQuery {
+    fn selection(&self) -> Result<audio::Selection, (StatusCode, &'static str)> {
+        let window = time_window(self.offset, self.duration).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if self.channel == Some(0) {
+            return Err((StatusCode::BAD_REQUEST, "channel is numbered from 1"));
+        }
+
+        Ok(audio::Selection {
+            window,
+            channel: self.channel,
+        })
+    }
+}
+
+// Declared format of a raw PCM (`audio/pcm`) upload. Multipart fields of the
+// same names take precedence.
+#[derive(Deserialize)]
+struct PcmQuery {
+    sample_rate: Option<u32>,
+    bit_depth: Option<u16>,
+    channels: Option<u16>,
+    // "int" (the default) or "float".
+    sample_format: Option<String>,
+}
+
+impl MatchQuery {
+    fn experiment(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get(EXPERIMENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .or(self.experiment.as_deref())
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+    }
+}
+
+fn time_window(offset: Option<f64>, duration: Option<f64>) -> Result<audio::TimeWindow, &'static str> {
+    let offset_secs = offset.unwrap_or(0.0);
+    if !offset_secs.is_finite() || offset_secs < 0.0 {
+        return Err("offset must be a non-negative number of seconds");
+    }
+    if let Some(duration) = duration {
+        if !duration.is_finite() || duration <= 0.0 {
+            return Err("duration must be a positive number of seconds");
+        }
+    }
+
+    Ok(audio::TimeWindow {
+        offset_secs,
+        duration_secs: duration,
+    })
+}
+
+#[derive(Deserialize)]
+struct MatchAllQuery {
+    min_score: Option<f64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct MatchExportQuery {
+    after: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct SyncChangesQuery {
+    since: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct FieldsQuery {
+    // Comma-separated subset of SongSummary::FIELDS.
+    fields: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogLevel {
+    // RUST_LOG-style directives, e.g. "info,music_matcher::fingerprint=debug".
+    filter: String,
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    song_count: i64,
+    scheduled_jobs: Vec<JobStatus>,
+}
+
+#[derive(Serialize)]
+struct BatchMatchResult {
+    name: String,
+    matched: bool,
+    matches: Vec<SongMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UnidentifiedListQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct PromoteRequest {
+    title: String,
+    artist: String,
+}
+
+#[cfg(feature = "media-bridge")]
+#[derive(Deserialize)]
+struct AddSongUrlRequest {
+    url: String,
+    title: String,
+    artist: String,
+}
+
+#[cfg(feature = "remote-fetch")]
+#[derive(Deserialize)]
+struct AddSongFetchRequest {
+    url: String,
+    title: String,
+    artist: String,
+    #[serde(flatten)]
+    metadata: SongMetadata,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let log_control = LogControl::init();
+
+    let cli = Cli::parse();
+    let config = Config::load()?;
 
-    let db = Database::new("songs.db").await?;
+    match cli.command.unwrap_or(Command::Serve { repair_on_start: false }) {
+        Command::Serve { repair_on_start } => serve(config, repair_on_start, log_control).await,
+        Command::Stats => cli::run_stats(&open_database(&config).await?).await,
+        Command::Verify { repair } => cli::run_verify(&open_database(&config).await?, repair).await,
+        Command::Evaluate { corpus, json } => {
+            cli::run_evaluate(&open_database(&config).await?, &config, &corpus, json).await
+        }
+        #[cfg(feature = "loadtest")]
+        Command::Loadtest { target, rps, clip_len, duration, max_in_flight } => {
+            cli::run_loadtest(&music_matcher::loadtest::LoadTestOptions {
+                target,
+                rps,
+                clip_secs: clip_len,
+                duration_secs: duration,
+                max_in_flight,
+                clips: 16,
+            })
+            .await
+        }
+    }
+}
+
+async fn open_database(config: &Config) -> anyhow::Result<Database> {
+    let encryption_key = config.encryption.resolve_key()?;
+    let db = Database::new(&config.database_url, encryption_key.as_deref())
+        .await?
+        .with_scorer(config.matching.scorer)
+        .with_candidate_limit(config.matching.candidates)
+        .with_time_diff_tolerance(config.matching.time_diff_tolerance);
     db.init().await?;
 
-    let state = AppState { db };
+    Ok(db)
+}
+
+// A crash mid-write can leave a song without postings (or postings without
+// a song), which matching silently misses; fix those before taking traffic.
+async fn repair_index(db: &Database) -> anyhow::Result<()> {
+    let report = db.verify_catalog().await?;
+    if report.is_consistent() {
+        info!("Index verified: {} songs consistent", report.songs_checked);
+        return Ok(());
+    }
+
+    db.repair_catalog(&report).await?;
+    info!(
+        "Index repaired: removed {} orphaned postings, rebuilt postings for songs {:?}",
+        report.orphaned_postings, report.mismatched
+    );
+    for (song_id, e) in &report.unparsable {
+        error!("Song {} has an unparsable fingerprint and was removed from the index: {}", song_id, e);
+    }
+
+    Ok(())
+}
+
+async fn serve(mut config: Config, repair_on_start: bool, log_control: LogControl) -> anyhow::Result<()> {
+    let db = open_database(&config).await?;
+    if repair_on_start {
+        repair_index(&db).await?;
+    }
+
+    let addr = config.bind_addr;
+    let scheduler = Scheduler::start(&config.scheduler, db.clone())?;
+
+    // Batch archives are bounded by [batch] unless /match/batch sets its own
+    // body limit.
+    config
+        .http
+        .routes
+        .entry("/match/batch".to_string())
+        .or_default()
+        .max_body_bytes
+        .get_or_insert(config.batch.max_archive_bytes);
+    let config = Arc::new(config);
+
+    let jobs = JobQueue::new(db.clone(), config.clone());
+    jobs.start_worker().await?;
+
+    #[cfg(feature = "sync")]
+    let mirror = match config.sync.upstream.clone() {
+        Some(upstream) => {
+            info!(
+                "Mirroring catalog from {} every {}s{}",
+                upstream,
+                config.sync.interval_secs,
+                if config.sync.standby { " as a standby" } else { "" }
+            );
+            let mirror = sync::Mirror::new(config.sync.clone(), upstream, db.clone())?;
+            mirror.start();
+            Some(mirror)
+        }
+        None => None,
+    };
+
+    let admin = AdminAuth::new(config.admin.resolve_token(), config.admin.resolve_sync_token());
+    if !admin.is_configured() {
+        info!("{} is not set; admin endpoints are unauthenticated", config.admin.token_env);
+    }
+
+    let state = AppState {
+        db,
+        config,
+        scheduler,
+        jobs,
+        log_control,
+        admin,
+        #[cfg(feature = "sync")]
+        mirror,
+    };
 
+    let http = state.config.http.clone();
     let app = Router::new()
-        .route("/", get(health_check))
-        .route("/match", post(match_audio))
-        .route("/add-song", post(add_song))
+        .limited_route(&http, "/", get(health_check))
+        .limited_route(&http, "/stats", get(stats))
+        .limited_route(&http, "/match", post(match_audio))
+        .limited_route(&http, "/match-all", post(match_all))
+        .limited_route(&http, "/probe", post(probe_audio))
+        .limited_route(&http, "/matches/export", get(export_matches))
+        .limited_route(&http, "/add-song", post(add_song))
+        .limited_route(&http, "/songs", get(list_songs))
+        .limited_route(&http, "/songs/:id", get(get_song))
+        .limited_route(&http, "/songs/:id/audio", put(replace_song_audio))
+        .limited_route(&http, "/songs/:id/metadata", put(set_song_metadata))
+        .limited_route(&http, "/songs/:id/versions", get(list_song_versions))
+        .limited_route(&http, "/songs/:id/versions/:version/restore", post(restore_song_version))
+        .limited_route(&http, "/jobs", get(list_jobs))
+        .limited_route(&http, "/jobs/add-song", post(enqueue_add_song))
+        .limited_route(&http, "/jobs/:id", get(get_job))
+        .limited_route(&http, "/match/batch", post(match_batch));
+
+    let admin = Router::new()
+        .limited_route(&http, "/admin/maintenance", post(run_maintenance))
+        .limited_route(&http, "/admin/log-level", get(get_log_level).put(set_log_level))
+        .limited_route(&http, "/admin/snapshot", post(create_snapshot))
+        .limited_route(&http, "/admin/snapshots", get(list_snapshots))
+        .limited_route(&http, "/admin/rollback/:snapshot_id", post(rollback_snapshot))
+        .limited_route(&http, "/admin/stale-songs", get(stale_songs))
+        .limited_route(&http, "/admin/stale-songs/refingerprint", post(refingerprint_stale_songs))
+        .limited_route(&http, "/admin/unidentified", get(list_unidentified))
+        .limited_route(&http, "/admin/unidentified/:id", delete(discard_unidentified))
+        .limited_route(&http, "/admin/unidentified/:id/audio", get(unidentified_audio))
+        .limited_route(&http, "/admin/unidentified/:id/promote", post(promote_unidentified))
+        // Imports read server-side paths, and discarding or requeueing a job
+        // changes what the workers do, so these sit behind the token too.
+        .limited_route(&http, "/jobs/imports", post(start_import))
+        .limited_route(&http, "/jobs/imports/:id", get(import_progress))
+        .limited_route(&http, "/jobs/:id", delete(discard_job))
+        .limited_route(&http, "/jobs/:id/requeue", post(requeue_job));
+
+    #[cfg(feature = "profiling")]
+    let admin = admin.limited_route(&http, "/admin/profile", get(capture_profile));
+
+    #[cfg(feature = "sync")]
+    let admin = admin.limited_route(&http, "/admin/standby/promote", post(promote_standby));
+
+    let app = app.merge(admin.route_layer(axum::middleware::from_fn_with_state(
+        state.admin.clone(),
+        admin::require_admin,
+    )));
+
+    let app = app.merge(
+        Router::new()
+            .limited_route(&http, "/admin/sync/changes", get(sync_changes))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.admin.clone(),
+                admin::require_sync_reader,
+            )),
+    );
+
+    #[cfg(feature = "media-bridge")]
+    let app = app.limited_route(&http, "/add-song/url", post(add_song_from_url));
+
+    #[cfg(feature = "remote-fetch")]
+    let app = app.limited_route(&http, "/add-song/fetch", post(add_song_from_fetch));
+
+    #[cfg(feature = "graphql")]
+    let app = {
+        let schema = graphql::build_schema(state.db.clone());
+        app.limited_route(
+            &http,
+            "/graphql",
+            post(move |Json(request): Json<async_graphql::Request>| async move {
+                Json(schema.execute(request).await)
+            }),
+        )
+    };
+
+    #[cfg(feature = "sync")]
+    let app = match state.mirror.clone() {
+        Some(mirror) => app.route_layer(axum::middleware::from_fn_with_state(mirror, sync::reject_mirror_writes)),
+        None => app,
+    };
+
+    let app = app
+        .layer(axum::middleware::from_fn_with_state(
+            state.config.uploads.clone(),
+            decompression::decompress_request,
+        ))
         .layer(CorsLayer::permissive())
         .with_state(state);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     info!("Server running on http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
@@ -64,104 +497,1381 @@ async fn health_check() -> &'static str {
     "Audio matching service is running"
 }
 
+async fn stats(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    let song_count = state.db.count_songs().await.map_err(|e| {
+        error!("Stats error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(StatsResponse {
+        song_count,
+        scheduled_jobs: state.scheduler.status(),
+    }))
+}
+
 async fn match_audio(
     axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<MatchQuery>,
+    Query(selection_query): Query<SelectionQuery>,
+    Query(pcm_query): Query<PcmQuery>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Result<Json<MatchResponse>, StatusCode> {
-    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
-        if field.name() == Some("audio") {
-            let data = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-            
-            match process_audio_match(&state.db, &data).await {
-                Ok(response) => return Ok(Json(response)),
-                Err(e) => {
-                    error!("Audio processing error: {}", e);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            }
+) -> Result<Negotiated<MatchResponse>, Response> {
+    let selection = selection_query.selection().map_err(IntoResponse::into_response)?;
+    let experiment = query.experiment(&headers);
+    let state = experiment_state(state, experiment.as_deref()).map_err(IntoResponse::into_response)?;
+    let (form, decoders, options) = read_audio_form(&state, &mut multipart, &pcm_query, Some(selection)).await?;
+    form.require(&["audio"]).map_err(IntoResponse::into_response)?;
+    let filter = song_filter(&form).map_err(IntoResponse::into_response)?;
+    let silence = query.silence.unwrap_or(state.config.silence.mode);
+    let denoise = query.denoise.unwrap_or(state.config.denoise.enabled);
+
+    let result = async {
+        let clips = finish_decoding(decoders, &options).await?;
+        if clips.len() == 1 {
+            process_audio_match(&state, &clips[0], silence, denoise, &filter, experiment).await
+        } else {
+            process_multi_clip_match(&state, &clips, silence, denoise, &filter, experiment).await
         }
     }
-    
-    Err(StatusCode::BAD_REQUEST)
+    .await;
+
+    match result {
+        Ok(response) => Ok(Negotiated::new(&headers, response)),
+        Err(e) => Err(processing_error("Audio processing", e)),
+    }
 }
 
-async fn add_song(
+// Swaps in the scorer of the experiment a request opted into. Everything
+// else about the request, including the candidates retrieved, is unchanged.
+fn experiment_state(state: AppState, experiment: Option<&str>) -> Result<AppState, (StatusCode, String)> {
+    let Some(name) = experiment else {
+        return Ok(state);
+    };
+
+    match state.config.matching.experiments.get(name) {
+        Some(&scorer) => Ok(AppState {
+            db: state.db.clone().with_scorer(scorer),
+            ..state
+        }),
+        None => Err((StatusCode::BAD_REQUEST, format!("Unknown experiment: {}", name))),
+    }
+}
+
+// Every match for one clip, a page at a time. Unlike /match, nothing is
+// recorded in the match history, since paging would count a query repeatedly.
+async fn match_all(
     axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<MatchAllQuery>,
+    Query(pcm_query): Query<PcmQuery>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let mut audio_data = None;
-    let mut title = None;
-    let mut artist = None;
-
-    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
-        match field.name() {
-            Some("audio") => {
-                audio_data = Some(field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?);
-            }
-            Some("title") => {
-                title = Some(field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?);
-            }
-            Some("artist") => {
-                artist = Some(field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?);
+) -> Result<Negotiated<MatchAllResponse>, Response> {
+    let (form, decoders, options) = read_audio_form(&state, &mut multipart, &pcm_query, None).await?;
+    form.require(&["audio"]).map_err(IntoResponse::into_response)?;
+
+    let page = MatchPageQuery {
+        candidates: state.config.matching.match_all_candidates.max(1),
+        min_score: query.min_score.unwrap_or(0.0),
+        offset: query.offset.unwrap_or(0),
+        limit: query.limit.unwrap_or(50).clamp(1, 500),
+    };
+
+    let result = async {
+        let audio = first_decoded(decoders, &options).await?;
+        let fingerprint =
+            fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &state.config.match_hashing())?;
+        state.db.find_matches_page(&fingerprint, &page).await
+    }
+    .await;
+
+    match result {
+        Ok(found) => Ok(Negotiated::new(
+            &headers,
+            MatchAllResponse {
+                matched: found.total > 0,
+                total: found.total,
+                offset: page.offset,
+                limit: page.limit,
+                matches: to_song_matches(found.matches, &state.config.matching.tiers),
+            },
+        )),
+        Err(e) => Err(processing_error("Audio processing", e)),
+    }
+}
+
+// Reports what an upload holds without decoding or fingerprinting it. An
+// upload that can't be read is described in a 422, not logged as a failure.
+async fn probe_audio(mut multipart: Multipart) -> Result<Json<audio::AudioProbe>, Response> {
+    let form = MultipartForm::read(&mut multipart).await.map_err(IntoResponse::into_response)?;
+    form.require(&["audio"]).map_err(IntoResponse::into_response)?;
+    let audio_data = form.bytes("audio").unwrap_or_default();
+
+    match audio::probe(&audio_data) {
+        Ok(probe) => Ok(Json(probe)),
+        Err(e) if e.is::<audio::UnsupportedFormat>() => Err(processing_error("Probe", e)),
+        Err(e) => Err((StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response()),
+    }
+}
+
+async fn match_batch(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Negotiated<Vec<BatchMatchResult>>, Response> {
+    let form = MultipartForm::read(&mut multipart).await.map_err(IntoResponse::into_response)?;
+    form.require(&["archive"]).map_err(IntoResponse::into_response)?;
+    let archive = form.bytes("archive").unwrap_or_default();
+
+    let batch_config = state.config.batch.clone();
+    let entries = tokio::task::spawn_blocking(move || batch::extract_zip(&archive, &batch_config))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+        .map_err(|e| {
+            error!("Batch archive error: {}", e);
+            StatusCode::UNPROCESSABLE_ENTITY.into_response()
+        })?;
+
+    let semaphore = Arc::new(Semaphore::new(state.config.batch.concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let state = state.clone();
+        let semaphore = semaphore.clone();
+
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let config = state.config.clone();
+            let data = entry.data;
+            let result = async {
+                let query = tokio::task::spawn_blocking(move || {
+                    let audio = upload::decode_upload(&data, &config, &audio::Selection::default())?;
+                    prepare_query(&config, &audio, config.silence.mode, config.denoise.enabled)
+                })
+                .await??;
+                match_prepared_query(&state, &query, &SongFilter::default(), None).await
             }
-            _ => {}
+            .await;
+            (index, entry.name, result)
+        });
+    }
+
+    let mut results: Vec<Option<BatchMatchResult>> = Vec::new();
+    results.resize_with(tasks.len(), || None);
+
+    while let Some(joined) = tasks.join_next().await {
+        let (index, name, result) = joined.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+        results[index] = Some(match result {
+            Ok(response) => BatchMatchResult {
+                name,
+                matched: response.matched,
+                matches: response.matches,
+                error: None,
+            },
+            Err(e) => BatchMatchResult {
+                name,
+                matched: false,
+                matches: Vec::new(),
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    Ok(Negotiated::new(&headers, results.into_iter().flatten().collect()))
+}
+
+async fn add_song(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(selection_query): Query<SelectionQuery>,
+    Query(pcm_query): Query<PcmQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<IngestReport>, Response> {
+    let selection = selection_query.selection().map_err(IntoResponse::into_response)?;
+    let (form, decoders, options) = read_audio_form(&state, &mut multipart, &pcm_query, Some(selection)).await?;
+    let (title, artist) = song_fields(&form).map_err(IntoResponse::into_response)?;
+    let metadata = song_metadata(&form).map_err(IntoResponse::into_response)?;
+
+    let result = async {
+        let audio = first_decoded(decoders, &options).await?;
+        add_song_samples(&state, &audio, &title, &artist, &metadata).await
+    }
+    .await;
+
+    match result {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => Err(processing_error("Add song", e)),
+    }
+}
+
+async fn list_songs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<FieldsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let fields = parse_fields(query.fields.as_deref())?;
+
+    let result = async {
+        let revision = state.db.catalog_revision().await?;
+        let etag = catalog_etag(revision, ResponseFormat::from_headers(&headers));
+        if etag_matches(&headers, &etag) {
+            return anyhow::Ok(not_modified(etag));
+        }
+
+        let songs: Vec<serde_json::Value> = state
+            .db
+            .get_all_songs()
+            .await?
+            .iter()
+            .map(|song| select_fields(song, fields.as_deref()))
+            .collect::<Result<_, _>>()?;
+        Ok(([(header::ETAG, etag)], Negotiated::new(&headers, songs)).into_response())
+    }
+    .await;
+
+    result.map_err(|e| {
+        error!("List songs error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn get_song(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(song_id): Path<i64>,
+    Query(query): Query<FieldsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let fields = parse_fields(query.fields.as_deref())?;
+
+    let result = async {
+        let revision = state.db.catalog_revision().await?;
+        let etag = catalog_etag(revision, ResponseFormat::from_headers(&headers));
+        if etag_matches(&headers, &etag) {
+            return anyhow::Ok(Some(not_modified(etag)));
         }
+
+        let Some(song) = state.db.get_song(song_id).await? else {
+            return Ok(None);
+        };
+        let song = select_fields(&song, fields.as_deref())?;
+        Ok(Some(([(header::ETAG, etag)], Negotiated::new(&headers, song)).into_response()))
     }
+    .await;
 
-    let audio_data = audio_data.ok_or(StatusCode::BAD_REQUEST)?;
-    let title = title.ok_or(StatusCode::BAD_REQUEST)?;
-    let artist = artist.ok_or(StatusCode::BAD_REQUEST)?;
+    match result {
+        Ok(Some(response)) => Ok(response),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Get song error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Unknown field names are rejected rather than ignored, so a typo doesn't
+// silently return an empty object.
+fn parse_fields(fields: Option<&str>) -> Result<Option<Vec<String>>, StatusCode> {
+    let Some(fields) = fields else {
+        return Ok(None);
+    };
+
+    let fields: Vec<String> = fields
+        .split(',')
+        .map(|field| field.trim())
+        .filter(|field| !field.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    if fields.iter().any(|field| !SongSummary::FIELDS.contains(&field.as_str())) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(Some(fields))
+}
+
+fn select_fields(song: &SongSummary, fields: Option<&[String]>) -> serde_json::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(song)?;
+    if let (Some(fields), Some(object)) = (fields, value.as_object_mut()) {
+        object.retain(|key, _| fields.iter().any(|field| field == key));
+    }
+    Ok(value)
+}
+
+// Song representations change only when the catalog does, so the catalog
+// revision serves as the validator for every song resource. The format is
+// part of the tag since JSON and MessagePack bodies differ byte for byte.
+fn catalog_etag(revision: i64, format: ResponseFormat) -> String {
+    format!("\"catalog-{}-{}\"", revision, format.tag())
+}
+
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+fn not_modified(etag: String) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [(header::ETAG, etag), (header::VARY, "accept".to_string())],
+    )
+        .into_response()
+}
 
-    match process_add_song(&state.db, &audio_data, &title, &artist).await {
-        Ok(song_id) => Ok(Json(serde_json::json!({
+async fn replace_song_audio(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(song_id): Path<i64>,
+    Query(pcm_query): Query<PcmQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, Response> {
+    let (form, decoders, options) = read_audio_form(&state, &mut multipart, &pcm_query, None).await?;
+    form.require(&["audio"]).map_err(IntoResponse::into_response)?;
+
+    let result = async {
+        let audio = first_decoded(decoders, &options).await?;
+        process_replace_song(&state, song_id, &audio).await
+    }
+    .await;
+
+    match result {
+        Ok(Some(version)) => Ok(Json(serde_json::json!({
+            "success": true,
+            "song_id": song_id,
+            "version": version
+        }))),
+        Ok(None) => Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => Err(processing_error("Replace song audio", e)),
+    }
+}
+
+async fn set_song_metadata(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(song_id): Path<i64>,
+    Json(metadata): Json<SongMetadata>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    match state.db.set_song_metadata(song_id, &metadata).await {
+        Ok(true) => Ok(Json(serde_json::json!({
             "success": true,
             "song_id": song_id
         }))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            error!("Add song error: {}", e);
+            error!("Set song metadata error: {}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-async fn process_audio_match(db: &Database, audio_data: &[u8]) -> anyhow::Result<MatchResponse> {
-    let audio_samples = audio::decode_audio(audio_data)?;
-    let fingerprint = fingerprint::generate_fingerprint(&audio_samples)?;
-    
-    let all_matches = db.find_all_matches(&fingerprint).await?;
-    
-    if all_matches.is_empty() {
-        Ok(MatchResponse {
-            matched: false,
-            matches: Vec::new(),
-        })
+async fn list_song_versions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(song_id): Path<i64>,
+) -> Result<Json<Vec<SongVersion>>, StatusCode> {
+    state.db.list_song_versions(song_id).await.map(Json).map_err(|e| {
+        error!("List song versions error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn restore_song_version(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((song_id, version)): Path<(i64, i64)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    match state.db.restore_song_version(song_id, version).await {
+        Ok(Some(new_version)) => Ok(Json(serde_json::json!({
+            "success": true,
+            "song_id": song_id,
+            "version": new_version,
+            "restored_from": version
+        }))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Restore song version error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Inputs over the decode limits are the client's to fix, so they get 413 and
+// the reason; anything else is logged and reported as a server error.
+fn processing_error(context: &str, e: anyhow::Error) -> Response {
+    if let Some(limit) = e.downcast_ref::<audio::LimitExceeded>() {
+        return (StatusCode::PAYLOAD_TOO_LARGE, limit.to_string()).into_response();
+    }
+    if let Some(format) = e.downcast_ref::<audio::UnsupportedFormat>() {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, format.to_string()).into_response();
+    }
+    if let Some(window) = e.downcast_ref::<audio::WindowOutOfRange>() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, window.to_string()).into_response();
+    }
+    if let Some(channel) = e.downcast_ref::<audio::ChannelOutOfRange>() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, channel.to_string()).into_response();
+    }
+
+    error!("{} error: {}", context, e);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+const SONG_FIELDS: [&str; 3] = ["audio", "title", "artist"];
+
+// Buffers the whole upload, for routes that keep the file rather than decode it.
+async fn read_song_form(multipart: &mut Multipart) -> Result<(Bytes, String, String), Response> {
+    let form = MultipartForm::read(multipart).await.map_err(IntoResponse::into_response)?;
+    let (title, artist) = song_fields(&form).map_err(IntoResponse::into_response)?;
+
+    Ok((form.bytes("audio").unwrap_or_default(), title, artist))
+}
+
+fn song_fields(form: &MultipartForm) -> Result<(String, String), FormError> {
+    form.require(&SONG_FIELDS)?;
+
+    let title = form.text("title")?.unwrap_or_default();
+    let artist = form.text("artist")?.unwrap_or_default();
+
+    Ok((title, artist))
+}
+
+// Optional /add-song fields: `year`, and `tags` as a comma-separated list.
+fn song_metadata(form: &MultipartForm) -> Result<SongMetadata, FormError> {
+    let tags = form.text("tags")?.unwrap_or_default();
+
+    Ok(SongMetadata {
+        year: form.integer("year")?,
+        tags: tags.split(',').map(str::to_string).collect(),
+    })
+}
+
+// Optional /match fields that scope the catalog searched: `artist` (may be
+// repeated), `tag`, `year_from` and `year_to`.
+fn song_filter(form: &MultipartForm) -> Result<SongFilter, FormError> {
+    Ok(SongFilter {
+        artists: form.all_text("artist")?,
+        tag: form.text("tag")?,
+        year_from: form.integer("year_from")?,
+        year_to: form.integer("year_to")?,
+    })
+}
+
+// What decoding needs from the rest of the form once it has been read.
+struct DecodeOptions {
+    // The declared format of raw PCM parts.
+    pcm: Option<audio::PcmFormat>,
+    selection: audio::Selection,
+}
+
+// Reads a multipart upload, decoding each `audio` part while it arrives.
+// Returns one decoder per part, in upload order, and the DecodeOptions the
+// whole form settles: the declared format when any part is raw PCM, and the
+// window and channel to cut from the decoded audio.
+//
+// `selection` is the query's on routes that take one, and None on routes that
+// don't. Window fields sent before an audio part let only the window be
+// decoded; ones sent after it are cut from the whole decoded upload.
+async fn read_audio_form(
+    state: &AppState,
+    multipart: &mut Multipart,
+    pcm_query: &PcmQuery,
+    selection: Option<audio::Selection>,
+) -> Result<(MultipartForm, Vec<StreamingDecoder>, DecodeOptions), Response> {
+    let mut decoders = Vec::new();
+    let form = MultipartForm::read_streaming(multipart, "audio", |fields, content_type| {
+        let (chunks, decoder) = if upload::is_raw_pcm(content_type) {
+            StreamingDecoder::collect_pcm(state.config.limits)
+        } else {
+            let known = match selection {
+                Some(query) => audio::Selection {
+                    window: form_window(fields).ok().flatten().unwrap_or(query.window),
+                    ..query
+                },
+                None => audio::Selection::default(),
+            };
+            StreamingDecoder::start(&state.config, known)
+        };
+        decoders.push(decoder);
+        chunks
+    })
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    let pcm = if decoders.iter().any(StreamingDecoder::is_pcm) {
+        Some(pcm_format(&form, pcm_query).map_err(IntoResponse::into_response)?)
     } else {
-        let matches = all_matches
+        None
+    };
+    let selection = match selection {
+        Some(query) => match form_window(&form).map_err(IntoResponse::into_response)? {
+            Some(_) if query.window != audio::TimeWindow::default() => {
+                let message = "Give the window as offset/duration query parameters or as offset_secs/duration_secs fields, not both";
+                return Err(form.invalid(message.to_string()).into_response());
+            }
+            Some(window) => audio::Selection { window, ..query },
+            None => query,
+        },
+        None => audio::Selection::default(),
+    };
+
+    Ok((form, decoders, DecodeOptions { pcm, selection }))
+}
+
+// The window given in `offset_secs` and `duration_secs` fields, if either is.
+fn form_window(form: &MultipartForm) -> Result<Option<audio::TimeWindow>, FormError> {
+    let offset = form.number("offset_secs")?;
+    let duration = form.number("duration_secs")?;
+    if offset.is_none() && duration.is_none() {
+        return Ok(None);
+    }
+
+    time_window(offset, duration)
+        .map(Some)
+        .map_err(|e| form.invalid(format!("Invalid window in multipart fields: {}", e)))
+}
+
+// The format of a raw PCM upload, from the multipart fields or failing that
+// the query string. Sample rate, bit depth and channel count are required.
+fn pcm_format(form: &MultipartForm, query: &PcmQuery) -> Result<audio::PcmFormat, FormError> {
+    fn field<T: TryFrom<i64>>(form: &MultipartForm, name: &'static str) -> Result<Option<T>, FormError> {
+        match form.integer(name)? {
+            None => Ok(None),
+            Some(value) => T::try_from(value)
+                .map(Some)
+                .map_err(|_| form.invalid(format!("Multipart field {} is out of range", name))),
+        }
+    }
+
+    let sample_rate = field(form, "sample_rate")?.or(query.sample_rate);
+    let bit_depth = field(form, "bit_depth")?.or(query.bit_depth);
+    let channels = field(form, "channels")?.or(query.channels);
+    let float = match form.text("sample_format")?.or_else(|| query.sample_format.clone()).as_deref() {
+        None | Some("int") => false,
+        Some("float") => true,
+        Some(other) => return Err(form.invalid(format!("sample_format must be int or float, not {}", other))),
+    };
+
+    match (sample_rate, bit_depth, channels) {
+        (Some(sample_rate), Some(bit_depth), Some(channels)) => Ok(audio::PcmFormat {
+            sample_rate,
+            bit_depth,
+            channels,
+            float,
+        }),
+        _ => {
+            let missing: Vec<&str> = [
+                ("sample_rate", sample_rate.is_none()),
+                ("bit_depth", bit_depth.is_none()),
+                ("channels", channels.is_none()),
+            ]
             .into_iter()
-            .map(|(song_id, title, artist, confidence)| SongMatch {
-                song_id,
-                title,
-                artist,
-                confidence,
-            })
+            .filter_map(|(name, missing)| missing.then_some(name))
             .collect();
-            
-        Ok(MatchResponse {
-            matched: true,
+            Err(form.invalid(format!("Raw PCM upload is missing its {}", missing.join(", "))))
+        }
+    }
+}
+
+async fn finish_decoding(
+    decoders: Vec<StreamingDecoder>,
+    options: &DecodeOptions,
+) -> anyhow::Result<Vec<audio::DecodedAudio>> {
+    let mut clips = Vec::with_capacity(decoders.len());
+    for decoder in decoders {
+        clips.push(decoder.finish(options.pcm.as_ref(), &options.selection).await?);
+    }
+    Ok(clips)
+}
+
+// Routes that take one file use the first `audio` part, as form.bytes does.
+async fn first_decoded(
+    decoders: Vec<StreamingDecoder>,
+    options: &DecodeOptions,
+) -> anyhow::Result<audio::DecodedAudio> {
+    match decoders.into_iter().next() {
+        Some(decoder) => decoder.finish(options.pcm.as_ref(), &options.selection).await,
+        None => Err(anyhow::anyhow!("No audio uploaded")),
+    }
+}
+
+async fn enqueue_add_song(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<serde_json::Value>), Response> {
+    let (audio_data, title, artist) = read_song_form(&mut multipart).await?;
+
+    match state.jobs.enqueue_add_song(&audio_data, &title, &artist).await {
+        Ok(job_id) => Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({
+                "success": true,
+                "job_id": job_id
+            })),
+        )),
+        Err(e) => {
+            error!("Enqueue error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+async fn start_import(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(source): Json<ImportSource>,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, String)> {
+    match state.jobs.enqueue_import(&source).await {
+        Ok((import_id, queued)) => Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({
+                "import_id": import_id,
+                "queued": queued
+            })),
+        )),
+        Err(e) => {
+            error!("Import error: {}", e);
+            Err((StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))
+        }
+    }
+}
+
+async fn import_progress(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(import_id): Path<i64>,
+) -> Result<Json<ImportProgress>, StatusCode> {
+    match state.db.import_progress(import_id).await {
+        Ok(Some(progress)) => Ok(Json(progress)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Import progress error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn list_jobs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<JobListQuery>,
+) -> Result<Json<Vec<IngestJob>>, StatusCode> {
+    let status = jobs::parse_status_filter(query.status.as_deref()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    state.db.list_jobs(status, limit).await.map(Json).map_err(|e| {
+        error!("List jobs error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn get_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(job_id): Path<i64>,
+) -> Result<Json<IngestJob>, StatusCode> {
+    match state.db.get_job(job_id).await {
+        Ok(Some(job)) => Ok(Json(job)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Get job error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn requeue_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(job_id): Path<i64>,
+) -> StatusCode {
+    match state.jobs.requeue(job_id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::CONFLICT,
+        Err(e) => {
+            error!("Requeue job error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn discard_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(job_id): Path<i64>,
+) -> StatusCode {
+    match state.jobs.discard(job_id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::CONFLICT,
+        Err(e) => {
+            error!("Discard job error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn run_maintenance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<Json<MaintenanceReport>, StatusCode> {
+    match state.db.run_maintenance().await {
+        Ok(report) => {
+            if !report.integrity_ok {
+                error!("Integrity check reported problems: {:?}", report.integrity_findings);
+            }
+            info!("Maintenance complete, reclaimed {} bytes", report.reclaimed_bytes);
+            Ok(Json(report))
+        }
+        Err(e) => {
+            error!("Maintenance error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn get_log_level(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<Json<LogLevel>, StatusCode> {
+    match state.log_control.current() {
+        Ok(filter) => Ok(Json(LogLevel { filter })),
+        Err(e) => {
+            error!("Log level error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn set_log_level(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(request): Json<LogLevel>,
+) -> Result<Json<LogLevel>, (StatusCode, String)> {
+    match state.log_control.set(&request.filter) {
+        Ok(filter) => {
+            info!("Log filter changed to {}", filter);
+            Ok(Json(LogLevel { filter }))
+        }
+        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+    }
+}
+
+#[cfg(feature = "profiling")]
+#[derive(Deserialize)]
+struct ProfileQuery {
+    #[serde(default = "default_profile_seconds")]
+    seconds: u64,
+    #[serde(default)]
+    format: profiling::ProfileFormat,
+}
+
+#[cfg(feature = "profiling")]
+fn default_profile_seconds() -> u64 {
+    30
+}
+
+// Stack samples expose internals, so unlike the other admin endpoints this
+// one is never served without an admin token.
+#[cfg(feature = "profiling")]
+async fn capture_profile(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<ProfileQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    if !state.admin.is_configured() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Profiling requires {} to be set", state.config.admin.token_env),
+        ));
+    }
+
+    info!("Capturing {}s {:?} profile", query.seconds, query.format);
+    match profiling::capture(query.seconds, query.format).await {
+        Ok((content_type, body)) => Ok(([(header::CONTENT_TYPE, content_type)], body).into_response()),
+        Err(e) if e.is::<profiling::ProfilerBusy>() => Err((StatusCode::CONFLICT, e.to_string())),
+        Err(e) => {
+            error!("Profiling error: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+    }
+}
+
+async fn create_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<(StatusCode, Json<CatalogSnapshot>), StatusCode> {
+    match state.db.create_snapshot(&state.config.snapshots.dir).await {
+        Ok(snapshot) => {
+            info!("Created catalog snapshot {} at {}", snapshot.id, snapshot.path);
+            Ok((StatusCode::CREATED, Json(snapshot)))
+        }
+        Err(e) => {
+            error!("Snapshot error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn list_snapshots(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<Json<Vec<CatalogSnapshot>>, StatusCode> {
+    state.db.list_snapshots().await.map(Json).map_err(|e| {
+        error!("List snapshots error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// Rows of match history read per page of an export. The next page is only
+// read once the client has taken the previous one, so a slow consumer holds
+// one page in memory rather than the whole history.
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+// Streams match history as ndjson, oldest first. The export stops at the
+// newest row when it started; `after` resumes from the last id received.
+async fn export_matches(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<MatchExportQuery>,
+) -> Result<Response, StatusCode> {
+    let until = state.db.latest_match_id().await.map_err(|e| {
+        error!("Match export error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let db = state.db.clone();
+    let pages = futures_util::stream::try_unfold(query.after.unwrap_or(0), move |cursor| {
+        let db = db.clone();
+        async move {
+            let entries = db.match_history_after(cursor, until, EXPORT_PAGE_SIZE).await?;
+            let Some(last) = entries.last().map(|entry| entry.id) else {
+                return Ok(None);
+            };
+
+            let mut chunk = Vec::new();
+            for entry in &entries {
+                serde_json::to_writer(&mut chunk, entry)?;
+                chunk.push(b'\n');
+            }
+            anyhow::Ok(Some((Bytes::from(chunk), last)))
+        }
+    })
+    // A failure mid-stream can only abort the response; the client resumes
+    // from the last complete line.
+    .map_err(|e| {
+        error!("Match export error: {}", e);
+        std::io::Error::other(e.to_string())
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::HeaderName::from_static("x-export-until"), until.to_string()),
+        ],
+        Body::from_stream(pages),
+    )
+        .into_response())
+}
+
+// Change log pages for edge instances mirroring this catalog.
+async fn sync_changes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<SyncChangesQuery>,
+) -> Result<Json<CatalogChanges>, StatusCode> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    state
+        .db
+        .catalog_changes(query.since.unwrap_or(0), limit)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Sync changes error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[cfg(feature = "sync")]
+async fn promote_standby(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<Json<sync::Promotion>, (StatusCode, &'static str)> {
+    let mirror = match &state.mirror {
+        Some(mirror) if mirror.is_standby() => mirror,
+        _ => return Err((StatusCode::CONFLICT, "This instance is not a standby")),
+    };
+
+    match mirror.promote().await {
+        Some(promotion) => Ok(Json(promotion)),
+        None => Err((StatusCode::CONFLICT, "This instance has already been promoted")),
+    }
+}
+
+async fn stale_songs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    match state.db.stale_songs(&state.config.hashing).await {
+        Ok(songs) => Ok(Json(serde_json::json!({
+            "algorithm_version": fingerprint::ALGORITHM_VERSION,
+            "params_digest": state.config.hashing.digest(),
+            "songs": songs
+        }))),
+        Err(e) => {
+            error!("Stale songs error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn refingerprint_stale_songs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    match state.jobs.enqueue_refingerprint().await {
+        Ok((job_ids, without_original)) => Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({
+                "queued": job_ids.len(),
+                "job_ids": job_ids,
+                "without_original": without_original
+            })),
+        )),
+        Err(e) => {
+            error!("Re-fingerprint error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn list_unidentified(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Query(query): Query<UnidentifiedListQuery>,
+) -> Result<Json<Vec<UnidentifiedClip>>, StatusCode> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    state.db.list_unidentified(limit).await.map(Json).map_err(|e| {
+        error!("List unidentified error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn unidentified_audio(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(clip_id): Path<i64>,
+) -> Result<Response, StatusCode> {
+    let result = async {
+        let Some(clip) = state.db.get_unidentified(clip_id).await? else {
+            return anyhow::Ok(None);
+        };
+        let snippet = tokio::fs::read(&clip.snippet_path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read snippet {}: {}", clip.snippet_path, e))?;
+        Ok(Some(snippet))
+    }
+    .await;
+
+    match result {
+        Ok(Some(snippet)) => Ok(([(header::CONTENT_TYPE, "audio/wav")], snippet).into_response()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Unidentified audio error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Adds the clip's fingerprint to the catalog under the given labels.
+async fn promote_unidentified(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(clip_id): Path<i64>,
+    Json(request): Json<PromoteRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    match state.db.promote_unidentified(clip_id, &request.title, &request.artist).await {
+        Ok(Some((song_id, clip))) => {
+            remove_snippet(&clip).await;
+            info!("Promoted unidentified clip {} to song {}", clip_id, song_id);
+            Ok(Json(serde_json::json!({
+                "success": true,
+                "song_id": song_id
+            })))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Promote unidentified error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn discard_unidentified(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(clip_id): Path<i64>,
+) -> StatusCode {
+    match state.db.delete_unidentified(clip_id).await {
+        Ok(Some(clip)) => {
+            remove_snippet(&clip).await;
+            StatusCode::NO_CONTENT
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Discard unidentified error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn remove_snippet(clip: &UnidentifiedClip) {
+    if let Err(e) = tokio::fs::remove_file(&clip.snippet_path).await {
+        error!("Failed to remove snippet {}: {}", clip.snippet_path, e);
+    }
+}
+
+// Snapshots the current catalog first, so the rollback itself can be undone.
+async fn rollback_snapshot(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(snapshot_id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let result = async {
+        if state.db.get_snapshot(snapshot_id).await?.is_none() {
+            return Ok(None);
+        }
+        let before = state.db.create_snapshot(&state.config.snapshots.dir).await?;
+        let restored = state.db.rollback_to_snapshot(snapshot_id).await?;
+        anyhow::Ok(restored.map(|snapshot| (before, snapshot)))
+    }
+    .await;
+
+    match result {
+        Ok(Some((before, snapshot))) => {
+            info!(
+                "Rolled catalog back to snapshot {} ({} songs); previous state saved as snapshot {}",
+                snapshot.id, snapshot.song_count, before.id
+            );
+            Ok(Json(serde_json::json!({
+                "success": true,
+                "snapshot_id": snapshot.id,
+                "song_count": snapshot.song_count,
+                "pre_rollback_snapshot_id": before.id
+            })))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Rollback error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(feature = "media-bridge")]
+async fn add_song_from_url(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(request): Json<AddSongUrlRequest>,
+) -> Result<Json<IngestReport>, Response> {
+    let audio = match media_bridge::fetch_media(&request.url, &state.config.media_bridge).await {
+        Ok(audio) => audio,
+        Err(e) if e.is::<UrlRejected>() => return Err((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+        Err(e) => {
+            error!("Media bridge error for {}: {}", request.url, e);
+            return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
+        }
+    };
+
+    match add_song_samples(&state, &audio, &request.title, &request.artist, &SongMetadata::default()).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => {
+            error!("Add song error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+#[cfg(feature = "remote-fetch")]
+async fn add_song_from_fetch(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(request): Json<AddSongFetchRequest>,
+) -> Result<Json<IngestReport>, Response> {
+    let result = async {
+        let data = remote_fetch::fetch_audio(&request.url, &state.config.remote_fetch).await?;
+        let config = state.config.clone();
+        let audio = tokio::task::spawn_blocking(move || upload::decode_upload(&data, &config, &audio::Selection::default()))
+            .await??;
+        add_song_samples(&state, &audio, &request.title, &request.artist, &request.metadata).await
+    }
+    .await;
+
+    match result {
+        Ok(report) => Ok(Json(report)),
+        Err(e) if e.is::<UrlRejected>() => Err((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+        Err(e) if e.is::<remote_fetch::FetchFailed>() => {
+            error!("Remote fetch error: {}", e);
+            Err((StatusCode::BAD_GATEWAY, e.to_string()).into_response())
+        }
+        Err(e) => Err(processing_error("Add song", e)),
+    }
+}
+
+async fn process_audio_match(
+    state: &AppState,
+    audio: &audio::DecodedAudio,
+    silence: audio::SilenceMode,
+    denoise: bool,
+    filter: &SongFilter,
+    experiment: Option<String>,
+) -> anyhow::Result<MatchResponse> {
+    let query = prepare_query(&state.config, audio, silence, denoise)?;
+    match_prepared_query(state, &query, filter, experiment).await
+}
+
+// A clip denoised, trimmed and fingerprinted, ready to look up.
+struct PreparedQuery {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    fingerprint: fingerprint::AudioFingerprint,
+    truncation: Option<audio::Truncation>,
+}
+
+// The CPU-bound half of process_audio_match, for callers that run it on a
+// blocking thread.
+fn prepare_query(
+    config: &Config,
+    audio: &audio::DecodedAudio,
+    silence: audio::SilenceMode,
+    denoise: bool,
+) -> anyhow::Result<PreparedQuery> {
+    let audio_samples = audio::denoise(&audio.samples, audio.sample_rate, denoise, &config.denoise);
+    let audio_samples = audio::remove_silence(&audio_samples, audio.sample_rate, silence, &config.silence);
+    let (audio_samples, truncation) = audio::truncate(&audio_samples, audio.sample_rate, config.matching.max_clip_secs);
+    let fingerprint = fingerprint::generate_fingerprint(audio_samples, audio.sample_rate, &config.match_hashing())?;
+
+    Ok(PreparedQuery {
+        samples: audio_samples.to_vec(),
+        sample_rate: audio.sample_rate,
+        fingerprint,
+        truncation,
+    })
+}
+
+async fn match_prepared_query(
+    state: &AppState,
+    query: &PreparedQuery,
+    filter: &SongFilter,
+    experiment: Option<String>,
+) -> anyhow::Result<MatchResponse> {
+    let matches = to_song_matches(
+        find_query_matches(state, &query.fingerprint, filter).await?,
+        &state.config.matching.tiers,
+    );
+    record_match(state, &matches, experiment.as_deref()).await;
+    // A miss within a filtered catalog doesn't make the clip unknown.
+    if matches.is_empty() && filter.is_empty() {
+        capture_unidentified(state, &query.samples, query.sample_rate, &query.fingerprint).await;
+    }
+
+    Ok(MatchResponse {
+        matched: !matches.is_empty(),
+        tier: matches.first().map(|m| m.tier),
+        matches,
+        combined: None,
+        clips: None,
+        experiment,
+        truncation: query.truncation,
+    })
+}
+
+// Fingerprints each clip on its own. When every clip that matched anything
+// agrees on the same top song, the clips are treated as segments of one
+// capture and their hashes are merged into a single fingerprint, which gives
+// a stronger verdict than any individual segment.
+async fn process_multi_clip_match(
+    state: &AppState,
+    clips: &[audio::DecodedAudio],
+    silence: audio::SilenceMode,
+    denoise: bool,
+    filter: &SongFilter,
+    experiment: Option<String>,
+) -> anyhow::Result<MatchResponse> {
+    let mut fingerprints = Vec::with_capacity(clips.len());
+    let mut clip_results = Vec::with_capacity(clips.len());
+
+    for (clip_index, audio) in clips.iter().enumerate() {
+        let audio_samples = audio::denoise(&audio.samples, audio.sample_rate, denoise, &state.config.denoise);
+        let audio_samples = audio::remove_silence(&audio_samples, audio.sample_rate, silence, &state.config.silence);
+        let (audio_samples, truncation) =
+            audio::truncate(&audio_samples, audio.sample_rate, state.config.matching.max_clip_secs);
+        let fingerprint = fingerprint::generate_fingerprint(audio_samples, audio.sample_rate, &state.config.match_hashing())?;
+        let matches = to_song_matches(
+            find_query_matches(state, &fingerprint, filter).await?,
+            &state.config.matching.tiers,
+        );
+
+        fingerprints.push(fingerprint);
+        clip_results.push(ClipMatch {
+            clip_index,
+            matched: !matches.is_empty(),
             matches,
+            truncation,
+        });
+    }
+
+    let mut top_songs = clip_results
+        .iter()
+        .filter_map(|clip| clip.matches.first().map(|m| m.song_id));
+    let same_capture = match top_songs.next() {
+        Some(first) => top_songs.all(|song_id| song_id == first),
+        None => false,
+    };
+
+    let matches = if same_capture {
+        let merged = fingerprint::merge_fingerprints(&fingerprints);
+        to_song_matches(find_query_matches(state, &merged, filter).await?, &state.config.matching.tiers)
+    } else {
+        best_per_song(&clip_results)
+    };
+    record_match(state, &matches, experiment.as_deref()).await;
+
+    Ok(MatchResponse {
+        matched: !matches.is_empty(),
+        tier: matches.first().map(|m| m.tier),
+        matches,
+        combined: Some(same_capture),
+        clips: Some(clip_results),
+        experiment,
+        truncation: None,
+    })
+}
+
+// Matches for a query clip, also trying it pitch-shifted as
+// matching.pitch_shift asks.
+async fn find_query_matches(
+    state: &AppState,
+    fingerprint: &fingerprint::AudioFingerprint,
+    filter: &SongFilter,
+) -> anyhow::Result<Vec<MatchResult>> {
+    let shifts = state.config.matching.pitch_shift.shifts();
+    if shifts.is_empty() {
+        state.db.find_filtered_matches(fingerprint, filter).await
+    } else {
+        state.db.find_pitch_shifted_matches(fingerprint, filter, &shifts).await
+    }
+}
+
+// History feeds reporting only, so a failed write is logged rather than
+// failing the request.
+async fn record_match(state: &AppState, matches: &[SongMatch], experiment: Option<&str>) {
+    let best = matches.first().map(|m| (m.song_id, m.confidence));
+    if let Err(e) = state.db.record_match(best, experiment).await {
+        error!("Failed to record match history: {}", e);
+    }
+}
+
+// Queues an unmatched clip for labeling when capture is on. Clips without
+// hashes (silence) are skipped, as is everything while the queue is full.
+async fn capture_unidentified(
+    state: &AppState,
+    samples: &[f32],
+    sample_rate: u32,
+    fingerprint: &fingerprint::AudioFingerprint,
+) {
+    let config = &state.config.unidentified;
+    if !config.enabled || fingerprint.hashes.is_empty() {
+        return;
+    }
+
+    let result = async {
+        if state.db.count_unidentified().await? >= config.max_entries {
+            return anyhow::Ok(None);
+        }
+        let snippet_len = ((config.snippet_secs.max(0.0) * sample_rate as f64) as usize).min(samples.len());
+        let snippet = audio::encode_wav(&samples[..snippet_len], sample_rate)?;
+        state.db.capture_unidentified(fingerprint, &snippet, &config.dir).await.map(Some)
+    }
+    .await;
+
+    match result {
+        Ok(Some(clip)) => info!("Captured unidentified clip {}", clip.id),
+        Ok(None) => {}
+        Err(e) => error!("Failed to capture unidentified clip: {}", e),
+    }
+}
+
+fn to_song_matches(all_matches: Vec<MatchResult>, tiers: &TierThresholds) -> Vec<SongMatch> {
+    all_matches
+        .into_iter()
+        .map(|m| {
+            let tier = tiers.classify(m.confidence, &m.evidence);
+            debug!(
+                "Match song_id={} confidence={:.3} tier={:?} shared_hashes={} query_hashes={} aligned_hashes={}",
+                m.song_id,
+                m.confidence,
+                tier,
+                m.evidence.shared_hashes,
+                m.evidence.query_hashes,
+                m.evidence.aligned_hashes
+            );
+            SongMatch {
+                song_id: m.song_id,
+                title: m.title,
+                artist: m.artist,
+                confidence: m.confidence,
+                tier,
+                evidence: m.evidence,
+                pitch_shift_percent: m.pitch_shift_percent,
+            }
         })
+        .collect()
+}
+
+fn best_per_song(clips: &[ClipMatch]) -> Vec<SongMatch> {
+    let mut best: Vec<SongMatch> = Vec::new();
+
+    for m in clips.iter().flat_map(|clip| clip.matches.iter()) {
+        match best.iter_mut().find(|b| b.song_id == m.song_id) {
+            Some(existing) if existing.confidence < m.confidence => {
+                existing.confidence = m.confidence;
+                existing.tier = m.tier;
+                existing.evidence = m.evidence;
+                existing.pitch_shift_percent = m.pitch_shift_percent;
+            }
+            Some(_) => {}
+            None => best.push(SongMatch {
+                song_id: m.song_id,
+                title: m.title.clone(),
+                artist: m.artist.clone(),
+                confidence: m.confidence,
+                tier: m.tier,
+                evidence: m.evidence,
+                pitch_shift_percent: m.pitch_shift_percent,
+            }),
+        }
     }
+
+    best.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal));
+    best
 }
 
-async fn process_add_song(
-    db: &Database,
-    audio_data: &[u8],
+async fn process_replace_song(
+    state: &AppState,
+    song_id: i64,
+    audio: &audio::DecodedAudio,
+) -> anyhow::Result<Option<i64>> {
+    let fingerprint = fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &state.config.hashing)?;
+
+    state.db.replace_song_fingerprint(song_id, &fingerprint).await
+}
+
+async fn add_song_samples(
+    state: &AppState,
+    audio: &audio::DecodedAudio,
     title: &str,
     artist: &str,
-) -> anyhow::Result<i64> {
-    let audio_samples = audio::decode_audio(audio_data)?;
-    let fingerprint = fingerprint::generate_fingerprint(&audio_samples)?;
+    metadata: &SongMetadata,
+) -> anyhow::Result<IngestReport> {
+    let fingerprint = fingerprint::generate_fingerprint(&audio.samples, audio.sample_rate, &state.config.hashing)?;
+    let duplicates = to_song_matches(state.db.find_all_matches(&fingerprint).await?, &state.config.matching.tiers);
     
-    let song_id = db.add_song(title, artist, &fingerprint).await?;
-    Ok(song_id)
+    let song_id = state.db.add_song_with_metadata(title, artist, metadata, &fingerprint).await?;
+
+    let mut preprocessing = Vec::new();
+    if let Some(channel) = audio.source.channel {
+        preprocessing.push(format!("took channel {} of {}", channel, audio.source.channels));
+    } else if audio.source.channels > 1 {
+        preprocessing.push(format!("downmixed {} channels to mono", audio.source.channels));
+    }
+    preprocessing.extend(fingerprint::preprocessing(audio.sample_rate, &state.config.hashing));
+
+    Ok(IngestReport {
+        success: true,
+        song_id,
+        format: audio.source.clone(),
+        duration: fingerprint.duration,
+        sample_rate: audio.sample_rate,
+        hash_count: fingerprint.hashes.len(),
+        quality: audio::assess_quality(&audio.samples, audio.sample_rate, fingerprint.hashes.len(), &state.config.silence),
+        duplicates,
+        preprocessing,
+    })
 }
\ No newline at end of file
@@ -0,0 +1,208 @@
+use anyhow::{anyhow, Result};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, Command};
+
+use crate::audio::{DecodedAudio, SourceFormat};
+use crate::config::MediaBridgeConfig;
+use crate::network::{is_listed, is_public, UrlRejected};
+
+const BRIDGE_SAMPLE_RATE: u32 = 44100;
+
+static NEXT_WORK_DIR: AtomicU64 = AtomicU64::new(0);
+
+// Fetches remote media through ffmpeg (optionally fed by yt-dlp for pages such
+// as YouTube) and returns it as mono 44.1 kHz audio, ready for fingerprinting.
+//
+// The child processes run with an empty environment, a throwaway working
+// directory, no stdin, a restricted ffmpeg protocol whitelist, a hard timeout,
+// and a cap on how many bytes of decoded audio we are willing to read back.
+// URLs whose host is on the server's own network are refused before either
+// starts, unless the host is listed in allowed_hosts.
+pub async fn fetch_media(url: &str, config: &MediaBridgeConfig) -> Result<DecodedAudio> {
+    validate_url(url, config)?;
+    validate_host(url, config).await?;
+
+    let work_dir = std::env::temp_dir().join(format!(
+        "music-matcher-bridge-{}-{}",
+        std::process::id(),
+        NEXT_WORK_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::create_dir_all(&work_dir).await?;
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(config.timeout_secs),
+        run_pipeline(url, config, &work_dir),
+    )
+    .await
+    .unwrap_or_else(|_| Err(anyhow!("Media bridge timed out after {}s", config.timeout_secs)));
+
+    let _ = tokio::fs::remove_dir_all(&work_dir).await;
+
+    let pcm = result?;
+    if pcm.is_empty() {
+        return Err(anyhow!("Media bridge produced no audio for {}", url));
+    }
+
+    let samples = pcm
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+        .collect();
+
+    Ok(DecodedAudio {
+        samples,
+        sample_rate: BRIDGE_SAMPLE_RATE,
+        source: SourceFormat {
+            container: "ffmpeg".to_string(),
+            codec: "PCM 16-bit".to_string(),
+            channels: 1,
+            channel: None,
+        },
+    })
+}
+
+fn validate_url(url: &str, config: &MediaBridgeConfig) -> Result<(), UrlRejected> {
+    let scheme = url
+        .split_once("://")
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+        .ok_or_else(|| UrlRejected(format!("Invalid media URL: {}", url)))?;
+
+    if !config.allowed_schemes.iter().any(|s| s.eq_ignore_ascii_case(&scheme)) {
+        return Err(UrlRejected(format!("URL scheme '{}' is not allowed", scheme)));
+    }
+
+    Ok(())
+}
+
+// ffmpeg and yt-dlp resolve the host themselves, so the best we can do is
+// look it up first and refuse if any address is on the server's own network.
+// What they fetch after that (redirects, media URLs yt-dlp finds on a page)
+// is out of our hands; allowed_hosts is the way to pin that down.
+async fn validate_host(url: &str, config: &MediaBridgeConfig) -> Result<(), UrlRejected> {
+    let host = url_host(url).ok_or_else(|| UrlRejected(format!("Invalid media URL: {}", url)))?;
+    if is_listed(&config.allowed_hosts, host) {
+        return Ok(());
+    }
+    if !config.allowed_hosts.is_empty() {
+        return Err(UrlRejected(format!("Host '{}' is not allowed", host)));
+    }
+
+    let name = host.trim_start_matches('[').trim_end_matches(']');
+    let resolved: Vec<_> = tokio::net::lookup_host((name, 0))
+        .await
+        .map_err(|e| UrlRejected(format!("Host '{}' could not be resolved: {}", host, e)))?
+        .collect();
+    if resolved.is_empty() || resolved.iter().any(|addr| !is_public(addr.ip())) {
+        return Err(UrlRejected(format!("Host '{}' does not resolve to a public address", host)));
+    }
+
+    Ok(())
+}
+
+// The host of scheme://[userinfo@]host[:port]/..., brackets and all for IPv6.
+fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host_port)| host_port);
+    let host = match host_port.strip_prefix('[') {
+        Some(bracketed) => &host_port[..bracketed.find(']')? + 2],
+        None => host_port.split(':').next()?,
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+async fn run_pipeline(url: &str, config: &MediaBridgeConfig, work_dir: &std::path::Path) -> Result<Vec<u8>> {
+    let mut ffmpeg = Command::new(&config.ffmpeg_path);
+    sandbox(&mut ffmpeg, work_dir);
+    ffmpeg.args(["-nostdin", "-hide_banner", "-loglevel", "error"]);
+
+    let mut downloader = None;
+    match &config.ytdlp_path {
+        Some(ytdlp_path) => {
+            let mut ytdlp = Command::new(ytdlp_path);
+            sandbox(&mut ytdlp, work_dir);
+            ytdlp
+                .args(["--quiet", "--no-playlist", "--format", "bestaudio/best", "--output", "-"])
+                .arg("--max-filesize")
+                .arg(config.max_output_bytes.to_string())
+                .arg(url)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped());
+
+            let mut child = ytdlp.spawn().map_err(|e| anyhow!("Failed to start yt-dlp: {}", e))?;
+            let stdout: Stdio = child
+                .stdout
+                .take()
+                .ok_or_else(|| anyhow!("yt-dlp stdout unavailable"))?
+                .try_into()?;
+
+            ffmpeg.args(["-i", "pipe:0"]).stdin(stdout);
+            downloader = Some(child);
+        }
+        None => {
+            ffmpeg
+                .args(["-protocol_whitelist", "http,https,tcp,tls,crypto"])
+                .args(["-i", url])
+                .stdin(Stdio::null());
+        }
+    }
+
+    ffmpeg
+        .args(["-vn", "-ac", "1", "-f", "s16le", "-acodec", "pcm_s16le"])
+        .arg("-ar")
+        .arg(BRIDGE_SAMPLE_RATE.to_string())
+        .arg("-t")
+        .arg(config.max_duration_secs.to_string())
+        .arg("pipe:1")
+        .stdout(Stdio::piped());
+
+    let mut child = ffmpeg.spawn().map_err(|e| anyhow!("Failed to start ffmpeg: {}", e))?;
+    let output = read_capped(&mut child, config.max_output_bytes).await;
+
+    let status = child.wait().await?;
+    if let Some(mut downloader) = downloader {
+        let _ = downloader.kill().await;
+    }
+
+    let output = output?;
+    if !status.success() {
+        return Err(anyhow!("ffmpeg exited with {}", status));
+    }
+
+    Ok(output)
+}
+
+fn sandbox(command: &mut Command, work_dir: &std::path::Path) {
+    command
+        .env_clear()
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .current_dir(work_dir)
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+}
+
+async fn read_capped(child: &mut Child, max_bytes: usize) -> Result<Vec<u8>> {
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("ffmpeg stdout unavailable"))?;
+
+    let mut output = Vec::new();
+    let mut chunk = [0u8; 64 * 1024];
+
+    loop {
+        let n = stdout.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        if output.len() + n > max_bytes {
+            let _ = child.kill().await;
+            return Err(anyhow!("Decoded media exceeds {} byte limit", max_bytes));
+        }
+        output.extend_from_slice(&chunk[..n]);
+    }
+
+    Ok(output)
+}
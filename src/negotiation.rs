@@ -0,0 +1,77 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use serde::Serialize;
+use tracing::error;
+
+const MSGPACK_TYPES: [&str; 3] = ["application/msgpack", "application/x-msgpack", "application/vnd.msgpack"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    // JSON unless the client explicitly asks for MessagePack, so browsers and
+    // `Accept: */*` clients see no change.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let wants_msgpack = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|media_type| media_type.split(';').next().unwrap_or("").trim())
+            .any(|media_type| MSGPACK_TYPES.iter().any(|t| media_type.eq_ignore_ascii_case(t)));
+
+        if wants_msgpack {
+            ResponseFormat::MessagePack
+        } else {
+            ResponseFormat::Json
+        }
+    }
+
+    // Distinguishes the representations for validators such as ETags.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "json",
+            ResponseFormat::MessagePack => "msgpack",
+        }
+    }
+}
+
+// A response body serialized in whichever format the request negotiated.
+pub struct Negotiated<T> {
+    pub format: ResponseFormat,
+    pub body: T,
+}
+
+impl<T> Negotiated<T> {
+    pub fn new(headers: &HeaderMap, body: T) -> Self {
+        Negotiated {
+            format: ResponseFormat::from_headers(headers),
+            body,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let mut response = match self.format {
+            ResponseFormat::Json => Json(self.body).into_response(),
+            // Named encoding keeps field names, so the payload has the same
+            // shape as the JSON one.
+            ResponseFormat::MessagePack => match rmp_serde::to_vec_named(&self.body) {
+                Ok(bytes) => ([(header::CONTENT_TYPE, MSGPACK_TYPES[0])], bytes).into_response(),
+                Err(e) => {
+                    error!("MessagePack encoding error: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            },
+        };
+
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
@@ -0,0 +1,46 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// A caller-supplied URL outside the configured allowed_schemes or
+// allowed_hosts, or one whose host is (or resolves only to) a loopback,
+// private or link-local address.
+#[derive(Debug)]
+pub struct UrlRejected(pub String);
+
+impl std::fmt::Display for UrlRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UrlRejected {}
+
+pub fn is_listed(allowed_hosts: &[String], host: &str) -> bool {
+    allowed_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+}
+
+// Anything but loopback, private, link-local, unspecified and similar
+// addresses that only mean something on the server's own network.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let shared = ip.octets()[0] == 100 && ip.octets()[1] & 0xC0 == 64;
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.octets()[0] == 0
+        || shared)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local())
+}
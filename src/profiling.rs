@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+use pprof::protos::Message;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const MAX_SECONDS: u64 = 300;
+const SAMPLE_FREQUENCY: i32 = 99;
+
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileFormat {
+    #[default]
+    Flamegraph,
+    Pprof,
+}
+
+// A second capture while one is running is an error rather than a wait: the
+// sampler is process-wide, and two overlapping profiles would both be skewed.
+#[derive(Debug)]
+pub struct ProfilerBusy;
+
+impl std::fmt::Display for ProfilerBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("A profile is already being captured")
+    }
+}
+
+impl std::error::Error for ProfilerBusy {}
+
+struct BusyGuard;
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        PROFILING.store(false, Ordering::SeqCst);
+    }
+}
+
+// Samples every thread's stack for `seconds` and returns the rendered report
+// with its content type: an SVG flamegraph, or an uncompressed pprof
+// protobuf for `go tool pprof`.
+pub async fn capture(seconds: u64, format: ProfileFormat) -> Result<(&'static str, Vec<u8>)> {
+    if PROFILING.swap(true, Ordering::SeqCst) {
+        return Err(ProfilerBusy.into());
+    }
+    let _busy = BusyGuard;
+
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(SAMPLE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| anyhow!("Failed to start profiler: {}", e))?;
+
+    tokio::time::sleep(Duration::from_secs(seconds.clamp(1, MAX_SECONDS))).await;
+
+    let report = guard
+        .report()
+        .build()
+        .map_err(|e| anyhow!("Failed to build profile report: {}", e))?;
+
+    let mut body = Vec::new();
+    match format {
+        ProfileFormat::Flamegraph => {
+            report
+                .flamegraph(&mut body)
+                .map_err(|e| anyhow!("Failed to render flamegraph: {}", e))?;
+            Ok(("image/svg+xml", body))
+        }
+        ProfileFormat::Pprof => {
+            report
+                .pprof()
+                .map_err(|e| anyhow!("Failed to build pprof profile: {}", e))?
+                .encode(&mut body)
+                .map_err(|e| anyhow!("Failed to encode pprof profile: {}", e))?;
+            Ok(("application/octet-stream", body))
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{redirect, Url};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::audio::LimitExceeded;
+use crate::config::RemoteFetchConfig;
+use crate::network::{is_listed, is_public, UrlRejected};
+
+const MAX_REDIRECTS: usize = 5;
+
+// The remote server couldn't be reached, answered with an error status, or
+// didn't finish sending within remote_fetch.timeout_secs.
+#[derive(Debug)]
+pub struct FetchFailed(pub String);
+
+impl std::fmt::Display for FetchFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FetchFailed {}
+
+// Downloads an audio file for the normal decode path, so the client doesn't
+// have to proxy it. Every redirect hop is checked against the same allow
+// lists as the original URL, every connection goes through PublicResolver,
+// and the body is read no further than max_bytes.
+pub async fn fetch_audio(url: &str, config: &RemoteFetchConfig) -> Result<Vec<u8>> {
+    let url = Url::parse(url).map_err(|e| UrlRejected(format!("Invalid URL {}: {}", url, e)))?;
+    validate_url(&url, config)?;
+
+    let redirect_config = config.clone();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .dns_resolver(Arc::new(PublicResolver {
+            allowed_hosts: config.allowed_hosts.clone(),
+        }))
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                return attempt.error(format!("More than {} redirects", MAX_REDIRECTS));
+            }
+            match validate_url(attempt.url(), &redirect_config) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        }))
+        .build()
+        .map_err(|e| anyhow!("Failed to build HTTP client for remote fetch: {}", e))?;
+
+    let failed = |e: reqwest::Error| {
+        // reqwest's own message leaves out the cause, e.g. which redirect
+        // the policy refused.
+        let message = match std::error::Error::source(&e) {
+            Some(cause) => format!("Failed to fetch {}: {}: {}", url, e, cause),
+            None => format!("Failed to fetch {}: {}", url, e),
+        };
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            if let Some(rejected) = cause.downcast_ref::<UrlRejected>() {
+                return anyhow::Error::new(UrlRejected(format!("Failed to fetch {}: {}", url, rejected)));
+            }
+            source = cause.source();
+        }
+        if e.is_redirect() {
+            anyhow::Error::new(UrlRejected(message))
+        } else {
+            anyhow::Error::new(FetchFailed(message))
+        }
+    };
+
+    let mut response = client.get(url.clone()).send().await.map_err(failed)?;
+    if !response.status().is_success() {
+        return Err(FetchFailed(format!("Failed to fetch {}: server answered {}", url, response.status())).into());
+    }
+
+    let too_large = || LimitExceeded(format!("Remote audio is larger than the {}-byte limit", config.max_bytes));
+    if response.content_length().is_some_and(|length| length > config.max_bytes as u64) {
+        return Err(too_large().into());
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(failed)? {
+        if data.len() + chunk.len() > config.max_bytes {
+            return Err(too_large().into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    if data.is_empty() {
+        return Err(FetchFailed(format!("Failed to fetch {}: the response was empty", url)).into());
+    }
+
+    Ok(data)
+}
+
+fn validate_url(url: &Url, config: &RemoteFetchConfig) -> Result<(), UrlRejected> {
+    if !config.allowed_schemes.iter().any(|s| s.eq_ignore_ascii_case(url.scheme())) {
+        return Err(UrlRejected(format!("URL scheme '{}' is not allowed", url.scheme())));
+    }
+
+    let host = url.host_str().unwrap_or_default();
+    if !config.allowed_hosts.is_empty() && !is_listed(&config.allowed_hosts, host) {
+        return Err(UrlRejected(format!("Host '{}' is not allowed", host)));
+    }
+
+    // Names are checked when PublicResolver looks them up; literal addresses
+    // never reach it.
+    let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+    if let Ok(ip) = literal {
+        if !is_public(ip) && !is_listed(&config.allowed_hosts, host) {
+            return Err(UrlRejected(format!("Address {} is not a public address", ip)));
+        }
+    }
+
+    Ok(())
+}
+
+// Resolves like the system resolver but drops addresses on the server's own
+// network, unless the name is listed in allowed_hosts. Filtering the
+// addresses actually connected to, on the first request and every redirect,
+// leaves no gap for a name that resolves differently the second time.
+struct PublicResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let trusted = is_listed(&self.allowed_hosts, name.as_str());
+        Box::pin(async move {
+            let host = name.as_str();
+            let resolved: Vec<_> = tokio::net::lookup_host((host, 0)).await?.collect();
+            let public: Vec<_> = resolved.into_iter().filter(|addr| trusted || is_public(addr.ip())).collect();
+            if public.is_empty() {
+                let rejected = UrlRejected(format!("Host '{}' does not resolve to a public address", host));
+                return Err(rejected.into());
+            }
+            let addrs: Addrs = Box::new(public.into_iter());
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+use crate::config::{JobConfig, JobKind, SchedulerConfig};
+use crate::database::Database;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub kind: JobKind,
+    pub schedule: String,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<u64>,
+    pub last_ok: Option<bool>,
+    pub last_message: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Scheduler {
+    statuses: Arc<Mutex<HashMap<String, JobStatus>>>,
+}
+
+impl Scheduler {
+    // Parses every job's cron expression up front so a typo fails startup
+    // instead of silently never running.
+    pub fn start(config: &SchedulerConfig, db: Database) -> Result<Self> {
+        let scheduler = Scheduler {
+            statuses: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        if !config.enabled {
+            return Ok(scheduler);
+        }
+
+        for job in &config.jobs {
+            let schedule = Schedule::from_str(&job.cron)
+                .map_err(|e| anyhow!("Invalid cron expression for job {}: {}", job.name, e))?;
+
+            scheduler.statuses.lock().unwrap().insert(
+                job.name.clone(),
+                JobStatus {
+                    name: job.name.clone(),
+                    kind: job.kind,
+                    schedule: job.cron.clone(),
+                    next_run: schedule.upcoming(Utc).next(),
+                    last_run: None,
+                    last_duration_ms: None,
+                    last_ok: None,
+                    last_message: None,
+                },
+            );
+
+            tokio::spawn(run_job_loop(
+                job.clone(),
+                schedule,
+                config.clone(),
+                db.clone(),
+                scheduler.clone(),
+            ));
+        }
+
+        Ok(scheduler)
+    }
+
+    pub fn status(&self) -> Vec<JobStatus> {
+        let mut statuses: Vec<JobStatus> = self.statuses.lock().unwrap().values().cloned().collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    fn record(&self, name: &str, started: DateTime<Utc>, result: &Result<String>, next_run: Option<DateTime<Utc>>) {
+        let mut statuses = self.statuses.lock().unwrap();
+        if let Some(status) = statuses.get_mut(name) {
+            status.last_run = Some(started);
+            status.last_duration_ms = Some((Utc::now() - started).num_milliseconds().max(0) as u64);
+            status.last_ok = Some(result.is_ok());
+            status.last_message = Some(match result {
+                Ok(message) => message.clone(),
+                Err(e) => e.to_string(),
+            });
+            status.next_run = next_run;
+        }
+    }
+}
+
+async fn run_job_loop(job: JobConfig, schedule: Schedule, config: SchedulerConfig, db: Database, scheduler: Scheduler) {
+    while let Some(next) = schedule.upcoming(Utc).next() {
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let started = Utc::now();
+        let result = run_job(job.kind, &config, &db).await;
+
+        match &result {
+            Ok(message) => info!("Scheduled job {} finished: {}", job.name, message),
+            Err(e) => error!("Scheduled job {} failed: {}", job.name, e),
+        }
+
+        scheduler.record(&job.name, started, &result, schedule.upcoming(Utc).next());
+    }
+}
+
+async fn run_job(kind: JobKind, config: &SchedulerConfig, db: &Database) -> Result<String> {
+    match kind {
+        JobKind::Maintenance => {
+            let report = db.run_maintenance().await?;
+            if !report.integrity_ok {
+                return Err(anyhow!("Integrity check failed: {:?}", report.integrity_findings));
+            }
+            Ok(format!("reclaimed {} bytes", report.reclaimed_bytes))
+        }
+        JobKind::Backup => {
+            tokio::fs::create_dir_all(&config.backup_dir).await?;
+            let path = config
+                .backup_dir
+                .join(format!("songs-{}.db", Utc::now().format("%Y%m%dT%H%M%SZ")));
+            db.backup_to(&path).await?;
+            Ok(format!("wrote {}", path.display()))
+        }
+        JobKind::Retention => {
+            let removed = purge_old_backups(&config.backup_dir, config.backup_retention).await?;
+            Ok(format!("removed {} old backups", removed))
+        }
+        JobKind::Dedupe => {
+            let duplicates = db.find_duplicates(config.dedupe_threshold).await?;
+            for (first, second, similarity) in &duplicates {
+                info!("Possible duplicate songs {} and {} (similarity {:.3})", first, second, similarity);
+            }
+            Ok(format!("found {} possible duplicate pairs", duplicates.len()))
+        }
+    }
+}
+
+async fn purge_old_backups(backup_dir: &Path, keep: usize) -> Result<usize> {
+    let mut backups: Vec<PathBuf> = Vec::new();
+
+    let mut entries = match tokio::fs::read_dir(backup_dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("songs-") && name.ends_with(".db"));
+        if is_backup {
+            backups.push(path);
+        }
+    }
+
+    // Backup names embed a sortable timestamp, newest last.
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+
+    for path in &backups[..excess] {
+        tokio::fs::remove_file(path).await?;
+    }
+
+    Ok(excess)
+}
@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::fingerprint::{calculate_similarity, AudioFingerprint};
+
+// The confidence a match has to exceed to be reported at all. Everything
+// above it is at least a weak match.
+pub const MATCH_THRESHOLD: f64 = 0.3;
+
+// Raw counts behind a confidence score, reported so integrators can apply
+// their own acceptance rules and disputed matches can be audited from logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchEvidence {
+    pub shared_hashes: usize,
+    pub query_hashes: usize,
+    pub aligned_hashes: usize,
+}
+
+impl MatchEvidence {
+    // Fraction of the shared hashes that agree on a single time offset.
+    // Hashes shared by chance are scattered in time; a real match lines up.
+    pub fn alignment(&self) -> f64 {
+        if self.shared_hashes == 0 {
+            return 0.0;
+        }
+        self.aligned_hashes as f64 / self.shared_hashes as f64
+    }
+}
+
+// How far downstream automation can trust a match: accept `certain`
+// outright, send `weak` to human review.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchTier {
+    Certain,
+    Probable,
+    Weak,
+}
+
+impl MatchTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchTier::Certain => "certain",
+            MatchTier::Probable => "probable",
+            MatchTier::Weak => "weak",
+        }
+    }
+}
+
+// Cut-offs for MatchTier. Tune them with the per-tier precision that
+// `music-matcher evaluate` reports on a labeled corpus.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TierThresholds {
+    pub certain: f64,
+    pub probable: f64,
+    // Minimum MatchEvidence::alignment for a match to be certain.
+    pub certain_alignment: f64,
+    // Minimum alignment for a match to be probable. Songs that only share
+    // hashes from common chord textures share them at scattered offsets, so
+    // however much of the query they cover they stay weak. Clips matched
+    // through a few percent of tempo drift or pitch shift still line up
+    // about a third of their shared hashes, so this stays below that.
+    pub probable_alignment: f64,
+}
+
+impl Default for TierThresholds {
+    fn default() -> Self {
+        TierThresholds {
+            certain: 0.7,
+            probable: 0.5,
+            certain_alignment: 0.5,
+            probable_alignment: 0.25,
+        }
+    }
+}
+
+impl TierThresholds {
+    // A high score whose shared hashes don't line up in time is only
+    // probable, or weak if they barely line up at all. Fingerprints stored
+    // without offsets report no aligned hashes and can't be checked, so they
+    // are never certain but can still be probable.
+    pub fn classify(&self, confidence: f64, evidence: &MatchEvidence) -> MatchTier {
+        let unchecked = evidence.aligned_hashes == 0;
+        if confidence >= self.certain && evidence.alignment() >= self.certain_alignment {
+            MatchTier::Certain
+        } else if confidence >= self.probable && (unchecked || evidence.alignment() >= self.probable_alignment) {
+            MatchTier::Probable
+        } else {
+            MatchTier::Weak
+        }
+    }
+}
+
+pub fn match_evidence(query: &AudioFingerprint, reference: &AudioFingerprint) -> MatchEvidence {
+    let query_weights = query.weighted_hashes();
+    let reference_weights = reference.weighted_hashes();
+
+    MatchEvidence {
+        shared_hashes: query_weights.keys().filter(|hash| reference_weights.contains_key(hash)).count(),
+        query_hashes: query_weights.len(),
+        aligned_hashes: aligned_hashes(query, reference),
+    }
+}
+
+pub trait SimilarityScorer: Send + Sync {
+    fn score(&self, query: &AudioFingerprint, reference: &AudioFingerprint) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScorerKind {
+    Jaccard,
+    WeightedJaccard,
+    // Default because it is normalized by the query alone: a confidence of
+    // 0.6 means 60% of the query's (weighted) hashes were found in the song,
+    // whether the clip is 5 seconds or 60.
+    #[default]
+    Containment,
+    Overlap,
+    OffsetAligned,
+}
+
+pub fn scorer_for(kind: ScorerKind) -> Box<dyn SimilarityScorer> {
+    match kind {
+        ScorerKind::Jaccard => Box::new(Jaccard),
+        ScorerKind::WeightedJaccard => Box::new(WeightedJaccard),
+        ScorerKind::Containment => Box::new(Containment),
+        ScorerKind::Overlap => Box::new(Overlap),
+        ScorerKind::OffsetAligned => Box::new(OffsetAligned),
+    }
+}
+
+// Weighted |Q ∩ R| / |Q ∪ R| over distinct hashes.
+pub struct Jaccard;
+
+impl SimilarityScorer for Jaccard {
+    fn score(&self, query: &AudioFingerprint, reference: &AudioFingerprint) -> f64 {
+        calculate_similarity(query, reference)
+    }
+}
+
+// Jaccard over hash multisets, so a hash repeated throughout a song counts
+// once per occurrence instead of once overall.
+pub struct WeightedJaccard;
+
+impl SimilarityScorer for WeightedJaccard {
+    fn score(&self, query: &AudioFingerprint, reference: &AudioFingerprint) -> f64 {
+        let query_counts = hash_counts(query);
+        let reference_counts = hash_counts(reference);
+
+        let mut min_sum = 0usize;
+        let mut max_sum = 0usize;
+
+        for (hash, &q) in &query_counts {
+            let r = reference_counts.get(hash).copied().unwrap_or(0);
+            min_sum += q.min(r);
+            max_sum += q.max(r);
+        }
+        for (hash, &r) in &reference_counts {
+            if !query_counts.contains_key(hash) {
+                max_sum += r;
+            }
+        }
+
+        if max_sum == 0 {
+            0.0
+        } else {
+            min_sum as f64 / max_sum as f64
+        }
+    }
+}
+
+// Weighted |Q ∩ R| / |Q|: how much of the query is explained by the
+// reference. A short clip of a long song can still score 1.0.
+pub struct Containment;
+
+impl SimilarityScorer for Containment {
+    fn score(&self, query: &AudioFingerprint, reference: &AudioFingerprint) -> f64 {
+        let query_weights = query.weighted_hashes();
+        let query_total: f64 = query_weights.values().sum();
+
+        if query_total == 0.0 {
+            return 0.0;
+        }
+
+        shared_weight(&query_weights, &reference.weighted_hashes()) / query_total
+    }
+}
+
+// Weighted |Q ∩ R| / min(|Q|, |R|).
+pub struct Overlap;
+
+impl SimilarityScorer for Overlap {
+    fn score(&self, query: &AudioFingerprint, reference: &AudioFingerprint) -> f64 {
+        let query_weights = query.weighted_hashes();
+        let reference_weights = reference.weighted_hashes();
+
+        let smaller = query_weights
+            .values()
+            .sum::<f64>()
+            .min(reference_weights.values().sum::<f64>());
+        if smaller == 0.0 {
+            return 0.0;
+        }
+
+        shared_weight(&query_weights, &reference_weights) / smaller
+    }
+}
+
+// Weight of the shared hashes that agree on a single time offset between
+// query and reference, normalized by total query weight. Fingerprints stored
+// before offsets were recorded score 0.
+pub struct OffsetAligned;
+
+impl SimilarityScorer for OffsetAligned {
+    fn score(&self, query: &AudioFingerprint, reference: &AudioFingerprint) -> f64 {
+        let aligned = aligned_peak(query, reference, |i| query.weight(i));
+        if aligned == 0.0 {
+            return 0.0;
+        }
+
+        let query_total: f64 = (0..query.hashes.len()).map(|i| query.weight(i)).sum();
+        (aligned / query_total).min(1.0)
+    }
+}
+
+// Height of the tallest bin in the histogram of (reference − query) anchor
+// offsets over shared hashes, each query hash contributing `weight(index)`.
+fn aligned_peak(query: &AudioFingerprint, reference: &AudioFingerprint, weight: impl Fn(usize) -> f64) -> f64 {
+    if !query.has_offsets() || !reference.has_offsets() {
+        return 0.0;
+    }
+
+    let reference_offsets = offsets_by_hash(reference);
+    let mut histogram: HashMap<i64, f64> = HashMap::new();
+    for (i, (hash, &query_offset)) in query.hashes.iter().zip(&query.offsets).enumerate() {
+        if let Some(offsets) = reference_offsets.get(hash) {
+            for &reference_offset in offsets {
+                *histogram.entry(reference_offset as i64 - query_offset as i64).or_default() += weight(i);
+            }
+        }
+    }
+
+    histogram.values().copied().fold(0.0, f64::max)
+}
+
+// Distinct query hashes at the most common (reference − query) anchor
+// offset. A hash repeated in either fingerprint still counts once, so this
+// never exceeds MatchEvidence::shared_hashes.
+fn aligned_hashes(query: &AudioFingerprint, reference: &AudioFingerprint) -> usize {
+    if !query.has_offsets() || !reference.has_offsets() {
+        return 0;
+    }
+
+    let reference_offsets = offsets_by_hash(reference);
+    let mut histogram: HashMap<i64, HashSet<u64>> = HashMap::new();
+    for (hash, &query_offset) in query.hashes.iter().zip(&query.offsets) {
+        if let Some(offsets) = reference_offsets.get(hash) {
+            for &reference_offset in offsets {
+                histogram
+                    .entry(reference_offset as i64 - query_offset as i64)
+                    .or_default()
+                    .insert(*hash);
+            }
+        }
+    }
+
+    histogram.values().map(HashSet::len).max().unwrap_or(0)
+}
+
+fn offsets_by_hash(fingerprint: &AudioFingerprint) -> HashMap<u64, Vec<u32>> {
+    let mut offsets: HashMap<u64, Vec<u32>> = HashMap::new();
+    for (&hash, &offset) in fingerprint.hashes.iter().zip(&fingerprint.offsets) {
+        offsets.entry(hash).or_default().push(offset);
+    }
+    offsets
+}
+
+fn shared_weight(query: &HashMap<u64, f64>, reference: &HashMap<u64, f64>) -> f64 {
+    query
+        .iter()
+        .filter_map(|(hash, &q)| reference.get(hash).map(|&r| q.min(r)))
+        .sum()
+}
+
+fn hash_counts(fingerprint: &AudioFingerprint) -> HashMap<u64, usize> {
+    let mut counts = HashMap::new();
+    for &hash in &fingerprint.hashes {
+        *counts.entry(hash).or_default() += 1;
+    }
+    counts
+}
@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::config::SyncConfig;
+use crate::database::{CatalogChanges, Database};
+
+// Routes that change the catalog, as route patterns. A mirror refuses them
+// (a standby until it is promoted), since the next sync could overwrite
+// their changes or, after a full resync, delete the songs they added.
+const CATALOG_WRITES: &[&str] = &[
+    "/add-song",
+    "/add-song/url",
+    "/add-song/fetch",
+    "/songs/:id/audio",
+    "/songs/:id/metadata",
+    "/songs/:id/versions/:version/restore",
+    "/jobs/add-song",
+    "/jobs/imports",
+    "/admin/rollback/:snapshot_id",
+    "/admin/stale-songs/refingerprint",
+    "/admin/unidentified/:id/promote",
+];
+
+// Edge mode: keeps the local catalog a mirror of `upstream` by pulling its
+// change log. Matching never waits on the network, so an edge keeps serving
+// its last synced catalog while the upstream is unreachable. Edges refuse
+// catalog writes. A standby is an edge that can be promoted, after which it
+// stops syncing, accepts writes and serves as the primary.
+#[derive(Clone)]
+pub struct Mirror {
+    inner: Arc<MirrorState>,
+}
+
+struct MirrorState {
+    config: SyncConfig,
+    upstream: String,
+    db: Database,
+    client: reqwest::Client,
+    token: Option<String>,
+    promoted: AtomicBool,
+    // Held for each sync pass, so a promotion waits for the pass in progress.
+    pass: Mutex<()>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Promotion {
+    pub upstream: String,
+    // What the final sync pass before promotion applied. When the upstream
+    // was unreachable the standby is promoted with what it already had.
+    pub updated: usize,
+    pub deleted: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_sync_error: Option<String>,
+}
+
+impl Mirror {
+    pub fn new(config: SyncConfig, upstream: String, db: Database) -> Result<Mirror> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| anyhow!("Failed to build HTTP client for catalog sync: {}", e))?;
+        let token = std::env::var(&config.token_env)
+            .ok()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty());
+
+        Ok(Mirror {
+            inner: Arc::new(MirrorState {
+                config,
+                upstream,
+                db,
+                client,
+                token,
+                promoted: AtomicBool::new(false),
+                pass: Mutex::new(()),
+            }),
+        })
+    }
+
+    pub fn start(&self) {
+        tokio::spawn(run(self.clone()));
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.inner.config.standby
+    }
+
+    pub fn is_promoted(&self) -> bool {
+        self.inner.promoted.load(Ordering::SeqCst)
+    }
+
+    // Pulls whatever the upstream still has, then stops syncing and lifts
+    // the write restriction. Returns None if already promoted.
+    pub async fn promote(&self) -> Option<Promotion> {
+        let _pass = self.inner.pass.lock().await;
+        if self.is_promoted() {
+            return None;
+        }
+
+        let (updated, deleted, final_sync_error) = match sync_once(&self.inner).await {
+            Ok((updated, deleted)) => (updated, deleted, None),
+            Err(e) => {
+                warn!("Final sync from {} before promotion failed: {}", self.inner.upstream, e);
+                (0, 0, Some(e.to_string()))
+            }
+        };
+        self.inner.promoted.store(true, Ordering::SeqCst);
+        info!("Promoted from standby of {}; catalog writes are now accepted", self.inner.upstream);
+
+        Some(Promotion {
+            upstream: self.inner.upstream.clone(),
+            updated,
+            deleted,
+            final_sync_error,
+        })
+    }
+}
+
+async fn run(mirror: Mirror) {
+    let state = &mirror.inner;
+
+    loop {
+        {
+            let _pass = state.pass.lock().await;
+            if mirror.is_promoted() {
+                return;
+            }
+
+            match sync_once(state).await {
+                Ok((0, 0)) => {}
+                Ok((updated, deleted)) => info!(
+                    "Synced catalog from {}: {} songs updated, {} deleted",
+                    state.upstream, updated, deleted
+                ),
+                Err(e) => warn!("Catalog sync from {} failed: {}", state.upstream, e),
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(state.config.interval_secs.max(1))).await;
+    }
+}
+
+// Answers catalog writes to an edge, or to a standby that hasn't been
+// promoted, with 503.
+pub async fn reject_mirror_writes(State(mirror): State<Mirror>, request: Request, next: Next) -> Response {
+    let write = request.method() != Method::GET
+        && request
+            .extensions()
+            .get::<MatchedPath>()
+            .is_some_and(|path| CATALOG_WRITES.contains(&path.as_str()));
+
+    if write && !mirror.is_promoted() {
+        let message = if mirror.is_standby() {
+            "This instance is a standby; catalog writes go to the primary until it is promoted"
+        } else {
+            "This instance mirrors its upstream's catalog; catalog writes go to the upstream"
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, message).into_response();
+    }
+
+    next.run(request).await
+}
+
+// Pulls pages until caught up. Each page is applied atomically together
+// with its cursor, so an interrupted sync resumes where it stopped. A pass
+// from the start of the change log sees every upstream song, so local songs
+// it didn't mention are removed at the end of it.
+async fn sync_once(state: &MirrorState) -> Result<(usize, usize)> {
+    let MirrorState {
+        config,
+        upstream,
+        db,
+        client,
+        token,
+        ..
+    } = state;
+    let url = format!("{}/admin/sync/changes", upstream.trim_end_matches('/'));
+    let mut since = db.sync_cursor(upstream).await?;
+    let (mut updated, mut deleted) = (0, 0);
+    let mut seen: Option<HashSet<i64>> = (since == 0).then(HashSet::new);
+
+    loop {
+        let mut request = client
+            .get(&url)
+            .query(&[("since", since), ("limit", config.page_size as i64)]);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to reach {}: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(anyhow!("{} returned {}", url, response.status()));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| anyhow!("Failed to read response from {}: {}", url, e))?;
+        let changes: CatalogChanges =
+            serde_json::from_slice(&body).map_err(|e| anyhow!("Failed to parse response from {}: {}", url, e))?;
+
+        if changes.latest < since {
+            warn!(
+                "Upstream {} change log is behind our cursor ({} < {}); resyncing from the start",
+                upstream, changes.latest, since
+            );
+            since = 0;
+            seen = Some(HashSet::new());
+            continue;
+        }
+
+        db.apply_catalog_changes(upstream, &changes).await?;
+        updated += changes.songs.len();
+        deleted += changes.deleted.len();
+        since = changes.cursor;
+        if let Some(seen) = seen.as_mut() {
+            seen.extend(changes.songs.iter().map(|song| song.id));
+        }
+
+        if !changes.more {
+            break;
+        }
+    }
+
+    if let Some(seen) = seen {
+        for song_id in db.song_ids().await? {
+            if !seen.contains(&song_id) && db.delete_song(song_id).await? {
+                deleted += 1;
+            }
+        }
+    }
+
+    Ok((updated, deleted))
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use std::f32::consts::PI;
+
+// Deterministic test signals, so tests, benchmarks and evaluation runs don't
+// depend on (copyrighted) audio files. Everything is mono at 44.1 kHz, and
+// the same seed always gives the same samples.
+pub const SAMPLE_RATE: u32 = 44100;
+
+fn sample_count(secs: f32) -> usize {
+    (secs.max(0.0) * SAMPLE_RATE as f32).round() as usize
+}
+
+// xorshift64*: small, fast and stable across platforms and releases, unlike
+// an external RNG whose output may change between versions.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn range(&mut self, low: f32, high: f32) -> f32 {
+        low + (high - low) * self.next_f32()
+    }
+}
+
+// Sum of steady sine partials, given as (frequency Hz, amplitude).
+pub fn sine_mix(partials: &[(f32, f32)], secs: f32) -> Vec<f32> {
+    (0..sample_count(secs))
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            partials
+                .iter()
+                .map(|&(freq, amplitude)| amplitude * (2.0 * PI * freq * t).sin())
+                .sum()
+        })
+        .collect()
+}
+
+// Linear sweep from `start_hz` to `end_hz`.
+pub fn chirp(start_hz: f32, end_hz: f32, secs: f32) -> Vec<f32> {
+    let rate = (end_hz - start_hz) / secs.max(f32::EPSILON);
+    (0..sample_count(secs))
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            (2.0 * PI * (start_hz * t + 0.5 * rate * t * t)).sin()
+        })
+        .collect()
+}
+
+// Unit impulses every `interval_secs`, starting at the first sample.
+pub fn clicks(interval_secs: f32, secs: f32) -> Vec<f32> {
+    let interval = sample_count(interval_secs).max(1);
+    (0..sample_count(secs))
+        .map(|i| if i % interval == 0 { 1.0 } else { 0.0 })
+        .collect()
+}
+
+// Uniform white noise in [-amplitude, amplitude].
+pub fn white_noise(len: usize, amplitude: f32, seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    (0..len).map(|_| rng.range(-amplitude, amplitude)).collect()
+}
+
+// A note of a melody: a fundamental plus one partial at `partial` times its
+// frequency, with a short attack and exponential decay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub freq: f32,
+    pub partial: f32,
+    pub amplitude: f32,
+}
+
+impl Note {
+    pub fn random(rng: &mut Rng) -> Self {
+        Note {
+            freq: rng.range(200.0, 3000.0),
+            partial: rng.range(1.5, 3.0),
+            amplitude: rng.range(0.3, 1.0),
+        }
+    }
+}
+
+pub const NOTE_SECS: f32 = 0.25;
+
+// Music-like reference signal: consecutive notes whose spectral peaks move
+// over time, unlike a steady tone.
+pub fn render_notes(notes: &[Note]) -> Vec<f32> {
+    let note_len = sample_count(NOTE_SECS);
+    let mut samples = Vec::with_capacity(notes.len() * note_len);
+
+    for note in notes {
+        for i in 0..note_len {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = (i as f32 / 400.0).min(1.0) * (-3.0 * t).exp();
+            let phase = 2.0 * PI * note.freq * t;
+            let value = phase.sin() + 0.5 * (phase * note.partial).sin();
+            samples.push(note.amplitude * envelope * value / 1.5);
+        }
+    }
+
+    samples
+}
+
+// `secs` of random melody; distinct seeds give unrelated "songs".
+pub fn melody(seed: u64, secs: f32) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let count = (secs / NOTE_SECS).ceil().max(1.0) as usize;
+    let notes: Vec<Note> = (0..count).map(|_| Note::random(&mut rng)).collect();
+    render_notes(&notes)
+}
+
+pub fn scale(signal: &[f32], gain: f32) -> Vec<f32> {
+    signal.iter().map(|s| s * gain).collect()
+}
+
+// Adds white noise at `snr_db` below the signal's RMS level.
+pub fn add_noise(signal: &[f32], snr_db: f32, seed: u64) -> Vec<f32> {
+    let rms = (signal.iter().map(|s| s * s).sum::<f32>() / signal.len().max(1) as f32).sqrt();
+    // Uniform noise in [-a, a] has an RMS of a / √3.
+    let amplitude = rms * 10f32.powf(-snr_db / 20.0) * 3f32.sqrt();
+    signal
+        .iter()
+        .zip(white_noise(signal.len(), amplitude, seed))
+        .map(|(s, n)| s + n)
+        .collect()
+}
+
+pub fn pad_silence(signal: &[f32], leading_secs: f32) -> Vec<f32> {
+    let mut padded = vec![0.0; sample_count(leading_secs)];
+    padded.extend_from_slice(signal);
+    padded
+}
+
+// The part of `signal` from `start_secs` lasting `secs`, as a recorded
+// clip of a longer song would be.
+pub fn excerpt(signal: &[f32], start_secs: f32, secs: f32) -> Vec<f32> {
+    let start = sample_count(start_secs).min(signal.len());
+    let end = (start + sample_count(secs)).min(signal.len());
+    signal[start..end].to_vec()
+}
+
+// 16-bit mono WAV, the format uploads are usually sent in.
+pub fn to_wav(samples: &[f32]) -> Result<Vec<u8>> {
+    crate::audio::encode_wav(samples, SAMPLE_RATE)
+}
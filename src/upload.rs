@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use axum::body::Bytes;
+use std::io::Read;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::audio::{self, DecodedAudio, PcmFormat, Selection, UnsupportedFormat};
+use crate::config::{AudioLimits, Config};
+
+// Chunks that can queue up between the request body and a decoder that has
+// fallen behind, before the upload is made to wait.
+const CHUNK_BACKLOG: usize = 16;
+
+// The decoders read synchronously, so they get the upload through a blocking
+// reader over its chunks as they arrive. It ends when the sender is dropped.
+// With `copy` set it keeps everything it has read.
+struct ChunkReader {
+    chunks: mpsc::Receiver<Bytes>,
+    current: Bytes,
+    copy: Option<Vec<u8>>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current.split_to(n));
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+// A ChunkReader the decoder can take while the caller keeps hold of it.
+#[cfg(feature = "ffmpeg")]
+struct SharedReader(std::sync::Arc<std::sync::Mutex<ChunkReader>>);
+
+#[cfg(feature = "ffmpeg")]
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.lock().map_err(|_| std::io::Error::other("upload reader lock poisoned"))?.read(buf)
+    }
+}
+
+// Decodes an upload that is already in memory. With the ffmpeg feature, one
+// the native decoders can't read is given to ffmpeg instead.
+pub fn decode_upload(audio_data: &[u8], config: &Config, selection: &Selection) -> Result<DecodedAudio> {
+    let audio = audio::decode_audio_selection(audio_data, &config.limits, selection);
+    #[cfg(feature = "ffmpeg")]
+    let audio = crate::ffmpeg::fallback(audio, audio_data, &config.limits, selection, &config.ffmpeg);
+    audio
+}
+
+// decode_audio_stream with the ffmpeg fallback. Falling back needs the whole
+// upload, so the reader keeps a copy of it: what the native decoder read
+// before giving up, and the rest read after.
+#[cfg(feature = "ffmpeg")]
+fn decode_stream_upload(
+    mut reader: ChunkReader,
+    limits: &AudioLimits,
+    selection: &Selection,
+    config: &crate::config::FfmpegConfig,
+) -> Result<DecodedAudio> {
+    reader.copy = Some(Vec::new());
+    let reader = std::sync::Arc::new(std::sync::Mutex::new(reader));
+    let native = audio::decode_audio_stream(SharedReader(reader.clone()), limits, selection);
+    if !native.as_ref().is_err_and(|e| e.is::<UnsupportedFormat>()) {
+        return native;
+    }
+
+    let mut reader = reader.lock().map_err(|_| anyhow!("Upload reader lock poisoned"))?;
+    std::io::copy(&mut *reader, &mut std::io::sink()).map_err(|e| anyhow!("Failed to read upload: {}", e))?;
+    let audio_data = reader.copy.take().unwrap_or_default();
+    crate::ffmpeg::fallback(native, &audio_data, limits, selection, config)
+}
+
+// An upload decoded on a blocking thread while its chunks are still being
+// received, so a long file is never buffered whole before decoding starts.
+// Raw PCM is the exception: its format may be declared in fields that come
+// after the audio part, so its bytes are collected and decoded at the end.
+// A container upload is decoded with the selection known when its part starts.
+pub enum StreamingDecoder {
+    Container(JoinHandle<Result<DecodedAudio>>, Selection),
+    Pcm(JoinHandle<Vec<u8>>, AudioLimits),
+}
+
+// Whether a part's content type marks it as headerless PCM.
+pub fn is_raw_pcm(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|t| t.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("audio/pcm"))
+}
+
+impl StreamingDecoder {
+    // The upload's chunks go into the returned sender; dropping it marks the
+    // end of the upload. Only `selection` of it is decoded.
+    pub fn start(config: &Config, selection: Selection) -> (mpsc::Sender<Bytes>, StreamingDecoder) {
+        let (sender, chunks) = mpsc::channel(CHUNK_BACKLOG);
+        let reader = ChunkReader { chunks, current: Bytes::new(), copy: None };
+        let limits = config.limits;
+        #[cfg(not(feature = "ffmpeg"))]
+        let task = tokio::task::spawn_blocking(move || audio::decode_audio_stream(reader, &limits, &selection));
+        #[cfg(feature = "ffmpeg")]
+        let task = {
+            let ffmpeg = config.ffmpeg.clone();
+            tokio::task::spawn_blocking(move || decode_stream_upload(reader, &limits, &selection, &ffmpeg))
+        };
+
+        (sender, StreamingDecoder::Container(task, selection))
+    }
+
+    pub fn collect_pcm(limits: AudioLimits) -> (mpsc::Sender<Bytes>, StreamingDecoder) {
+        let (sender, mut chunks) = mpsc::channel::<Bytes>(CHUNK_BACKLOG);
+        let task = tokio::spawn(async move {
+            let mut data = Vec::new();
+            while let Some(chunk) = chunks.recv().await {
+                data.extend_from_slice(&chunk);
+            }
+            data
+        });
+
+        (sender, StreamingDecoder::Pcm(task, limits))
+    }
+
+    pub fn is_pcm(&self) -> bool {
+        matches!(self, StreamingDecoder::Pcm(..))
+    }
+
+    // `pcm` is the declared format for a raw PCM upload; it is ignored for
+    // any other. `selection` is the part of the upload wanted; a decoder that
+    // started before its window was known (the window's fields came after the
+    // audio part) cuts the window from the whole upload now.
+    pub async fn finish(self, pcm: Option<&PcmFormat>, selection: &Selection) -> Result<DecodedAudio> {
+        match self {
+            StreamingDecoder::Container(task, decoded) => {
+                let audio = task.await.map_err(|e| anyhow!("Decoder task failed: {}", e))??;
+                if decoded.window == selection.window {
+                    return Ok(audio);
+                }
+                Ok(DecodedAudio {
+                    samples: audio::select_window(&audio, &selection.window)?.to_vec(),
+                    ..audio
+                })
+            }
+            StreamingDecoder::Pcm(task, limits) => {
+                let data = task.await.map_err(|e| anyhow!("Upload task failed: {}", e))?;
+                let format = *pcm.ok_or_else(|| {
+                    anyhow::Error::from(UnsupportedFormat("Raw PCM upload has no declared format".to_string()))
+                })?;
+                let selection = *selection;
+                tokio::task::spawn_blocking(move || audio::decode_pcm(&data, &format, &limits, &selection))
+                    .await
+                    .map_err(|e| anyhow!("Decoder task failed: {}", e))?
+            }
+        }
+    }
+}
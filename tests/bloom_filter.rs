@@ -0,0 +1,113 @@
+use music_matcher::bloom::{BloomFilter, HashFilter};
+
+const CAPACITY: usize = 1000;
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+#[test]
+fn duplicate_inserts_do_not_saturate() {
+    let mut filter = BloomFilter::with_capacity(CAPACITY, FALSE_POSITIVE_RATE);
+
+    for _ in 0..10 {
+        for hash in 0..CAPACITY as u64 {
+            filter.insert(hash);
+        }
+    }
+
+    assert!(!filter.is_saturated());
+}
+
+#[test]
+fn distinct_inserts_past_capacity_saturate() {
+    let mut filter = BloomFilter::with_capacity(CAPACITY, FALSE_POSITIVE_RATE);
+
+    for hash in 0..CAPACITY as u64 * 2 {
+        filter.insert(hash);
+    }
+
+    assert!(filter.is_saturated());
+    assert!((0..CAPACITY as u64 * 2).all(|hash| filter.may_contain(hash)));
+}
+
+#[test]
+fn empty_hash_filter_rules_nothing_out() {
+    let filter = HashFilter::new(CAPACITY, FALSE_POSITIVE_RATE);
+
+    assert!(filter.may_contain(42));
+    assert!(!filter.is_saturated());
+}
+
+#[test]
+fn rebuild_keeps_writes_still_in_flight() {
+    let mut filter = HashFilter::new(CAPACITY, FALSE_POSITIVE_RATE);
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[1, 2, 3]);
+
+    let write = filter.begin_write(&[100, 101]);
+
+    // The rebuild's query can't see the open transaction's postings.
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[1, 2, 3]);
+
+    assert!(filter.may_contain(100) && filter.may_contain(101));
+    assert!((1..=3).all(|hash| filter.may_contain(hash)));
+    filter.finish_write(write);
+}
+
+#[test]
+fn rebuild_keeps_writes_made_while_it_runs() {
+    let mut filter = HashFilter::new(CAPACITY, FALSE_POSITIVE_RATE);
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[]);
+
+    filter.begin_rebuild();
+    // Registered and committed between the query and the swap.
+    let write = filter.begin_write(&[200, 201]);
+    filter.finish_write(write);
+    filter.finish_rebuild(&[1]);
+
+    assert!(filter.may_contain(200) && filter.may_contain(201));
+}
+
+#[test]
+fn finished_writes_are_left_to_the_committed_postings() {
+    let mut filter = HashFilter::new(CAPACITY, FALSE_POSITIVE_RATE);
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[]);
+
+    let write = filter.begin_write(&[300]);
+    filter.finish_write(write);
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[300]);
+
+    assert!(filter.may_contain(300));
+}
+
+#[test]
+fn aborted_rebuild_keeps_the_old_filter() {
+    let mut filter = HashFilter::new(CAPACITY, FALSE_POSITIVE_RATE);
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[7]);
+
+    filter.begin_rebuild();
+    filter.abort_rebuild();
+
+    assert!(filter.may_contain(7));
+}
+
+#[test]
+fn rebuild_clears_saturation() {
+    let mut filter = HashFilter::new(CAPACITY, FALSE_POSITIVE_RATE);
+    filter.begin_rebuild();
+    filter.finish_rebuild(&[]);
+
+    let hashes: Vec<u64> = (0..CAPACITY as u64 * 2).collect();
+    let write = filter.begin_write(&hashes);
+    filter.finish_write(write);
+    assert!(filter.is_saturated());
+
+    filter.begin_rebuild();
+    filter.finish_rebuild(&hashes);
+
+    assert!(!filter.is_saturated());
+    assert!(hashes.iter().all(|&hash| filter.may_contain(hash)));
+}
@@ -0,0 +1,245 @@
+use music_matcher::audio::{self, BandPass, ResampleQuality};
+use music_matcher::codec;
+use music_matcher::fingerprint::{
+    generate_fingerprint, AudioFingerprint, FingerprintBuilder, FingerprintConfig, HashParams, PeakPicking,
+};
+use music_matcher::scoring::{scorer_for, ScorerKind, MATCH_THRESHOLD};
+use music_matcher::synth::{self, Note};
+use proptest::prelude::*;
+
+// One analysis hop (512 samples at 11025 Hz) in input samples.
+const HOP: usize = 2048;
+
+fn notes() -> impl Strategy<Value = Vec<Note>> {
+    prop::collection::vec(
+        (200.0f32..3000.0, 1.5f32..3.0, 0.3f32..1.0).prop_map(|(freq, partial, amplitude)| Note {
+            freq,
+            partial,
+            amplitude,
+        }),
+        16..24,
+    )
+}
+
+fn fingerprint(samples: &[f32]) -> AudioFingerprint {
+    generate_fingerprint(samples, synth::SAMPLE_RATE, &HashParams::default()).expect("fingerprint")
+}
+
+fn containment(query: &AudioFingerprint, reference: &AudioFingerprint) -> f64 {
+    scorer_for(ScorerKind::Containment).score(query, reference)
+}
+
+// `notes` played at `tempo` times the speed, without changing their pitch.
+fn render_at_tempo(notes: &[Note], tempo: f64) -> Vec<f32> {
+    let note_len = (synth::NOTE_SECS as f64 * synth::SAMPLE_RATE as f64 / tempo).round() as usize;
+    notes
+        .iter()
+        .flat_map(|note| {
+            let mut samples = synth::render_notes(std::slice::from_ref(note));
+            samples.resize(note_len, 0.0);
+            samples
+        })
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    // Samples are peak-normalized before analysis, so gain alone must not
+    // change a single hash.
+    #[test]
+    fn stable_under_volume_scaling(notes in notes(), gain in 0.01f32..4.0) {
+        let original = synth::render_notes(&notes);
+
+        let reference = fingerprint(&original);
+        let query = fingerprint(&synth::scale(&original, gain));
+
+        prop_assert!(!reference.hashes.is_empty());
+        prop_assert!(containment(&query, &reference) >= 0.95);
+    }
+
+    // Roughly a clean line-in capture.
+    #[test]
+    fn stable_under_small_noise(notes in notes(), snr_db in 40.0f32..60.0, seed in any::<u64>()) {
+        let original = synth::render_notes(&notes);
+        let noisy = synth::add_noise(&original, snr_db, seed);
+
+        let score = containment(&fingerprint(&noisy), &fingerprint(&original));
+        prop_assert!(score > MATCH_THRESHOLD, "score {} at {} dB SNR", score, snr_db);
+    }
+
+    // Hashes only encode relative timing, so a clip recorded late still
+    // matches. The silence is a whole number of hops: a sub-hop shift moves
+    // peaks between frames and, since peaks are paired by magnitude rank,
+    // currently changes most hashes.
+    #[test]
+    fn stable_under_leading_silence(notes in notes(), hops in 1usize..40) {
+        let original = synth::render_notes(&notes);
+        let padded = synth::pad_silence(&original, (hops * HOP) as f32 / synth::SAMPLE_RATE as f32);
+
+        let score = containment(&fingerprint(&padded), &fingerprint(&original));
+        prop_assert!(score > MATCH_THRESHOLD, "score {} after {} hops of silence", score, hops);
+    }
+
+    // A denser analysis layout has to keep the same contract. Halving the hop
+    // halves how long a target zone lasts, so the zone doubles to pair peaks
+    // across the same span; with a 20-frame zone and 400 peaks a few percent
+    // of clips scored under the cutoff at 40 dB.
+    #[test]
+    fn stable_under_noise_with_tuned_analysis(notes in notes(), snr_db in 40.0f32..60.0, seed in any::<u64>()) {
+        let params = HashParams {
+            analysis: FingerprintConfig {
+                hop_size: 256,
+                max_peaks: 300,
+                fan_out: 10,
+                target_zone_frames: 40,
+                ..FingerprintConfig::default()
+            },
+            ..HashParams::default()
+        };
+        let original = synth::render_notes(&notes);
+        let noisy = synth::add_noise(&original, snr_db, seed);
+
+        let reference = generate_fingerprint(&original, synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let query = generate_fingerprint(&noisy, synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let score = containment(&query, &reference);
+        prop_assert!(score > MATCH_THRESHOLD, "score {} at {} dB SNR", score, snr_db);
+    }
+
+    // So do peaks picked per octave band.
+    #[test]
+    fn stable_under_noise_with_log_bands(notes in notes(), snr_db in 40.0f32..60.0, seed in any::<u64>()) {
+        let params = HashParams {
+            analysis: FingerprintConfig { peaks: PeakPicking::LogBands, ..FingerprintConfig::default() },
+            ..HashParams::default()
+        };
+        let original = synth::render_notes(&notes);
+        let noisy = synth::add_noise(&original, snr_db, seed);
+
+        let reference = generate_fingerprint(&original, synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let query = generate_fingerprint(&noisy, synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let score = containment(&query, &reference);
+        prop_assert!(score > MATCH_THRESHOLD, "score {} at {} dB SNR", score, snr_db);
+    }
+
+    // A clip played up to 6% fast or slow, which shifts its pitch and timing,
+    // matches once scaled back by the nearest half-percent step and snapped
+    // to the song, as matching.pitch_shift does with log-band peaks; an
+    // unrelated melody still doesn't at any step.
+    #[test]
+    fn matches_when_pitch_shifted(notes in notes(), other in notes(), percent in -6.0f64..6.0) {
+        let params = HashParams {
+            analysis: FingerprintConfig { peaks: PeakPicking::LogBands, ..FingerprintConfig::default() },
+            ..HashParams::default()
+        };
+        let samples = synth::render_notes(&notes);
+        let reference = generate_fingerprint(&samples, synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let unrelated =
+            generate_fingerprint(&synth::render_notes(&other), synth::SAMPLE_RATE, &params).expect("fingerprint");
+        // The same samples played back at a different rate.
+        let played_rate = (synth::SAMPLE_RATE as f64 * (1.0 + percent / 100.0)) as u32;
+        let query = generate_fingerprint(&samples, played_rate, &params).expect("fingerprint");
+
+        let step = (percent * 2.0).round() / 2.0;
+        let score = containment(&query.unshift_pitch(step).snap_to(&reference), &reference);
+        prop_assert!(score > MATCH_THRESHOLD, "score {} at {}% scaled back by {}%", score, percent, step);
+
+        for step in (-12..=12).map(|k| k as f64 / 2.0) {
+            let score = containment(&query.unshift_pitch(step).snap_to(&unrelated), &unrelated);
+            prop_assert!(score < MATCH_THRESHOLD, "unrelated melody scored {} scaled back by {}%", score, step);
+        }
+    }
+
+    // A few percent of tempo drift moves many time differences by a frame;
+    // with one frame of tolerance the drifted recording matches again.
+    #[test]
+    fn matches_with_tempo_drift(notes in notes(), other in notes(), tempo in 0.97f64..1.03) {
+        let params = HashParams {
+            analysis: FingerprintConfig { peaks: PeakPicking::LogBands, ..FingerprintConfig::default() },
+            ..HashParams::default()
+        };
+        let reference =
+            generate_fingerprint(&synth::render_notes(&notes), synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let unrelated =
+            generate_fingerprint(&synth::render_notes(&other), synth::SAMPLE_RATE, &params).expect("fingerprint");
+        let query =
+            generate_fingerprint(&render_at_tempo(&notes, tempo), synth::SAMPLE_RATE, &params).expect("fingerprint");
+
+        let score = containment(&query.snap_time_diffs(&reference, 1), &reference);
+        prop_assert!(score > MATCH_THRESHOLD, "score {} at tempo {}", score, tempo);
+
+        let score = containment(&query.snap_time_diffs(&unrelated, 1), &unrelated);
+        prop_assert!(score < MATCH_THRESHOLD, "unrelated melody scored {}", score);
+    }
+
+    // Fed in chunks of any size, a normalized recording short enough to be a
+    // single block streams to the hashes it gives as a whole, whatever the
+    // resampling and filtering.
+    #[test]
+    fn streaming_matches_whole_recording(
+        notes in notes(),
+        chunk in 1usize..20000,
+        log_bands in any::<bool>(),
+        resample in prop_oneof![
+            Just(ResampleQuality::Fast),
+            Just(ResampleQuality::Linear),
+            Just(ResampleQuality::Balanced),
+            Just(ResampleQuality::High),
+        ],
+        band_pass in any::<bool>(),
+    ) {
+        let params = HashParams {
+            resample,
+            band_pass: band_pass.then_some(BandPass { low_hz: 300, high_hz: 3400 }),
+            analysis: FingerprintConfig {
+                peaks: if log_bands { PeakPicking::LogBands } else { PeakPicking::Strongest },
+                ..FingerprintConfig::default()
+            },
+            ..HashParams::default()
+        };
+        let samples = audio::normalize_audio(&synth::render_notes(&notes));
+        let whole = generate_fingerprint(&samples, synth::SAMPLE_RATE, &params).expect("fingerprint");
+
+        let mut builder = FingerprintBuilder::new(synth::SAMPLE_RATE, &params).expect("builder");
+        let mut streamed = builder.push(&[]);
+        for part in samples.chunks(chunk) {
+            streamed.append(builder.push(part));
+        }
+        streamed.append(builder.finish());
+
+        prop_assert_eq!(&streamed.hashes, &whole.hashes);
+        prop_assert_eq!(&streamed.offsets, &whole.offsets);
+        // Log-band weights are relative to the strongest peak so far.
+        if !log_bands {
+            prop_assert_eq!(&streamed.weights, &whole.weights);
+        }
+        prop_assert!((streamed.duration - whole.duration).abs() < 1e-9);
+    }
+
+    // Stored fingerprints are binary, or JSON from before the binary form;
+    // both have to read back exactly.
+    #[test]
+    fn stored_forms_round_trip(notes in notes()) {
+        let original = fingerprint(&synth::render_notes(&notes));
+        let json = serde_json::to_vec(&original).expect("json");
+
+        for stored in [codec::encode(&original), json] {
+            let decoded = codec::decode(&stored).expect("decode");
+            prop_assert_eq!(&decoded.hashes, &original.hashes);
+            prop_assert_eq!(&decoded.offsets, &original.offsets);
+            prop_assert_eq!(&decoded.weights, &original.weights);
+            prop_assert_eq!(decoded.duration, original.duration);
+            prop_assert_eq!(decoded.params, original.params);
+            prop_assert_eq!(decoded.version, original.version);
+        }
+    }
+
+    #[test]
+    fn unrelated_noise_does_not_match(notes in notes(), seed in any::<u64>()) {
+        let reference = fingerprint(&synth::render_notes(&notes));
+        let query = fingerprint(&synth::white_noise(synth::SAMPLE_RATE as usize * 5, 1.0, seed));
+
+        let score = containment(&query, &reference);
+        prop_assert!(score <= MATCH_THRESHOLD, "noise scored {}", score);
+    }
+}
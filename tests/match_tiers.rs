@@ -0,0 +1,63 @@
+use music_matcher::fingerprint::{AudioFingerprint, HashParams, HASH_FORMAT};
+use music_matcher::scoring::{match_evidence, MatchEvidence, MatchTier, TierThresholds};
+
+fn evidence(shared_hashes: usize, aligned_hashes: usize) -> MatchEvidence {
+    MatchEvidence {
+        shared_hashes,
+        query_hashes: 100,
+        aligned_hashes,
+    }
+}
+
+#[test]
+fn aligned_high_score_is_certain() {
+    let tiers = TierThresholds::default();
+
+    assert_eq!(tiers.classify(0.8, &evidence(80, 60)), MatchTier::Certain);
+}
+
+#[test]
+fn partly_aligned_high_score_is_probable() {
+    let tiers = TierThresholds::default();
+
+    assert_eq!(tiers.classify(0.8, &evidence(80, 30)), MatchTier::Probable);
+}
+
+// Hashes shared through common chord textures land at scattered offsets.
+#[test]
+fn scattered_high_score_is_weak() {
+    let tiers = TierThresholds::default();
+
+    assert_eq!(tiers.classify(0.8, &evidence(80, 8)), MatchTier::Weak);
+    assert_eq!(tiers.classify(0.6, &evidence(60, 6)), MatchTier::Weak);
+}
+
+#[test]
+fn without_offsets_tier_goes_by_confidence() {
+    let tiers = TierThresholds::default();
+
+    assert_eq!(tiers.classify(0.8, &evidence(80, 0)), MatchTier::Probable);
+    assert_eq!(tiers.classify(0.4, &evidence(40, 0)), MatchTier::Weak);
+}
+
+// A hash repeated at a steady interval in both fingerprints (a held chord, a
+// loop) lines up once per pair at the true offset, but is one shared hash.
+#[test]
+fn repeated_hashes_count_once_as_aligned() {
+    let fingerprint = |offsets: Vec<u32>| AudioFingerprint {
+        hashes: vec![7; offsets.len()],
+        weights: vec![1; offsets.len()],
+        offsets,
+        duration: 10.0,
+        params: HashParams::default(),
+        version: HASH_FORMAT,
+    };
+    let query = fingerprint(vec![0, 10, 20, 30]);
+    let reference = fingerprint(vec![100, 110, 120, 130]);
+
+    let evidence = match_evidence(&query, &reference);
+
+    assert_eq!(evidence.shared_hashes, 1);
+    assert_eq!(evidence.aligned_hashes, 1);
+    assert_eq!(evidence.alignment(), 1.0);
+}
@@ -0,0 +1,405 @@
+use music_matcher::audio::{
+    decode_audio, decode_audio_selection, resample, ChannelOutOfRange, DecodedAudio, LimitExceeded, ResampleQuality,
+    Selection, TimeWindow, UnsupportedFormat,
+};
+use music_matcher::config::AudioLimits;
+use music_matcher::fingerprint::{generate_fingerprint, HashParams};
+use music_matcher::scoring::{scorer_for, ScorerKind, MATCH_THRESHOLD};
+use music_matcher::synth;
+
+const RATE: u32 = 11025;
+const FREQ: f64 = 441.0;
+const AMPLITUDE: f64 = 0.5;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_ALAW: u16 = 0x0006;
+const WAVE_FORMAT_MULAW: u16 = 0x0007;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// How the test signal is written: `bits` used of each `container_bytes`,
+// left-justified as the WAV format requires.
+struct Layout {
+    tag: u16,
+    bits: u16,
+    container_bytes: u16,
+    channels: u16,
+    extensible: bool,
+    sample_rate: u32,
+}
+
+impl Layout {
+    fn pcm(bits: u16) -> Layout {
+        Layout {
+            tag: WAVE_FORMAT_PCM,
+            bits,
+            container_bytes: bits / 8,
+            channels: 1,
+            extensible: false,
+            sample_rate: RATE,
+        }
+    }
+
+    // G.711 codes, one byte per sample.
+    fn companded(tag: u16) -> Layout {
+        Layout { tag, ..Layout::pcm(8) }
+    }
+}
+
+fn sine(i: usize, sample_rate: u32) -> f64 {
+    AMPLITUDE * (2.0 * std::f64::consts::PI * FREQ * i as f64 / sample_rate as f64).sin()
+}
+
+// The reference G.711 encoders, from 16-bit linear PCM.
+fn mu_law(sample: i16) -> u8 {
+    let sign = if sample < 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(32635) + 0x84;
+    let segment = 7 - ((magnitude >> 7) as u8).leading_zeros() as i32;
+    let step = (magnitude >> (segment + 3)) & 0x0F;
+    !(sign | (segment << 4) as u8 | step as u8)
+}
+
+fn a_law(sample: i16) -> u8 {
+    let sign = if sample >= 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(32767) >> 3;
+    let (segment, step) = match magnitude {
+        0..=31 => (0, magnitude >> 1),
+        _ => {
+            let segment = 27 - (magnitude as u32).leading_zeros() as i32;
+            (segment, (magnitude >> segment) & 0x0F)
+        }
+    };
+    (sign | (segment << 4) as u8 | step as u8) ^ 0x55
+}
+
+fn encode_sample(layout: &Layout, value: f64) -> Vec<u8> {
+    let linear = (value * 32767.0).round() as i16;
+    match layout.tag {
+        WAVE_FORMAT_MULAW => return vec![mu_law(linear)],
+        WAVE_FORMAT_ALAW => return vec![a_law(linear)],
+        _ => {}
+    }
+    if layout.tag == WAVE_FORMAT_IEEE_FLOAT {
+        return match layout.container_bytes {
+            8 => value.to_le_bytes().to_vec(),
+            _ => (value as f32).to_le_bytes().to_vec(),
+        };
+    }
+    if layout.bits == 8 {
+        return vec![(value * 127.0).round() as i16 as u8 ^ 0x80];
+    }
+
+    let quantized = (value * ((1i64 << (layout.bits - 1)) - 1) as f64).round() as i64;
+    let justified = quantized << (layout.container_bytes * 8 - layout.bits);
+    justified.to_le_bytes()[..layout.container_bytes as usize].to_vec()
+}
+
+// One second of the test sine, the same on every channel.
+fn wav(layout: &Layout) -> Vec<u8> {
+    let signal: Vec<f64> = (0..layout.sample_rate as usize).map(|i| sine(i, layout.sample_rate)).collect();
+    encode_wav(layout, &signal)
+}
+
+// `signal` in every channel.
+fn encode_wav(layout: &Layout, signal: &[f64]) -> Vec<u8> {
+    let interleaved: Vec<f64> = signal
+        .iter()
+        .flat_map(|&value| std::iter::repeat_n(value, layout.channels as usize))
+        .collect();
+    encode_interleaved(layout, &interleaved)
+}
+
+fn encode_interleaved(layout: &Layout, interleaved: &[f64]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for &value in interleaved {
+        data.extend(encode_sample(layout, value));
+    }
+
+    let block_align = layout.container_bytes * layout.channels;
+    let tag = if layout.extensible { WAVE_FORMAT_EXTENSIBLE } else { layout.tag };
+    let mut fmt = Vec::new();
+    fmt.extend(tag.to_le_bytes());
+    fmt.extend(layout.channels.to_le_bytes());
+    fmt.extend(layout.sample_rate.to_le_bytes());
+    fmt.extend((layout.sample_rate * block_align as u32).to_le_bytes());
+    fmt.extend(block_align.to_le_bytes());
+    fmt.extend((layout.container_bytes * 8).to_le_bytes());
+    if layout.extensible {
+        fmt.extend(22u16.to_le_bytes());
+        fmt.extend(layout.bits.to_le_bytes());
+        fmt.extend(0u32.to_le_bytes());
+        fmt.extend((layout.tag as u32).to_le_bytes());
+        fmt.extend([0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+    }
+
+    let mut body = b"WAVE".to_vec();
+    body.extend(b"fmt ");
+    body.extend((fmt.len() as u32).to_le_bytes());
+    body.extend(fmt);
+    body.extend(b"data");
+    body.extend((data.len() as u32).to_le_bytes());
+    body.extend(data);
+
+    let mut file = b"RIFF".to_vec();
+    file.extend((body.len() as u32).to_le_bytes());
+    file.extend(body);
+    file
+}
+
+fn decode(layout: &Layout) -> DecodedAudio {
+    decode_audio(&wav(layout), &AudioLimits::default()).expect("decode")
+}
+
+// Every sample within `tolerance` of the sine that was written.
+fn assert_sine(audio: &DecodedAudio, tolerance: f32) {
+    assert_eq!(audio.samples.len(), RATE as usize);
+    for (i, &sample) in audio.samples.iter().enumerate() {
+        let expected = sine(i, RATE) as f32;
+        assert!(
+            (sample - expected).abs() <= tolerance,
+            "sample {} is {}, expected {}",
+            i,
+            sample,
+            expected
+        );
+    }
+}
+
+#[test]
+fn unsigned_8_bit_is_centered() {
+    let audio = decode(&Layout::pcm(8));
+
+    let mean = audio.samples.iter().sum::<f32>() / audio.samples.len() as f32;
+    assert!(mean.abs() < 1e-3, "DC offset {}", mean);
+    assert_sine(&audio, 1.0 / 64.0);
+    assert_eq!(audio.source.codec, "PCM 8-bit");
+}
+
+#[test]
+fn signed_16_bit() {
+    assert_sine(&decode(&Layout::pcm(16)), 1e-4);
+}
+
+#[test]
+fn packed_24_bit() {
+    let audio = decode(&Layout::pcm(24));
+
+    assert_sine(&audio, 1e-6);
+    assert_eq!(audio.source.codec, "PCM 24-bit");
+}
+
+#[test]
+fn signed_32_bit() {
+    assert_sine(&decode(&Layout::pcm(32)), 1e-6);
+}
+
+#[test]
+fn float_32_and_64_bit() {
+    for container_bytes in [4, 8] {
+        let layout = Layout {
+            tag: WAVE_FORMAT_IEEE_FLOAT,
+            bits: container_bytes * 8,
+            container_bytes,
+            channels: 1,
+            extensible: false,
+            sample_rate: RATE,
+        };
+        assert_sine(&decode(&layout), 1e-6);
+    }
+}
+
+#[test]
+fn extensible_24_bit_in_32_bit_container() {
+    let audio = decode(&Layout {
+        tag: WAVE_FORMAT_PCM,
+        bits: 24,
+        container_bytes: 4,
+        channels: 1,
+        extensible: true,
+        sample_rate: RATE,
+    });
+
+    assert_sine(&audio, 1e-6);
+    assert_eq!(audio.source.codec, "PCM 24-bit");
+}
+
+#[test]
+fn extensible_8_bit_is_unsigned() {
+    let audio = decode(&Layout {
+        extensible: true,
+        ..Layout::pcm(8)
+    });
+
+    assert_sine(&audio, 1.0 / 64.0);
+}
+
+#[test]
+fn stereo_24_bit_downmixes() {
+    let audio = decode(&Layout {
+        channels: 2,
+        ..Layout::pcm(24)
+    });
+
+    assert_sine(&audio, 1e-6);
+    assert_eq!(audio.source.channels, 2);
+}
+
+// 5.1 in WAVE_FORMAT_EXTENSIBLE order, with the sine only in the centre
+// channel (3) and a different tone in the others.
+#[test]
+fn surround_channel_is_selected() {
+    let layout = Layout {
+        channels: 6,
+        extensible: true,
+        ..Layout::pcm(16)
+    };
+    let interleaved: Vec<f64> = (0..RATE as usize)
+        .flat_map(|i| (1..=6).map(move |channel| if channel == 3 { sine(i, RATE) } else { sine(i * 3, RATE) }))
+        .collect();
+    let file = encode_interleaved(&layout, &interleaved);
+    let centre = Selection {
+        channel: Some(3),
+        ..Selection::default()
+    };
+
+    let audio = decode_audio_selection(&file, &AudioLimits::default(), &centre).expect("decode");
+    assert_sine(&audio, 1e-4);
+    assert_eq!((audio.source.channels, audio.source.channel), (6, Some(3)));
+
+    let missing = Selection {
+        channel: Some(7),
+        ..Selection::default()
+    };
+    let error = decode_audio_selection(&file, &AudioLimits::default(), &missing).expect_err("channel 7 decoded");
+    assert!(error.downcast_ref::<ChannelOutOfRange>().is_some());
+}
+
+// G.711 quantizes 0.5 in steps of about 1/32 of full scale.
+#[test]
+fn mu_law_expands_to_linear() {
+    let audio = decode(&Layout::companded(WAVE_FORMAT_MULAW));
+
+    assert_sine(&audio, 1.0 / 64.0);
+    assert_eq!(audio.source.codec, "G.711 µ-law");
+}
+
+#[test]
+fn a_law_expands_to_linear() {
+    let audio = decode(&Layout::companded(WAVE_FORMAT_ALAW));
+
+    assert_sine(&audio, 1.0 / 64.0);
+    assert_eq!(audio.source.codec, "G.711 A-law");
+}
+
+// Telephony audio is below the analysis rate and has to be upsampled. It
+// loses everything above 4 kHz, so it still has to match the full-rate
+// recording on what is left.
+#[test]
+fn telephony_rate_matches_full_rate() {
+    let params = HashParams::default();
+    let melody = synth::melody(2, 10.0);
+    let narrowband: Vec<f64> = resample(&melody, synth::SAMPLE_RATE, 8000, ResampleQuality::High)
+        .into_iter()
+        .map(f64::from)
+        .collect();
+    let layout = Layout {
+        sample_rate: 8000,
+        ..Layout::companded(WAVE_FORMAT_MULAW)
+    };
+    let telephony = decode_audio(&encode_wav(&layout, &narrowband), &AudioLimits::default()).expect("decode");
+
+    assert_eq!(telephony.sample_rate, 8000);
+    let query = generate_fingerprint(&telephony.samples, telephony.sample_rate, &params).expect("fingerprint");
+    let reference = generate_fingerprint(&melody, synth::SAMPLE_RATE, &params).expect("fingerprint");
+    let score = scorer_for(ScorerKind::Containment).score(&query, &reference);
+    assert!(score > MATCH_THRESHOLD, "score {}", score);
+}
+
+// A track cut from a long rip: the duration limit applies to the window,
+// not to the whole file.
+#[test]
+fn window_decodes_only_its_samples() {
+    let layout = Layout::pcm(16);
+    let signal: Vec<f64> = (0..RATE as usize * 20).map(|i| sine(i, RATE)).collect();
+    let file = encode_wav(&layout, &signal);
+    let limits = AudioLimits {
+        max_duration_secs: 5,
+        ..AudioLimits::default()
+    };
+    let selection = Selection {
+        window: TimeWindow {
+            offset_secs: 12.0,
+            duration_secs: Some(3.0),
+        },
+        channel: None,
+    };
+
+    let error = decode_audio(&file, &limits).expect_err("20 seconds decoded under a 5-second limit");
+    assert!(error.downcast_ref::<LimitExceeded>().is_some());
+
+    let audio = decode_audio_selection(&file, &limits, &selection).expect("decode");
+    assert_eq!(audio.samples.len(), RATE as usize * 3);
+    let start = RATE as usize * 12;
+    for (i, &sample) in audio.samples.iter().enumerate() {
+        assert!((sample as f64 - sine(start + i, RATE)).abs() < 1e-4, "sample {}", i);
+    }
+}
+
+#[test]
+fn compressed_encoding_is_unsupported() {
+    let file = wav(&Layout {
+        tag: 0x0002,
+        ..Layout::pcm(16)
+    });
+
+    let error = decode_audio(&file, &AudioLimits::default()).expect_err("MS ADPCM decoded");
+    let unsupported = error.downcast_ref::<UnsupportedFormat>().expect("UnsupportedFormat");
+    assert_eq!(unsupported.to_string(), "Unsupported WAV encoding: MS ADPCM");
+}
+
+
+#[test]
+fn unrecognized_header_is_unsupported() {
+    let amr = b"#!AMR\n\x3c\x48\xf5\x1f\x96\x66\x79\xe1\xe0\x01\xe7\xcf\xf0\x00\x00\x00".to_vec();
+
+    let error = decode_audio(&amr, &AudioLimits::default()).expect_err("AMR decoded");
+    assert!(error.downcast_ref::<UnsupportedFormat>().is_some(), "{}", error);
+}
+
+// ADTS header for one mono 44.1 kHz AAC-LC frame of `payload` bytes.
+fn adts_header(payload: usize) -> [u8; 7] {
+    let length = payload + 7;
+    [
+        0xFF,
+        0xF1,
+        0x50,
+        0x40 | (length >> 11) as u8,
+        (length >> 3) as u8,
+        ((length & 0x07) << 5) as u8 | 0x1F,
+        0xFC,
+    ]
+}
+
+#[test]
+fn adts_aac_is_not_taken_for_mp3() {
+    // One silent single-channel element (global gain 100, no scale factor
+    // bands) followed by the end element.
+    let silence = [0x00, 0xC8, 0x00, 0x07];
+    let frames = 50;
+    let mut stream = Vec::new();
+    for _ in 0..frames {
+        stream.extend_from_slice(&adts_header(silence.len()));
+        stream.extend_from_slice(&silence);
+    }
+
+    // Silence packs 1024 samples into 11 bytes, far denser than real AAC.
+    let limits = AudioLimits {
+        max_samples_per_byte: 128,
+        ..AudioLimits::default()
+    };
+    let audio = decode_audio(&stream, &limits).expect("decode");
+    assert_eq!(audio.source.container, "aac");
+    assert_eq!(audio.sample_rate, 44100);
+    assert!(audio.samples.len() >= (frames - 2) * 1024, "{} samples", audio.samples.len());
+    assert!(audio.samples.iter().all(|sample| sample.abs() < 1e-6));
+}